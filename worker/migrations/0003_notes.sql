@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS notes (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    raw_text TEXT NOT NULL,
+    polished_text TEXT NOT NULL,
+    tone TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    deleted INTEGER NOT NULL DEFAULT 0,
+    synced_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_notes_user_synced ON notes(user_id, synced_at);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS glossary_terms (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    term TEXT NOT NULL,
+    variants TEXT NOT NULL DEFAULT '[]',
+    replacement TEXT,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_glossary_terms_user_id ON glossary_terms(user_id);
@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN redact_pii INTEGER NOT NULL DEFAULT 0;
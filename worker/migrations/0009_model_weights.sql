@@ -0,0 +1,3 @@
+ALTER TABLE usage_events ADD COLUMN billed_tokens INTEGER NOT NULL DEFAULT 0;
+
+UPDATE usage_events SET billed_tokens = total_tokens;
@@ -0,0 +1 @@
+ALTER TABLE usage_events ADD COLUMN audio_seconds INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS sessions (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    device_name TEXT,
+    ip_country TEXT,
+    created_at INTEGER NOT NULL,
+    last_seen_at INTEGER NOT NULL,
+    revoked_at INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS idx_sessions_user ON sessions(user_id, last_seen_at);
+
+-- Refresh token families issued before sessions existed become sessions
+INSERT OR IGNORE INTO sessions (id, user_id, created_at, last_seen_at, revoked_at)
+SELECT family_id, user_id, MIN(created_at), MAX(created_at), MAX(revoked_at)
+FROM refresh_tokens
+GROUP BY family_id, user_id;
+
+-- Tokens without a session id stay valid until expiry unless the user signs
+-- out everywhere, which sets this; NULL means never signed out
+ALTER TABLE users ADD COLUMN signed_out_at INTEGER;
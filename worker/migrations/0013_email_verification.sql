@@ -0,0 +1,5 @@
+ALTER TABLE users ADD COLUMN email_verified_at INTEGER;
+ALTER TABLE users ADD COLUMN verification_sent_at INTEGER;
+
+-- Accounts created before verification existed keep what they had
+UPDATE users SET email_verified_at = created_at;
@@ -0,0 +1,269 @@
+use crate::auth::{extract_and_verify_token, user_settings};
+use crate::catalog::ModelCatalog;
+use crate::error::ApiError;
+use crate::models::Quota;
+use crate::provider::{Provider, Spend};
+use crate::redact::RedactionPolicy;
+use crate::usage::monthly_quota;
+use crate::verification::may_use_hosted;
+use worker::*;
+
+/// Window of the `RATE_LIMIT` binding, reported to clients as `retry_after`
+pub const RATE_LIMIT_PERIOD_SECS: u32 = 10;
+
+/// Window of the `TEXT_RATE_LIMIT` binding, which meters hosted polish input
+/// in units of one chunk's worth of characters
+pub const TEXT_RATE_LIMIT_PERIOD_SECS: u32 = 60;
+
+/// Rough characters per token, erring towards more tokens for non-English text
+const CHARS_PER_TOKEN: usize = 3;
+/// System prompt and delimiters sent with every provider call
+const PROMPT_OVERHEAD_TOKENS: u64 = 400;
+
+/// Who is calling an AI endpoint, and whose key pays for it
+pub struct AiAccess {
+    pub provider: Provider,
+    /// Always set for hosted calls; BYOK callers may sign in for account features
+    pub user_id: Option<String>,
+    pub is_byok: bool,
+    /// The hosted quota as read before the call, absent for BYOK
+    pub quota: Option<Quota>,
+    /// The account or the operator requires personal data to be redacted from every call
+    pub redact_pii: bool,
+    /// Quota tokens charged per token of the selected model (1 for BYOK)
+    pub cost_weight: f64,
+}
+
+impl AiAccess {
+    /// Whether to redact this call: the account setting wins over the request
+    pub fn redacts(&self, requested: bool) -> bool {
+        requested || self.redact_pii
+    }
+
+    /// Switch to a model the caller asked for, if this mode's allowlist has it
+    pub fn select_model(&mut self, env: &Env, model: &str) -> std::result::Result<(), ApiError> {
+        let selected = if self.is_byok {
+            ModelCatalog::byok(env).resolve(Some(model))?.clone()
+        } else {
+            ModelCatalog::hosted(env)?.resolve(Some(model))?.clone()
+        };
+        if !self.is_byok {
+            self.cost_weight = selected.cost_weight;
+        }
+        self.provider.set_model(selected.id, self.cost_weight);
+        Ok(())
+    }
+
+    /// Quota tokens a call with this usage costs
+    pub fn billed(&self, spend: &Spend) -> u64 {
+        spend.billed(self.cost_weight)
+    }
+
+    /// Refuse a hosted request whose estimated cost exceeds the remaining quota,
+    /// before any tokens are spent. Metering afterwards only catches up.
+    pub fn check_estimate(&self, estimate: Estimate) -> std::result::Result<(), ApiError> {
+        let Some(quota) = &self.quota else {
+            return Ok(());
+        };
+        let cost = estimate.cost(self.cost_weight);
+        if cost <= quota.remaining {
+            return Ok(());
+        }
+        Err(ApiError::QuotaExceeded {
+            message: format!(
+                "This request needs about {} quota tokens, but only {} of the monthly {} remain. Shorten the text, use your own API key, or upgrade.",
+                cost, quota.remaining, quota.limit
+            ),
+            retry_after: None,
+        })
+    }
+}
+
+/// The provider calls a hosted request will make, for an up-front cost estimate
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Estimate {
+    /// Characters sent as user text, summed over calls
+    pub input_chars: usize,
+    /// Characters expected back, summed over calls
+    pub output_chars: usize,
+    pub calls: usize,
+}
+
+impl Estimate {
+    /// A call (or chunked calls) that rewrites `chars` characters of text
+    pub fn rewrite(chars: usize, calls: usize) -> Self {
+        Self {
+            input_chars: chars,
+            output_chars: chars,
+            calls,
+        }
+    }
+
+    /// One call that reads `chars` characters and answers briefly
+    pub fn read(chars: usize) -> Self {
+        Self {
+            input_chars: chars,
+            output_chars: 0,
+            calls: 1,
+        }
+    }
+
+    pub fn and(self, other: Self) -> Self {
+        Self {
+            input_chars: self.input_chars + other.input_chars,
+            output_chars: self.output_chars + other.output_chars,
+            calls: self.calls + other.calls,
+        }
+    }
+
+    /// Estimated quota tokens at `cost_weight`
+    pub fn cost(&self, cost_weight: f64) -> u64 {
+        let tokens = (self.input_chars + self.output_chars).div_ceil(CHARS_PER_TOKEN) as u64
+            + self.calls as u64 * PROMPT_OVERHEAD_TOKENS;
+        (tokens as f64 * cost_weight).ceil() as u64
+    }
+}
+
+/// The shared gate for AI endpoints: a BYOK key from `X-OpenAI-Key`, or a
+/// signed-in user within both the burst rate limit and their monthly quota.
+pub async fn authorize(
+    req: &Request,
+    ctx: &RouteContext<()>,
+) -> std::result::Result<AiAccess, ApiError> {
+    authorize_with(req, ctx, true).await
+}
+
+/// The gate for transcription, which is metered in audio seconds: the token
+/// quota only matters if the transcript is polished, and polishing checks its
+/// own estimate.
+pub async fn authorize_transcription(
+    req: &Request,
+    ctx: &RouteContext<()>,
+) -> std::result::Result<AiAccess, ApiError> {
+    authorize_with(req, ctx, false).await
+}
+
+async fn authorize_with(
+    req: &Request,
+    ctx: &RouteContext<()>,
+    check_tokens: bool,
+) -> std::result::Result<AiAccess, ApiError> {
+    if let Some(key) = req.headers().get("X-OpenAI-Key")? {
+        let user_id = extract_and_verify_token(req, ctx).await.ok();
+        let redact_pii = match &user_id {
+            Some(user_id) => {
+                user_settings(&ctx.env.d1("DB")?, &ctx.env, user_id)
+                    .await?
+                    .redact_pii
+            }
+            None => RedactionPolicy::from_env(&ctx.env) == RedactionPolicy::Required,
+        };
+        return Ok(AiAccess {
+            provider: Provider::byok(key),
+            user_id,
+            is_byok: true,
+            quota: None,
+            redact_pii,
+            cost_weight: 1.0,
+        });
+    }
+
+    let user_id = extract_and_verify_token(req, ctx).await.map_err(|e| {
+        ApiError::Unauthorized(format!(
+            "Authentication required: {}. Use X-OpenAI-Key header for BYOK mode.",
+            e
+        ))
+    })?;
+
+    let rate_limiter = ctx.rate_limiter("RATE_LIMIT")?;
+    if !rate_limiter.limit(user_id.clone()).await?.success {
+        return Err(ApiError::RateLimited {
+            retry_after: RATE_LIMIT_PERIOD_SECS,
+        });
+    }
+
+    let db = ctx.env.d1("DB")?;
+    if !may_use_hosted(&db, &ctx.env, &user_id).await? {
+        return Err(ApiError::Forbidden(
+            "Verify your email address to use hosted AI. Use X-OpenAI-Key header for BYOK mode."
+                .to_string(),
+        ));
+    }
+
+    let quota = monthly_quota(&db, &user_id).await?;
+    if check_tokens && quota.is_exhausted() {
+        let retry_after = (quota.resets_at - chrono::Utc::now().timestamp()).max(0) as u32;
+        return Err(ApiError::QuotaExceeded {
+            message: format!(
+                "Monthly quota of {} tokens on the {} plan is used up. Use your own API key or upgrade.",
+                quota.limit,
+                quota.plan.id()
+            ),
+            retry_after: Some(retry_after),
+        });
+    }
+    // Each endpoint also checks its request's estimated cost once the body is read
+
+    let default_model = ModelCatalog::hosted(&ctx.env)?.resolve(None)?.clone();
+
+    Ok(AiAccess {
+        provider: Provider::from_env(&ctx.env)?,
+        cost_weight: default_model.cost_weight,
+        redact_pii: user_settings(&db, &ctx.env, &user_id).await?.redact_pii,
+        user_id: Some(user_id),
+        is_byok: false,
+        quota: Some(quota),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Plan;
+
+    fn access(remaining: u64, cost_weight: f64) -> AiAccess {
+        let limit = Plan::Free.monthly_token_allowance();
+        AiAccess {
+            provider: Provider::byok("sk-test".to_string()),
+            user_id: Some("u1".to_string()),
+            is_byok: false,
+            quota: Some(Quota::new(Plan::Free, limit - remaining, 0)),
+            redact_pii: false,
+            cost_weight,
+        }
+    }
+
+    #[test]
+    fn test_estimate_cost() {
+        assert_eq!(Estimate::rewrite(3_000, 1).cost(1.0), 2_400);
+        assert_eq!(Estimate::rewrite(3_000, 1).cost(2.5), 6_000);
+        assert_eq!(Estimate::read(3_000).cost(1.0), 1_400);
+        assert_eq!(
+            Estimate::rewrite(64_000, 8)
+                .and(Estimate::read(64_000))
+                .calls,
+            9
+        );
+    }
+
+    #[test]
+    fn test_check_estimate() {
+        // One token left can't pay for a long chunked request
+        let err = access(1, 1.0)
+            .check_estimate(Estimate::rewrite(64_000, 8))
+            .unwrap_err();
+        assert_eq!(err.code(), "quota_exceeded");
+
+        let estimate = Estimate::rewrite(3_000, 1);
+        assert!(access(2_400, 1.0).check_estimate(estimate).is_ok());
+        // A heavier model costs more of the same quota
+        assert!(access(2_400, 8.0).check_estimate(estimate).is_err());
+
+        let byok = AiAccess {
+            quota: None,
+            is_byok: true,
+            ..access(0, 1.0)
+        };
+        assert!(byok.check_estimate(estimate).is_ok());
+    }
+}
@@ -0,0 +1,400 @@
+use crate::access::{self, Estimate};
+use crate::error::ApiError;
+use crate::guard;
+use crate::models::{ActionItem, ActionItemsRequest, ActionItemsResponse, ApiResponse, Priority};
+use crate::provider::{
+    CompletionOptions, LlmProvider, Provider, ProviderError, ResponseSchema, Spend, SpentError,
+};
+use crate::redact::Redaction;
+use crate::usage::PendingUsage;
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use worker::*;
+
+/// One call covers the whole transcript, so allow what polish allows across chunks
+const MAX_EXTRACT_LENGTH_HOSTED: usize = 64_000;
+
+const MAX_ACTION_ITEMS: usize = 50;
+
+/// Malformed output gets one retry before the request fails
+const MAX_EXTRACT_ATTEMPTS: u32 = 2;
+
+const DEFAULT_TIMEZONE: &str = "UTC";
+
+const EXTRACT_PROMPT: &str = "You extract action items from dictated meeting notes: tasks someone committed to or was asked to do. For each, give the task as a short imperative sentence, the owner as named in the notes (null if nobody was named), the due date as YYYY-MM-DD and the time of day as 24-hour HH:MM (each null if not mentioned), and a priority: high if urgent or blocking, low if optional, otherwise medium. Resolve relative dates such as \"tomorrow\" or \"next Friday\" against the current date below. Write tasks in the same language as the notes. Return an empty list if there are no action items.";
+
+/// What the model returns, before validation
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExtractedItems {
+    action_items: Vec<ExtractedItem>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExtractedItem {
+    task: String,
+    owner: Option<String>,
+    due_date: Option<String>,
+    due_time: Option<String>,
+    priority: Priority,
+}
+
+pub async fn extract_action_items(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let access = match access::authorize(&req, &ctx).await {
+        Ok(access) => access,
+        Err(e) => return e.into_response(),
+    };
+
+    let body: ActionItemsRequest = match req.json().await {
+        Ok(b) => b,
+        Err(_) => {
+            return ApiError::validation("Invalid request body").into_response();
+        }
+    };
+
+    let text = body.text.trim();
+    if text.is_empty() {
+        return ApiError::validation("Text cannot be empty").into_response();
+    }
+    if !access.is_byok && text.len() > MAX_EXTRACT_LENGTH_HOSTED {
+        return ApiError::Validation {
+            message: format!(
+                "Text too long ({} chars). Maximum is {} chars. Use your own API key for longer texts.",
+                text.len(),
+                MAX_EXTRACT_LENGTH_HOSTED
+            ),
+            details: Some(serde_json::json!({ "max_length": MAX_EXTRACT_LENGTH_HOSTED })),
+        }
+        .into_response();
+    }
+
+    if let Err(e) = access.check_estimate(Estimate::read(text.chars().count())) {
+        return e.into_response();
+    }
+
+    let timezone = body.timezone.as_deref().unwrap_or(DEFAULT_TIMEZONE);
+    let Ok(tz) = timezone.parse::<Tz>() else {
+        return ApiError::validation(
+            "Invalid timezone, expected an IANA name such as \"Europe/Berlin\"",
+        )
+        .into_response();
+    };
+    let now = match resolve_now(body.now.as_deref(), tz) {
+        Ok(now) => now,
+        Err(e) => return ApiError::validation(e).into_response(),
+    };
+
+    let pending_usage = PendingUsage::start(ctx.env.d1("DB")?, &access, "action_items");
+
+    let mut redaction = Redaction::new(access.redacts(body.redact));
+    let (action_items, spend) = match extract(&access.provider, text, now, &mut redaction).await {
+        Ok(result) => result,
+        Err(e) => {
+            console_error!("Provider error: {}", e);
+            pending_usage.record_spent(e.spend.clone()).await;
+            return ApiError::from(e).into_response();
+        }
+    };
+
+    let served_by = access.provider.served_by(&spend);
+    pending_usage.record(spend.clone()).await;
+
+    let billed = access.billed(&spend);
+    let quota = access.quota.map(|mut quota| {
+        quota.consume(billed);
+        quota
+    });
+
+    Response::from_json(&ApiResponse::success(ActionItemsResponse {
+        action_items,
+        timezone: tz.name().to_string(),
+        served_by,
+        usage: spend.usage(),
+        quota,
+    }))
+}
+
+/// The client's "now" in its timezone, or the server clock when not given
+fn resolve_now(now: Option<&str>, tz: Tz) -> std::result::Result<DateTime<Tz>, String> {
+    match now {
+        Some(now) => DateTime::parse_from_rfc3339(now)
+            .map(|now| now.with_timezone(&tz))
+            .map_err(|_| "Invalid now, expected an RFC 3339 timestamp".to_string()),
+        None => Ok(Utc::now().with_timezone(&tz)),
+    }
+}
+
+/// Run the extraction with structured output, retrying once if the model's
+/// JSON doesn't validate. Usage covers every attempt, including failed ones.
+async fn extract(
+    provider: &Provider,
+    text: &str,
+    now: DateTime<Tz>,
+    redaction: &mut Redaction,
+) -> std::result::Result<(Vec<ActionItem>, Spend), SpentError> {
+    let text = redaction.redact(text);
+    let system = guard::system_prompt(&redaction.extend_prompt(&system_prompt(now)));
+    let text = guard::wrap(&text);
+    let options = CompletionOptions {
+        response_schema: Some(ResponseSchema {
+            name: "action_items",
+            schema: action_items_schema(),
+        }),
+        ..Default::default()
+    };
+
+    let mut spend = Spend::default();
+    let mut attempt = 1;
+    loop {
+        let completion = provider
+            .complete(&system, &text, &options)
+            .await
+            .map_err(|error| SpentError {
+                error,
+                spend: spend.clone(),
+            })?;
+        spend.merge(completion.spend);
+
+        match parse_action_items(&redaction.restore(&completion.text), now) {
+            Ok(items) => return Ok((items, spend)),
+            Err(e) if attempt < MAX_EXTRACT_ATTEMPTS => {
+                console_log!("Retrying malformed action items: {}", e);
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(SpentError {
+                    error: ProviderError::InvalidResponse(e),
+                    spend,
+                });
+            }
+        }
+    }
+}
+
+fn system_prompt(now: DateTime<Tz>) -> String {
+    format!(
+        "{}\n\nThe current date and time is {} in the {} timezone.",
+        EXTRACT_PROMPT,
+        now.format("%A, %Y-%m-%d %H:%M"),
+        now.timezone().name()
+    )
+}
+
+/// Strict-mode schema: every property is required, optional ones are nullable
+fn action_items_schema() -> serde_json::Value {
+    serde_json::json!({
+        "type": "object",
+        "properties": {
+            "action_items": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "task": { "type": "string" },
+                        "owner": { "type": ["string", "null"] },
+                        "due_date": { "type": ["string", "null"], "description": "YYYY-MM-DD" },
+                        "due_time": { "type": ["string", "null"], "description": "24-hour HH:MM" },
+                        "priority": { "type": "string", "enum": ["low", "medium", "high"] }
+                    },
+                    "required": ["task", "owner", "due_date", "due_time", "priority"],
+                    "additionalProperties": false
+                }
+            }
+        },
+        "required": ["action_items"],
+        "additionalProperties": false
+    })
+}
+
+/// Validate the model's JSON and resolve due dates in `now`'s timezone. Any
+/// field that doesn't parse rejects the whole response.
+fn parse_action_items(
+    text: &str,
+    now: DateTime<Tz>,
+) -> std::result::Result<Vec<ActionItem>, String> {
+    let start = text.find('{').ok_or("No JSON object in action items")?;
+    let end = text.rfind('}').ok_or("No JSON object in action items")?;
+    if end < start {
+        return Err("No JSON object in action items".to_string());
+    }
+
+    let extracted: ExtractedItems = serde_json::from_str(&text[start..=end])
+        .map_err(|e| format!("Invalid action items: {}", e))?;
+
+    extracted
+        .action_items
+        .into_iter()
+        .take(MAX_ACTION_ITEMS)
+        .map(|item| resolve_item(item, now))
+        .collect()
+}
+
+fn resolve_item(item: ExtractedItem, now: DateTime<Tz>) -> std::result::Result<ActionItem, String> {
+    let task = item.task.trim().to_string();
+    if task.is_empty() {
+        return Err("Action item has no task".to_string());
+    }
+
+    let due_date = item
+        .due_date
+        .as_deref()
+        .map(|date| {
+            NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+                .map_err(|_| format!("Invalid due_date: {}", date))
+        })
+        .transpose()?;
+    let due_time = item
+        .due_time
+        .as_deref()
+        .map(|time| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|_| format!("Invalid due_time: {}", time))
+        })
+        .transpose()?;
+
+    // A time without a date ("by 5pm") means today
+    let due_date = due_date.or(due_time.map(|_| now.date_naive()));
+    // Times that fall into a DST gap keep their date but get no timestamp
+    let due_at = due_date.zip(due_time).and_then(|(date, time)| {
+        now.timezone()
+            .from_local_datetime(&date.and_time(time))
+            .earliest()
+            .map(|at| at.to_rfc3339())
+    });
+
+    Ok(ActionItem {
+        task,
+        owner: item
+            .owner
+            .map(|owner| owner.trim().to_string())
+            .filter(|owner| !owner.is_empty()),
+        due_date: due_date.map(|date| date.format("%Y-%m-%d").to_string()),
+        due_at,
+        priority: item.priority,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn berlin_now() -> DateTime<Tz> {
+        resolve_now(Some("2026-06-10T08:00:00Z"), chrono_tz::Europe::Berlin).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_now() {
+        let now = berlin_now();
+        assert_eq!(now.to_rfc3339(), "2026-06-10T10:00:00+02:00");
+        assert!(resolve_now(Some("yesterday"), chrono_tz::UTC).is_err());
+        assert!(resolve_now(None, chrono_tz::UTC).is_ok());
+    }
+
+    #[test]
+    fn test_system_prompt_includes_local_now() {
+        let prompt = system_prompt(berlin_now());
+        assert!(prompt.contains("Wednesday, 2026-06-10 10:00"));
+        assert!(prompt.contains("Europe/Berlin"));
+    }
+
+    #[test]
+    fn test_schema_requires_every_property() {
+        let schema = action_items_schema();
+        let item = &schema["properties"]["action_items"]["items"];
+        let properties = item["properties"].as_object().unwrap();
+        let required = item["required"].as_array().unwrap();
+        assert_eq!(properties.len(), required.len());
+        assert!(
+            properties
+                .keys()
+                .all(|key| required.contains(&key.as_str().into()))
+        );
+    }
+
+    #[test]
+    fn test_parse_action_items() {
+        let text = r#"{"action_items":[
+            {"task":" Send the deck ","owner":"Priya","due_date":"2026-06-12","due_time":"17:00","priority":"high"},
+            {"task":"Book a room","owner":" ","due_date":null,"due_time":null,"priority":"low"}
+        ]}"#;
+        let items = parse_action_items(text, berlin_now()).unwrap();
+
+        assert_eq!(
+            items[0],
+            ActionItem {
+                task: "Send the deck".to_string(),
+                owner: Some("Priya".to_string()),
+                due_date: Some("2026-06-12".to_string()),
+                due_at: Some("2026-06-12T17:00:00+02:00".to_string()),
+                priority: Priority::High,
+            }
+        );
+        assert_eq!(items[1].owner, None);
+        assert_eq!(items[1].due_date, None);
+        assert_eq!(items[1].due_at, None);
+    }
+
+    #[test]
+    fn test_time_without_date_means_today() {
+        let text = r#"{"action_items":[{"task":"Call back","owner":null,"due_date":null,"due_time":"16:30","priority":"medium"}]}"#;
+        let items = parse_action_items(text, berlin_now()).unwrap();
+        assert_eq!(items[0].due_date.as_deref(), Some("2026-06-10"));
+        assert_eq!(
+            items[0].due_at.as_deref(),
+            Some("2026-06-10T16:30:00+02:00")
+        );
+    }
+
+    #[test]
+    fn test_time_in_dst_gap_has_no_timestamp() {
+        let text = r#"{"action_items":[{"task":"Deploy","owner":null,"due_date":"2026-03-29","due_time":"02:30","priority":"medium"}]}"#;
+        let items = parse_action_items(text, berlin_now()).unwrap();
+        assert_eq!(items[0].due_date.as_deref(), Some("2026-03-29"));
+        assert_eq!(items[0].due_at, None);
+    }
+
+    #[test]
+    fn test_parse_action_items_rejects_malformed() {
+        let now = berlin_now();
+        assert!(parse_action_items("Sure! Here are your action items.", now).is_err());
+        assert!(
+            parse_action_items(
+                r#"{"action_items":[{"task":"A","owner":null,"due_date":null,"due_time":null,"priority":"urgent"}]}"#,
+                now
+            )
+            .is_err()
+        );
+        assert!(
+            parse_action_items(
+                r#"{"action_items":[{"task":"A","owner":null,"due_date":"next friday","due_time":null,"priority":"low"}]}"#,
+                now
+            )
+            .is_err()
+        );
+        assert!(
+            parse_action_items(
+                r#"{"action_items":[{"task":" ","owner":null,"due_date":null,"due_time":null,"priority":"low"}]}"#,
+                now
+            )
+            .is_err()
+        );
+        assert!(
+            parse_action_items(
+                r#"{"action_items":[{"task":"A","priority":"low","confidence":0.9}]}"#,
+                now
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_action_items_empty() {
+        assert!(
+            parse_action_items(r#"{"action_items":[]}"#, berlin_now())
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
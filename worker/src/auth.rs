@@ -1,32 +1,40 @@
-use crate::models::{ApiResponse, AuthCredentials, AuthResponse, TokenClaims, UserInfo};
+use crate::error::ApiError;
+use crate::jwt::{self, Keys};
+use crate::models::{
+    ApiResponse, AuthCredentials, AuthResponse, MeResponse, TokenClaims, TokenPair, UserInfo,
+    UserSettings,
+};
+use crate::redact::RedactionPolicy;
+use crate::refresh;
+use crate::sessions::{self, Device};
+use crate::usage::monthly_quota;
+use crate::verification;
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
-use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
-use hmac::{Hmac, Mac};
 use rand::rngs::OsRng;
-use sha2::Sha256;
 use worker::*;
 
-type HmacSha256 = Hmac<Sha256>;
+/// Access tokens are renewed through `/auth/refresh`, so keep them short-lived
+pub const ACCESS_TOKEN_TTL_SECS: i64 = 15 * 60;
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Lifetime of tokens issued before sessions, which carry no session id
+const LEGACY_TOKEN_TTL_SECS: i64 = 86400 * 90;
 
 pub async fn register(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
     let body: AuthCredentials = match req.json().await {
         Ok(b) => b,
         Err(_) => {
-            return Response::from_json(&ApiResponse::<()>::error("Invalid request body"))
-                .map(|r| r.with_status(400));
+            return ApiError::validation("Invalid request body").into_response();
         }
     };
 
     if !body.email.contains('@') {
-        return Response::from_json(&ApiResponse::<()>::error("Invalid email format"))
-            .map(|r| r.with_status(400));
+        return ApiError::validation("Invalid email format").into_response();
     }
 
-    if body.password.len() < 8 {
-        return Response::from_json(&ApiResponse::<()>::error(
-            "Password must be at least 8 characters",
-        ))
-        .map(|r| r.with_status(400));
+    if body.password.len() < MIN_PASSWORD_LENGTH {
+        return ApiError::validation("Password must be at least 8 characters").into_response();
     }
 
     let db = ctx.env.d1("DB")?;
@@ -42,8 +50,7 @@ pub async fn register(mut req: Request, ctx: RouteContext<()>) -> Result<Respons
         .await?;
 
     if existing.is_some() {
-        return Response::from_json(&ApiResponse::<()>::error("Email already registered"))
-            .map(|r| r.with_status(409));
+        return ApiError::Conflict("Email already registered".to_string()).into_response();
     }
 
     db.prepare("INSERT INTO users (id, email, password_hash, created_at) VALUES (?1, ?2, ?3, ?4)")
@@ -56,13 +63,20 @@ pub async fn register(mut req: Request, ctx: RouteContext<()>) -> Result<Respons
         .run()
         .await?;
 
-    let token = generate_token(&user_id, &ctx)?;
+    // A mail outage shouldn't block sign-up; the link can be resent later
+    if let Err(e) = verification::start(&db, &ctx.env, &user_id, &body.email).await {
+        console_error!("Failed to send verification email: {:?}", e);
+    }
+
+    let device = Device::from_request(&req, body.device_name.as_deref());
+    let tokens = issue_tokens(&db, &user_id, &device, &ctx).await?;
 
     Response::from_json(&ApiResponse::success(AuthResponse {
-        token,
+        tokens,
         user: UserInfo {
             id: user_id,
             email: body.email,
+            email_verified: false,
         },
     }))
 }
@@ -71,15 +85,14 @@ pub async fn login(mut req: Request, ctx: RouteContext<()>) -> Result<Response>
     let body: AuthCredentials = match req.json().await {
         Ok(b) => b,
         Err(_) => {
-            return Response::from_json(&ApiResponse::<()>::error("Invalid request body"))
-                .map(|r| r.with_status(400));
+            return ApiError::validation("Invalid request body").into_response();
         }
     };
 
     let db = ctx.env.d1("DB")?;
 
     let result = db
-        .prepare("SELECT id, email, password_hash FROM users WHERE email = ?1")
+        .prepare("SELECT id, email, password_hash, email_verified_at FROM users WHERE email = ?1")
         .bind(&[body.email.clone().into()])?
         .first::<serde_json::Value>(None)
         .await?;
@@ -87,56 +100,115 @@ pub async fn login(mut req: Request, ctx: RouteContext<()>) -> Result<Response>
     let user = match result {
         Some(u) => u,
         None => {
-            return Response::from_json(&ApiResponse::<()>::error("Invalid credentials"))
-                .map(|r| r.with_status(401));
+            return ApiError::Unauthorized("Invalid credentials".to_string()).into_response();
         }
     };
 
     let stored_hash = user["password_hash"].as_str().unwrap_or("");
 
     if !verify_password(&body.password, stored_hash) {
-        return Response::from_json(&ApiResponse::<()>::error("Invalid credentials"))
-            .map(|r| r.with_status(401));
+        return ApiError::Unauthorized("Invalid credentials".to_string()).into_response();
     }
 
     let user_id = user["id"].as_str().unwrap_or("").to_string();
     let email = user["email"].as_str().unwrap_or("").to_string();
+    let email_verified = !user["email_verified_at"].is_null();
 
-    let token = generate_token(&user_id, &ctx)?;
+    let device = Device::from_request(&req, body.device_name.as_deref());
+    let tokens = issue_tokens(&db, &user_id, &device, &ctx).await?;
 
     Response::from_json(&ApiResponse::success(AuthResponse {
-        token,
-        user: UserInfo { id: user_id, email },
+        tokens,
+        user: UserInfo {
+            id: user_id,
+            email,
+            email_verified,
+        },
     }))
 }
 
 pub async fn get_me(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let user_id = match extract_and_verify_token(&req, &ctx) {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
         Ok(id) => id,
         Err(e) => {
-            return Response::from_json(&ApiResponse::<()>::error(e)).map(|r| r.with_status(401));
+            return ApiError::Unauthorized(e).into_response();
         }
     };
 
     let db = ctx.env.d1("DB")?;
 
     let result = db
-        .prepare("SELECT id, email FROM users WHERE id = ?1")
-        .bind(&[user_id.into()])?
+        .prepare("SELECT id, email, email_verified_at FROM users WHERE id = ?1")
+        .bind(&[user_id.clone().into()])?
         .first::<serde_json::Value>(None)
         .await?;
 
     match result {
-        Some(user) => Response::from_json(&ApiResponse::success(UserInfo {
-            id: user["id"].as_str().unwrap_or("").to_string(),
-            email: user["email"].as_str().unwrap_or("").to_string(),
+        Some(user) => Response::from_json(&ApiResponse::success(MeResponse {
+            user: UserInfo {
+                id: user["id"].as_str().unwrap_or("").to_string(),
+                email: user["email"].as_str().unwrap_or("").to_string(),
+                email_verified: !user["email_verified_at"].is_null(),
+            },
+            quota: monthly_quota(&db, &user_id).await?,
+            settings: user_settings(&db, &ctx.env, &user_id).await?,
         })),
-        None => Response::from_json(&ApiResponse::<()>::error("User not found"))
-            .map(|r| r.with_status(404)),
+        None => ApiError::NotFound("User not found".to_string()).into_response(),
     }
 }
 
-fn get_query_param(url: &Url, key: &str) -> Option<String> {
+pub async fn update_settings(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
+        Ok(id) => id,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let body: UserSettings = match req.json().await {
+        Ok(b) => b,
+        Err(_) => {
+            return ApiError::validation("Invalid request body").into_response();
+        }
+    };
+
+    if !body.redact_pii && RedactionPolicy::from_env(&ctx.env) == RedactionPolicy::Required {
+        return ApiError::Forbidden("Redaction is required on this server".to_string())
+            .into_response();
+    }
+
+    let db = ctx.env.d1("DB")?;
+    db.prepare("UPDATE users SET redact_pii = ?1 WHERE id = ?2")
+        .bind(&[
+            (if body.redact_pii { 1.0 } else { 0.0 }).into(),
+            user_id.clone().into(),
+        ])?
+        .run()
+        .await?;
+
+    Response::from_json(&ApiResponse::success(
+        user_settings(&db, &ctx.env, &user_id).await?,
+    ))
+}
+
+/// The user's account settings, defaults for an unknown user. Redaction is
+/// always on where the operator requires it.
+pub async fn user_settings(db: &D1Database, env: &Env, user_id: &str) -> Result<UserSettings> {
+    let row = db
+        .prepare("SELECT redact_pii FROM users WHERE id = ?1")
+        .bind(&[user_id.into()])?
+        .first::<serde_json::Value>(None)
+        .await?;
+
+    let required = RedactionPolicy::from_env(env) == RedactionPolicy::Required;
+    Ok(UserSettings {
+        redact_pii: required
+            || row.is_some_and(|row| row["redact_pii"].as_f64().unwrap_or(0.0) != 0.0),
+        redact_pii_required: required,
+    })
+}
+
+pub fn get_query_param(url: &Url, key: &str) -> Option<String> {
     url.query_pairs()
         .find(|(k, _)| k == key)
         .map(|(_, v)| v.to_string())
@@ -153,8 +225,7 @@ pub async fn oauth_start(req: Request, ctx: RouteContext<()>) -> Result<Response
     let allowed_list: Vec<&str> = allowed.split(',').collect();
 
     if !allowed_list.contains(&redirect_uri.as_str()) {
-        return Response::from_json(&ApiResponse::<()>::error("Invalid redirect_uri"))
-            .map(|r| r.with_status(400));
+        return ApiError::validation("Invalid redirect_uri").into_response();
     }
 
     let db = ctx.env.d1("DB")?;
@@ -203,8 +274,7 @@ pub async fn oauth_start(req: Request, ctx: RouteContext<()>) -> Result<Response
             )
         }
         _ => {
-            return Response::from_json(&ApiResponse::<()>::error("Unknown OAuth provider"))
-                .map(|r| r.with_status(400));
+            return ApiError::validation("Unknown OAuth provider").into_response();
         }
     };
 
@@ -221,8 +291,7 @@ pub async fn oauth_callback(req: Request, ctx: RouteContext<()>) -> Result<Respo
     ) {
         (Some(c), Some(s)) => (c, s),
         _ => {
-            return Response::from_json(&ApiResponse::<()>::error("Missing code or state"))
-                .map(|r| r.with_status(400));
+            return ApiError::validation("Missing code or state").into_response();
         }
     };
 
@@ -237,8 +306,7 @@ pub async fn oauth_callback(req: Request, ctx: RouteContext<()>) -> Result<Respo
     let session = match session {
         Some(s) => s,
         None => {
-            return Response::from_json(&ApiResponse::<()>::error("Invalid or expired state"))
-                .map(|r| r.with_status(400));
+            return ApiError::validation("Invalid or expired state").into_response();
         }
     };
 
@@ -246,8 +314,7 @@ pub async fn oauth_callback(req: Request, ctx: RouteContext<()>) -> Result<Respo
     let expires_at = session["expires_at"].as_f64().unwrap_or(0.0) as i64;
 
     if chrono::Utc::now().timestamp() > expires_at {
-        return Response::from_json(&ApiResponse::<()>::error("OAuth session expired"))
-            .map(|r| r.with_status(400));
+        return ApiError::validation("OAuth session expired").into_response();
     }
 
     // Delete the session (one-time use)
@@ -256,12 +323,11 @@ pub async fn oauth_callback(req: Request, ctx: RouteContext<()>) -> Result<Respo
         .run()
         .await?;
 
-    let (email, provider_id) = match provider.as_str() {
+    let (email, provider_id, email_verified) = match provider.as_str() {
         "google" => exchange_google_code(&code, &ctx).await?,
         "github" => exchange_github_code(&code, &ctx).await?,
         _ => {
-            return Response::from_json(&ApiResponse::<()>::error("Unknown provider"))
-                .map(|r| r.with_status(400));
+            return ApiError::validation("Unknown provider").into_response();
         }
     };
 
@@ -274,14 +340,14 @@ pub async fn oauth_callback(req: Request, ctx: RouteContext<()>) -> Result<Respo
 
     let queries = match provider.as_str() {
         "google" => ProviderQueries {
-            select: "SELECT id, email FROM users WHERE email = ?1 OR google_id = ?2",
+            select: "SELECT id, email, email_verified_at FROM users WHERE email = ?1 OR google_id = ?2",
             update: "UPDATE users SET google_id = ?1 WHERE id = ?2",
-            insert: "INSERT INTO users (id, email, google_id, created_at) VALUES (?1, ?2, ?3, ?4)",
+            insert: "INSERT INTO users (id, email, google_id, created_at, email_verified_at) VALUES (?1, ?2, ?3, ?4, ?5)",
         },
         "github" => ProviderQueries {
-            select: "SELECT id, email FROM users WHERE email = ?1 OR github_id = ?2",
+            select: "SELECT id, email, email_verified_at FROM users WHERE email = ?1 OR github_id = ?2",
             update: "UPDATE users SET github_id = ?1 WHERE id = ?2",
-            insert: "INSERT INTO users (id, email, github_id, created_at) VALUES (?1, ?2, ?3, ?4)",
+            insert: "INSERT INTO users (id, email, github_id, created_at, email_verified_at) VALUES (?1, ?2, ?3, ?4, ?5)",
         },
         _ => unreachable!(), // Already validated above
     };
@@ -298,31 +364,59 @@ pub async fn oauth_callback(req: Request, ctx: RouteContext<()>) -> Result<Respo
             .bind(&[provider_id.into(), id.clone().into()])?
             .run()
             .await?;
+        if email_verified && user["email_verified_at"].is_null() && user["email"] == email {
+            // The provider proved ownership of the address. Whoever set the
+            // password on this unverified account never did, so drop it and
+            // sign out anyone who got in with it.
+            let now = chrono::Utc::now().timestamp();
+            let mut statements = vec![
+                db.prepare("UPDATE users SET email_verified_at = ?1, password_hash = NULL WHERE id = ?2 AND email_verified_at IS NULL")
+                    .bind(&[(now as f64).into(), id.clone().into()])?,
+            ];
+            statements.extend(sessions::revoke_all_statements(&db, &id, now)?);
+            db.batch(statements).await?;
+        }
         id
     } else {
         let id = uuid::Uuid::new_v4().to_string();
-        let now = chrono::Utc::now().timestamp() as f64;
+        let now = chrono::Utc::now().timestamp();
+        let verified_at = oauth_verified_at(email_verified, now);
         db.prepare(queries.insert)
             .bind(&[
                 id.clone().into(),
                 email.clone().into(),
                 provider_id.into(),
-                now.into(),
+                (now as f64).into(),
+                verified_at.map_or(wasm_bindgen::JsValue::NULL, |t| (t as f64).into()),
             ])?
             .run()
             .await?;
+        if verified_at.is_none()
+            && let Err(e) = verification::start(&db, &ctx.env, &id, &email).await
+        {
+            console_error!("Failed to send verification email: {:?}", e);
+        }
         id
     };
 
-    let token = generate_token(&user_id, &ctx)?;
+    let tokens = issue_tokens(&db, &user_id, &Device::from_request(&req, None), &ctx).await?;
     let mut final_url = Url::parse(&redirect_uri)?;
-    final_url.query_pairs_mut().append_pair("token", &token);
+    final_url
+        .query_pairs_mut()
+        .append_pair("token", &tokens.token)
+        .append_pair("refresh_token", &tokens.refresh_token);
 
     Response::redirect(final_url)
 }
 
+/// When a new OAuth account counts as verified: only if the provider verified
+/// the address, otherwise it goes through email verification like a sign-up
+fn oauth_verified_at(email_verified: bool, now: i64) -> Option<i64> {
+    email_verified.then_some(now)
+}
+
 /// Hash a password using Argon2
-fn hash_password(password: &str) -> Result<String> {
+pub fn hash_password(password: &str) -> Result<String> {
     let argon2 = Argon2::default();
     let salt = SaltString::generate(&mut OsRng);
     argon2
@@ -342,37 +436,63 @@ fn verify_password(password: &str, stored: &str) -> bool {
         .is_ok()
 }
 
-/// Generate a JWT-like token with HMAC-SHA256 signature
-fn generate_token(user_id: &str, ctx: &RouteContext<()>) -> Result<String> {
-    let secret = ctx
-        .env
-        .secret("JWT_SECRET")
-        .map(|s| s.to_string())
-        .map_err(|_| Error::RustError("JWT_SECRET not configured".to_string()))?;
+/// Start a session for a sign-in: an access token plus the first refresh token
+/// of a new family, which shares the session's id
+pub async fn issue_tokens(
+    db: &D1Database,
+    user_id: &str,
+    device: &Device,
+    ctx: &RouteContext<()>,
+) -> Result<TokenPair> {
+    let session_id = sessions::create(db, user_id, device).await?;
+    let refresh_token = refresh::issue(db, user_id, &session_id).await?;
+    Ok(TokenPair {
+        token: generate_token(user_id, &session_id, ctx)?,
+        expires_in: ACCESS_TOKEN_TTL_SECS,
+        refresh_token,
+    })
+}
+
+/// Generate a short-lived access token for the user's session
+pub fn generate_token(user_id: &str, session_id: &str, ctx: &RouteContext<()>) -> Result<String> {
+    let keys = Keys::from_env(&ctx.env).map_err(Error::RustError)?;
+    let now = chrono::Utc::now().timestamp();
 
     let claims = TokenClaims {
         sub: user_id.to_string(),
-        exp: chrono::Utc::now().timestamp() + 86400 * 90, // 90 days
+        exp: now + ACCESS_TOKEN_TTL_SECS,
+        sid: Some(session_id.to_string()),
+        iat: Some(now),
+        nbf: None,
+        iss: Some(jwt::ISSUER.to_string()),
+        aud: Some(jwt::API_AUDIENCE.to_string()),
     };
 
-    // Create JWT-like token: base64url(claims).base64url(hmac-sha256(claims))
-    let claims_json = serde_json::to_string(&claims)?;
-    let claims_b64 = URL_SAFE_NO_PAD.encode(&claims_json);
-
-    let mut mac =
-        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
-    mac.update(claims_b64.as_bytes());
-    let signature = mac.finalize().into_bytes();
-    let sig_b64 = URL_SAFE_NO_PAD.encode(signature);
+    Ok(keys.sign(&claims)?)
+}
 
-    Ok(format!("{}.{}", claims_b64, sig_b64))
+/// The verified caller of a request
+pub struct Authenticated {
+    pub user_id: String,
+    /// Absent for tokens issued before sessions existed
+    pub session_id: Option<String>,
 }
 
-/// Extract and verify a JWT-like token from the Authorization header
-pub fn extract_and_verify_token(
+/// Extract and verify a JWT-like token from the Authorization header, returning
+/// the user id
+pub async fn extract_and_verify_token(
     req: &Request,
     ctx: &RouteContext<()>,
 ) -> std::result::Result<String, String> {
+    authenticate(req, ctx).await.map(|auth| auth.user_id)
+}
+
+/// Verify the bearer token, then check with one primary-key lookup that its
+/// session hasn't been signed out
+pub async fn authenticate(
+    req: &Request,
+    ctx: &RouteContext<()>,
+) -> std::result::Result<Authenticated, String> {
     let auth_header = req
         .headers()
         .get("Authorization")
@@ -383,44 +503,53 @@ pub fn extract_and_verify_token(
         .strip_prefix("Bearer ")
         .ok_or("Invalid Authorization header format")?;
 
-    let secret = ctx
-        .env
-        .secret("JWT_SECRET")
-        .map(|s| s.to_string())
-        .map_err(|_| "JWT_SECRET not configured".to_string())?;
+    let claims: TokenClaims = Keys::from_env(&ctx.env)?.verify(
+        token,
+        jwt::API_AUDIENCE,
+        chrono::Utc::now().timestamp(),
+    )?;
 
-    let parts: Vec<&str> = token.split('.').collect();
-    if parts.len() != 2 {
-        return Err("Invalid token format".to_string());
+    let db = ctx
+        .env
+        .d1("DB")
+        .map_err(|_| "Database not configured".to_string())?;
+    let active = match &claims.sid {
+        Some(session_id) => sessions::is_active(&db, &claims.sub, session_id).await,
+        None => legacy_token_active(&db, &claims).await,
+    }
+    .map_err(|e| format!("Session lookup failed: {}", e))?;
+    if !active {
+        return Err("Session revoked".to_string());
     }
 
-    let mut mac =
-        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
-    mac.update(parts[0].as_bytes());
-
-    let expected_sig = URL_SAFE_NO_PAD
-        .decode(parts[1])
-        .map_err(|_| "Invalid signature encoding")?;
-
-    mac.verify_slice(&expected_sig)
-        .map_err(|_| "Invalid token signature")?;
-
-    let claims_json = URL_SAFE_NO_PAD
-        .decode(parts[0])
-        .map_err(|_| "Invalid token encoding")?;
-    let claims_str = String::from_utf8(claims_json).map_err(|_| "Invalid token encoding")?;
-
-    let claims: TokenClaims =
-        serde_json::from_str(&claims_str).map_err(|_| "Invalid token claims")?;
+    Ok(Authenticated {
+        user_id: claims.sub,
+        session_id: claims.sid,
+    })
+}
 
-    if chrono::Utc::now().timestamp() > claims.exp {
-        return Err("Token expired".to_string());
-    }
+/// Tokens without a session can only be revoked by signing out everywhere
+async fn legacy_token_active(db: &D1Database, claims: &TokenClaims) -> Result<bool> {
+    let signed_out_at = db
+        .prepare("SELECT signed_out_at FROM users WHERE id = ?1")
+        .bind(&[claims.sub.clone().into()])?
+        .first::<serde_json::Value>(None)
+        .await?
+        .and_then(|row| row["signed_out_at"].as_f64())
+        .map(|t| t as i64);
+    Ok(!issued_before(claims.exp, signed_out_at))
+}
 
-    Ok(claims.sub)
+/// Whether a legacy token, known only by its expiry, may predate `signed_out_at`
+fn issued_before(exp: i64, signed_out_at: Option<i64>) -> bool {
+    signed_out_at.is_some_and(|t| exp - LEGACY_TOKEN_TTL_SECS <= t)
 }
 
-async fn exchange_google_code(code: &str, ctx: &RouteContext<()>) -> Result<(String, String)> {
+/// Returns the email, the provider's user id and whether the provider verified the email
+async fn exchange_google_code(
+    code: &str,
+    ctx: &RouteContext<()>,
+) -> Result<(String, String, bool)> {
     let client_id = ctx.env.secret("GOOGLE_CLIENT_ID")?.to_string();
     let client_secret = ctx.env.secret("GOOGLE_CLIENT_SECRET")?.to_string();
 
@@ -468,10 +597,15 @@ async fn exchange_google_code(code: &str, ctx: &RouteContext<()>) -> Result<(Str
         .ok_or_else(|| Error::RustError("No Google ID".to_string()))?
         .to_string();
 
-    Ok((email, google_id))
+    let verified = user_info["verified_email"].as_bool() == Some(true);
+
+    Ok((email, google_id, verified))
 }
 
-async fn exchange_github_code(code: &str, ctx: &RouteContext<()>) -> Result<(String, String)> {
+async fn exchange_github_code(
+    code: &str,
+    ctx: &RouteContext<()>,
+) -> Result<(String, String, bool)> {
     let client_id = ctx.env.secret("GITHUB_CLIENT_ID")?.to_string();
     let client_secret = ctx.env.secret("GITHUB_CLIENT_SECRET")?.to_string();
 
@@ -523,14 +657,17 @@ async fn exchange_github_code(code: &str, ctx: &RouteContext<()>) -> Result<(Str
     let mut resp = Fetch::Request(req).send().await?;
     let emails: Vec<serde_json::Value> = resp.json().await?;
 
-    let email = emails
+    let primary = emails
         .iter()
         .find(|e| e["primary"].as_bool() == Some(true))
-        .and_then(|e| e["email"].as_str())
+        .ok_or_else(|| Error::RustError("No primary email".to_string()))?;
+    let email = primary["email"]
+        .as_str()
         .ok_or_else(|| Error::RustError("No primary email".to_string()))?
         .to_string();
+    let verified = primary["verified"].as_bool() == Some(true);
 
-    Ok((email, github_id))
+    Ok((email, github_id, verified))
 }
 
 #[cfg(test)]
@@ -591,6 +728,22 @@ mod tests {
         assert!(verify_password(password, &hash));
     }
 
+    #[test]
+    fn test_oauth_verified_at() {
+        assert_eq!(oauth_verified_at(true, 1_000), Some(1_000));
+        // An address the provider hasn't verified must not skip UNVERIFIED_ACCOUNTS
+        assert_eq!(oauth_verified_at(false, 1_000), None);
+    }
+
+    #[test]
+    fn test_issued_before_sign_out() {
+        let exp = 10_000 + LEGACY_TOKEN_TTL_SECS;
+        assert!(!issued_before(exp, None));
+        assert!(issued_before(exp, Some(10_000)));
+        assert!(issued_before(exp, Some(20_000)));
+        assert!(!issued_before(exp, Some(9_999)));
+    }
+
     #[test]
     fn test_verify_password_unicode() {
         let password = "пароль密码🔐";
@@ -0,0 +1,188 @@
+use crate::access::RATE_LIMIT_PERIOD_SECS;
+use crate::catalog::ModelCatalog;
+use crate::error::ApiError;
+use crate::models::{ApiResponse, ByokValidation};
+use crate::provider::{OPENAI_BASE_URL, ProviderError, endpoint};
+use worker::*;
+
+/// Check a BYOK key with the cheapest upstream call there is: listing models.
+/// The key is never logged, stored, or echoed back beyond its fingerprint.
+pub async fn validate_key(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let key = match req.headers().get("X-OpenAI-Key")? {
+        Some(key) if !key.trim().is_empty() => key.trim().to_string(),
+        _ => return ApiError::validation("Missing X-OpenAI-Key header").into_response(),
+    };
+
+    // Keeps the endpoint from being used to test stolen keys in bulk
+    let client = req
+        .headers()
+        .get("CF-Connecting-IP")?
+        .unwrap_or_else(|| "unknown".to_string());
+    if !ctx
+        .rate_limiter("RATE_LIMIT")?
+        .limit(format!("byok-validate:{}", client))
+        .await?
+        .success
+    {
+        return ApiError::RateLimited {
+            retry_after: RATE_LIMIT_PERIOD_SECS,
+        }
+        .into_response();
+    }
+
+    let fingerprint = fingerprint(&key);
+    let listed = match list_models(&key).await {
+        Ok(listed) => listed,
+        Err(ProviderError::Status { status, .. }) if status == 401 || status == 403 => {
+            return Response::from_json(&ApiResponse::success(ByokValidation {
+                valid: false,
+                fingerprint,
+                models: Vec::new(),
+                reason: Some(
+                    if status == 401 {
+                        "OpenAI rejected the key"
+                    } else {
+                        "The key is not allowed to list models"
+                    }
+                    .to_string(),
+                ),
+            }));
+        }
+        Err(e) => {
+            // Upstream bodies can quote part of the key, so only the status is logged
+            console_error!(
+                "BYOK validation failed for {}: {}",
+                fingerprint,
+                redacted(&e)
+            );
+            return ApiError::from(e).into_response();
+        }
+    };
+
+    Response::from_json(&ApiResponse::success(ByokValidation {
+        valid: true,
+        fingerprint,
+        models: usable_models(&ModelCatalog::byok(&ctx.env), &listed),
+        reason: None,
+    }))
+}
+
+async fn list_models(key: &str) -> std::result::Result<serde_json::Value, ProviderError> {
+    let headers = Headers::new();
+    headers.set("Authorization", &format!("Bearer {}", key))?;
+
+    let mut init = RequestInit::new();
+    init.with_headers(headers);
+
+    let req = Request::new_with_init(&endpoint(OPENAI_BASE_URL, "models"), &init)?;
+    let mut resp = Fetch::Request(req).send().await?;
+
+    if !(200..300).contains(&resp.status_code()) {
+        return Err(ProviderError::Status {
+            provider: "openai",
+            status: resp.status_code(),
+            body: String::new(),
+        });
+    }
+
+    Ok(resp.json().await?)
+}
+
+/// A provider error fit for logs: no upstream body, no request details
+fn redacted(e: &ProviderError) -> String {
+    match e {
+        ProviderError::Status {
+            provider, status, ..
+        } => format!("{} API error ({})", provider, status),
+        ProviderError::Timeout(ms) => format!("timed out after {} ms", ms),
+        _ => "request failed".to_string(),
+    }
+}
+
+/// Enough of a key to tell keys apart in the UI: its type prefix and last four
+/// characters, e.g. `sk-proj-…x7Qa`
+pub fn fingerprint(key: &str) -> String {
+    let prefix = ["sk-proj-", "sk-svcacct-", "sk-admin-", "sk-"]
+        .into_iter()
+        .find(|prefix| key.starts_with(prefix))
+        .unwrap_or_default();
+
+    let chars: Vec<char> = key.chars().collect();
+    // Short strings would give most of themselves away
+    if chars.len() < prefix.len() + 12 {
+        return format!("{}…", prefix);
+    }
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}…{}", prefix, tail)
+}
+
+/// The BYOK allowlist, in allowlist order, filtered to models the key can see
+pub fn usable_models(catalog: &ModelCatalog, listed: &serde_json::Value) -> Vec<String> {
+    let listed: Vec<&str> = listed["data"]
+        .as_array()
+        .map(|models| models.iter().filter_map(|m| m["id"].as_str()).collect())
+        .unwrap_or_default();
+
+    catalog
+        .models
+        .iter()
+        .filter(|m| listed.contains(&m.id.as_str()))
+        .map(|m| m.id.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::ModelOption;
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(
+            fingerprint("sk-proj-abcdefghijklmnopqrstuvwxyz0123"),
+            "sk-proj-…0123"
+        );
+        assert_eq!(fingerprint("sk-abcdefghijklmnopWXYZ"), "sk-…WXYZ");
+        assert_eq!(fingerprint("custom-key-1234567890"), "…7890");
+        assert_eq!(fingerprint("sk-short"), "sk-…");
+        assert_eq!(fingerprint(""), "…");
+    }
+
+    #[test]
+    fn test_fingerprint_hides_the_key() {
+        let key = "sk-proj-T0pS3cr3tK3yV4lu3Th4tSh0uldN0tL34k";
+        let fp = fingerprint(key);
+        assert!(fp.chars().count() <= "sk-proj-".len() + 5);
+        assert!(!fp.contains("T0pS3cr3t"));
+    }
+
+    #[test]
+    fn test_usable_models() {
+        let catalog = ModelCatalog {
+            default: "a".to_string(),
+            models: ["a", "b", "c"]
+                .into_iter()
+                .map(|id| ModelOption {
+                    id: id.to_string(),
+                    cost_weight: 1.0,
+                })
+                .collect(),
+        };
+        let listed = serde_json::json!({
+            "object": "list",
+            "data": [{ "id": "c" }, { "id": "a" }, { "id": "whisper-1" }]
+        });
+        assert_eq!(usable_models(&catalog, &listed), vec!["a", "c"]);
+        assert!(usable_models(&catalog, &serde_json::json!({})).is_empty());
+    }
+
+    #[test]
+    fn test_redacted_drops_upstream_body() {
+        let e = ProviderError::Status {
+            provider: "openai",
+            status: 500,
+            body: "Incorrect API key provided: sk-proj-***abcd".to_string(),
+        };
+        assert_eq!(redacted(&e), "openai API error (500)");
+    }
+}
@@ -0,0 +1,233 @@
+use crate::error::ApiError;
+use crate::models::{ApiResponse, ModelInfo, ModelsResponse};
+use crate::provider::{DEFAULT_OPENAI_MODEL, ProviderResult, hosted_model};
+use worker::*;
+
+/// Models BYOK callers may pick when `BYOK_MODELS` isn't set. BYOK keys always
+/// go to OpenAI, and the caller pays, so there is no cost weight.
+const DEFAULT_BYOK_MODELS: &[&str] = &[
+    DEFAULT_OPENAI_MODEL,
+    "gpt-5-mini-2025-08-07",
+    "gpt-5-2025-08-07",
+];
+
+/// An allowed model and how many quota tokens each of its tokens costs
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModelOption {
+    pub id: String,
+    pub cost_weight: f64,
+}
+
+/// The models a request may ask for in one mode (hosted or BYOK)
+#[derive(Debug, PartialEq)]
+pub struct ModelCatalog {
+    pub default: String,
+    pub models: Vec<ModelOption>,
+}
+
+impl ModelCatalog {
+    /// `HOSTED_MODELS`, always including the hosted default model
+    pub fn hosted(env: &Env) -> ProviderResult<Self> {
+        let default = hosted_model(env)?;
+        Ok(Self::from_config(
+            default,
+            env_var(env, "HOSTED_MODELS"),
+            &[],
+        ))
+    }
+
+    /// `BYOK_MODELS`, or a few current OpenAI models
+    pub fn byok(env: &Env) -> Self {
+        let mut catalog = Self::from_config(
+            DEFAULT_OPENAI_MODEL.to_string(),
+            env_var(env, "BYOK_MODELS"),
+            DEFAULT_BYOK_MODELS,
+        );
+        for model in &mut catalog.models {
+            model.cost_weight = 1.0;
+        }
+        catalog
+    }
+
+    fn from_config(default: String, configured: Option<String>, fallback: &[&str]) -> Self {
+        let mut models = match configured.as_deref().map(parse_model_list) {
+            Some(Ok(models)) => models,
+            Some(Err(e)) => {
+                console_error!("Ignoring invalid model list: {}", e);
+                Vec::new()
+            }
+            None => fallback
+                .iter()
+                .map(|id| ModelOption {
+                    id: id.to_string(),
+                    cost_weight: 1.0,
+                })
+                .collect(),
+        };
+        if !models.iter().any(|m| m.id == default) {
+            models.insert(
+                0,
+                ModelOption {
+                    id: default.clone(),
+                    cost_weight: 1.0,
+                },
+            );
+        }
+        Self { default, models }
+    }
+
+    /// The requested model, or the default when none was asked for
+    pub fn resolve(&self, requested: Option<&str>) -> std::result::Result<&ModelOption, ApiError> {
+        let id = requested.map(str::trim).unwrap_or(&self.default);
+        self.models
+            .iter()
+            .find(|m| m.id == id)
+            .ok_or_else(|| ApiError::Validation {
+                message: format!("Model \"{}\" is not available", id),
+                details: Some(serde_json::json!({
+                    "allowed": self.models.iter().map(|m| &m.id).collect::<Vec<_>>(),
+                })),
+            })
+    }
+
+    fn info(&self, with_weights: bool) -> Vec<ModelInfo> {
+        self.models
+            .iter()
+            .map(|m| ModelInfo {
+                id: m.id.clone(),
+                default: m.id == self.default,
+                cost_weight: with_weights.then_some(m.cost_weight),
+            })
+            .collect()
+    }
+}
+
+/// List the models each mode accepts in a polish request's `model` field
+pub async fn list_models(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let hosted = match ModelCatalog::hosted(&ctx.env) {
+        Ok(catalog) => catalog.info(true),
+        Err(e) => return ApiError::from(e).into_response(),
+    };
+
+    Response::from_json(&ApiResponse::success(ModelsResponse {
+        hosted,
+        byok: ModelCatalog::byok(&ctx.env).info(false),
+    }))
+}
+
+fn env_var(env: &Env, name: &str) -> Option<String> {
+    env.var(name)
+        .ok()
+        .map(|v| v.to_string())
+        .filter(|v| !v.trim().is_empty())
+}
+
+/// Parse `model[=weight],...`, e.g. `gpt-5-nano,gpt-5=8`. Weights default to 1.
+fn parse_model_list(value: &str) -> std::result::Result<Vec<ModelOption>, String> {
+    let mut models: Vec<ModelOption> = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (id, cost_weight) = match entry.rsplit_once('=') {
+            Some((id, weight)) => {
+                let weight = weight
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|w| w.is_finite() && *w > 0.0)
+                    .ok_or_else(|| format!("invalid cost weight in \"{}\"", entry))?;
+                (id.trim(), weight)
+            }
+            None => (entry, 1.0),
+        };
+        if id.is_empty() {
+            return Err(format!("missing model id in \"{}\"", entry));
+        }
+        if models.iter().any(|m| m.id == id) {
+            return Err(format!("duplicate model \"{}\"", id));
+        }
+        models.push(ModelOption {
+            id: id.to_string(),
+            cost_weight,
+        });
+    }
+    Ok(models)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(id: &str, cost_weight: f64) -> ModelOption {
+        ModelOption {
+            id: id.to_string(),
+            cost_weight,
+        }
+    }
+
+    #[test]
+    fn test_parse_model_list() {
+        assert_eq!(
+            parse_model_list(" gpt-5-nano , gpt-5=8,llama3:8b=0.5,").unwrap(),
+            vec![
+                option("gpt-5-nano", 1.0),
+                option("gpt-5", 8.0),
+                option("llama3:8b", 0.5)
+            ]
+        );
+        assert!(parse_model_list("gpt-5=0").is_err());
+        assert!(parse_model_list("gpt-5=lots").is_err());
+        assert!(parse_model_list("=2").is_err());
+        assert!(parse_model_list("a,a=2").is_err());
+    }
+
+    #[test]
+    fn test_catalog_always_includes_default() {
+        let catalog =
+            ModelCatalog::from_config("small".to_string(), Some("big=4".to_string()), &[]);
+        assert_eq!(
+            catalog.models,
+            vec![option("small", 1.0), option("big", 4.0)]
+        );
+
+        let catalog = ModelCatalog::from_config(
+            "small".to_string(),
+            Some("big=4,small=0.5".to_string()),
+            &[],
+        );
+        assert_eq!(catalog.resolve(None).unwrap(), &option("small", 0.5));
+    }
+
+    #[test]
+    fn test_catalog_fallback() {
+        let catalog = ModelCatalog::from_config("a".to_string(), None, &["a", "b"]);
+        assert_eq!(catalog.models, vec![option("a", 1.0), option("b", 1.0)]);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let catalog =
+            ModelCatalog::from_config("small".to_string(), Some("big=4".to_string()), &[]);
+        assert_eq!(catalog.resolve(Some("big")).unwrap().cost_weight, 4.0);
+        assert_eq!(catalog.resolve(None).unwrap().id, "small");
+
+        match catalog.resolve(Some("huge")) {
+            Err(ApiError::Validation { details, .. }) => {
+                assert_eq!(
+                    details.unwrap()["allowed"],
+                    serde_json::json!(["small", "big"])
+                );
+            }
+            _ => panic!("expected a validation error"),
+        }
+    }
+
+    #[test]
+    fn test_info() {
+        let catalog =
+            ModelCatalog::from_config("small".to_string(), Some("big=4".to_string()), &[]);
+        let info = catalog.info(true);
+        assert!(info[0].default);
+        assert!(!info[1].default);
+        assert_eq!(info[1].cost_weight, Some(4.0));
+        assert_eq!(catalog.info(false)[1].cost_weight, None);
+    }
+}
@@ -0,0 +1,300 @@
+/// Split text into chunks of at most `max_len` bytes, breaking at paragraph
+/// boundaries where possible, then sentences, then words.
+pub fn split_into_chunks(text: &str, max_len: usize) -> Vec<String> {
+    let text = text.trim();
+    if text.len() <= max_len {
+        return vec![text.to_string()];
+    }
+
+    // Each piece remembers whether it starts a new paragraph
+    let mut pieces: Vec<(&str, bool)> = Vec::new();
+    for paragraph in paragraphs(text) {
+        if paragraph.len() <= max_len {
+            pieces.push((paragraph, true));
+            continue;
+        }
+        let mut first = true;
+        for (start, end) in sentence_spans(paragraph) {
+            for part in hard_split(&paragraph[start..end], max_len) {
+                pieces.push((part, first));
+                first = false;
+            }
+        }
+    }
+
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for (piece, new_paragraph) in pieces {
+        let joiner = if new_paragraph { "\n\n" } else { " " };
+        if !current.is_empty() && current.len() + joiner.len() + piece.len() > max_len {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push_str(joiner);
+        }
+        current.push_str(piece);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+fn paragraphs(text: &str) -> impl Iterator<Item = &str> {
+    text.split("\n\n")
+        .flat_map(|p| p.split("\r\n\r\n"))
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+}
+
+/// Byte ranges of the sentences in `text`, trimmed of surrounding whitespace.
+/// A sentence ends at `.`, `!` or `?` followed by whitespace, or at a blank line.
+fn sentence_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, n)| n);
+        let end = i + c.len_utf8();
+        let sentence_end = matches!(c, '.' | '!' | '?') && next.is_none_or(char::is_whitespace);
+        let blank_line = c == '\n'
+            && text[end..]
+                .trim_start_matches([' ', '\t', '\r'])
+                .starts_with('\n');
+
+        if sentence_end || blank_line {
+            push_trimmed(text, start, end, &mut spans);
+            start = end;
+        }
+    }
+    push_trimmed(text, start, text.len(), &mut spans);
+
+    spans
+}
+
+fn push_trimmed(text: &str, start: usize, end: usize, spans: &mut Vec<(usize, usize)>) {
+    let slice = &text[start..end];
+    let trimmed_start = start + (slice.len() - slice.trim_start().len());
+    let trimmed_end = end - (slice.len() - slice.trim_end().len());
+    if trimmed_start < trimmed_end {
+        spans.push((trimmed_start, trimmed_end));
+    }
+}
+
+/// Last-resort split of an oversized sentence at whitespace (or any char boundary)
+fn hard_split(text: &str, max_len: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = text.trim();
+
+    while rest.len() > max_len {
+        let mut cut = max_len;
+        while !rest.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        if let Some(ws) = rest[..cut].rfind(char::is_whitespace).filter(|&ws| ws > 0) {
+            cut = ws;
+        }
+        if cut == 0 {
+            cut = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        parts.push(rest[..cut].trim_end());
+        rest = rest[cut..].trim_start();
+    }
+    if !rest.is_empty() {
+        parts.push(rest);
+    }
+
+    parts
+}
+
+/// The text around each boundary between polished chunks that a stitching pass
+/// should smooth over: the last sentence of one chunk and the first of the next.
+/// A boundary is skipped (`None`) when its left chunk has no sentence left to give
+/// after the previous seam took its first one.
+pub fn seams(chunks: &[String]) -> Vec<Option<String>> {
+    let counts: Vec<usize> = chunks.iter().map(|c| sentence_spans(c).len()).collect();
+    let mut head_taken = false;
+
+    (0..chunks.len().saturating_sub(1))
+        .map(|i| {
+            let needed = if head_taken { 2 } else { 1 };
+            if counts[i] < needed || counts[i + 1] == 0 {
+                head_taken = false;
+                return None;
+            }
+            head_taken = true;
+            let (_, tail) = split_tail(&chunks[i]);
+            let (head, _) = split_head(&chunks[i + 1]);
+            Some(format!("{}\n\n{}", tail, head))
+        })
+        .collect()
+}
+
+/// Reassemble polished chunks, replacing each stitched boundary with its rewritten seam
+pub fn stitch(chunks: &[String], seams: &[Option<String>]) -> String {
+    let mut output = String::new();
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        let stitched_before = i > 0 && seams.get(i - 1).is_some_and(Option::is_some);
+        let stitched_after = seams.get(i).is_some_and(Option::is_some);
+
+        let mut body = chunk.trim();
+        if stitched_before {
+            body = split_head(body).1;
+        }
+        if stitched_after {
+            body = split_tail(body).0;
+        }
+
+        if !body.is_empty() {
+            if !output.is_empty() {
+                output.push_str(if stitched_before { " " } else { "\n\n" });
+            }
+            output.push_str(body);
+        }
+
+        if let Some(Some(seam)) = seams.get(i) {
+            if !output.is_empty() {
+                output.push(' ');
+            }
+            output.push_str(seam.trim());
+        }
+    }
+
+    output
+}
+
+fn split_head(text: &str) -> (&str, &str) {
+    match sentence_spans(text).first() {
+        Some(&(start, end)) => (&text[start..end], text[end..].trim()),
+        None => ("", text.trim()),
+    }
+}
+
+fn split_tail(text: &str) -> (&str, &str) {
+    match sentence_spans(text).last() {
+        Some(&(start, end)) => (text[..start].trim(), &text[start..end]),
+        None => (text.trim(), ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentences(text: &str) -> Vec<&str> {
+        sentence_spans(text)
+            .into_iter()
+            .map(|(s, e)| &text[s..e])
+            .collect()
+    }
+
+    #[test]
+    fn test_short_text_is_single_chunk() {
+        assert_eq!(
+            split_into_chunks("  Hello there.  ", 100),
+            vec!["Hello there."]
+        );
+    }
+
+    #[test]
+    fn test_sentence_spans() {
+        assert_eq!(
+            sentences("One. Two!  Three? v1.2 is out\n\nNext para"),
+            vec!["One.", "Two!", "Three?", "v1.2 is out", "Next para"]
+        );
+    }
+
+    #[test]
+    fn test_chunks_prefer_paragraphs() {
+        let text = "First paragraph here.\n\nSecond paragraph here.\n\nThird one.";
+        let chunks = split_into_chunks(text, 45);
+        assert_eq!(
+            chunks,
+            vec![
+                "First paragraph here.\n\nSecond paragraph here.",
+                "Third one."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chunks_fall_back_to_sentences() {
+        let text = "Alpha beta gamma. Delta epsilon zeta. Eta theta iota.";
+        let chunks = split_into_chunks(text, 40);
+        assert_eq!(
+            chunks,
+            vec!["Alpha beta gamma. Delta epsilon zeta.", "Eta theta iota."]
+        );
+    }
+
+    #[test]
+    fn test_chunks_respect_max_len_and_keep_words() {
+        let text = "word ".repeat(500);
+        let chunks = split_into_chunks(&text, 64);
+        assert!(chunks.iter().all(|c| c.len() <= 64));
+        assert!(chunks.iter().all(|c| c.split(' ').all(|w| w == "word")));
+        assert_eq!(chunks.join(" ").split(' ').count(), 500);
+    }
+
+    #[test]
+    fn test_hard_split_multibyte() {
+        let text = "é".repeat(10);
+        let parts = hard_split(&text, 5);
+        assert!(parts.iter().all(|p| p.len() <= 5));
+        assert_eq!(parts.concat(), text);
+    }
+
+    #[test]
+    fn test_seams_and_stitch() {
+        let chunks = vec![
+            "Intro one. Intro two.".to_string(),
+            "Middle one. Middle two.".to_string(),
+            "End one.".to_string(),
+        ];
+
+        let seams = seams(&chunks);
+        assert_eq!(
+            seams,
+            vec![
+                Some("Intro two.\n\nMiddle one.".to_string()),
+                Some("Middle two.\n\nEnd one.".to_string()),
+            ]
+        );
+
+        let rewritten = vec![
+            Some("Intro two, leading into middle one.".to_string()),
+            Some("Middle two and end one.".to_string()),
+        ];
+        assert_eq!(
+            stitch(&chunks, &rewritten),
+            "Intro one. Intro two, leading into middle one. Middle two and end one."
+        );
+    }
+
+    #[test]
+    fn test_seams_skip_exhausted_chunk() {
+        let chunks = vec![
+            "A one. A two.".to_string(),
+            "B only.".to_string(),
+            "C one.".to_string(),
+        ];
+
+        let seams = seams(&chunks);
+        assert_eq!(seams, vec![Some("A two.\n\nB only.".to_string()), None]);
+
+        let rewritten = vec![Some("A two, then B.".to_string()), None];
+        assert_eq!(
+            stitch(&chunks, &rewritten),
+            "A one. A two, then B.\n\nC one."
+        );
+    }
+
+    #[test]
+    fn test_stitch_without_seams_joins_paragraphs() {
+        let chunks = vec!["One.".to_string(), "Two.".to_string()];
+        assert_eq!(stitch(&chunks, &[None]), "One.\n\nTwo.");
+    }
+}
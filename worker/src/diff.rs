@@ -0,0 +1,386 @@
+use crate::models::{DiffKind, DiffOp};
+
+/// Edit distance (in tokens) past which the middle of the texts is reported as a
+/// single replacement. Bounds the trace Myers keeps to roughly 16 MB.
+const MAX_DIFF_EDITS: usize = 2000;
+
+/// A word, a run of whitespace, or a single punctuation character
+#[derive(Debug, PartialEq)]
+struct Token<'a> {
+    text: &'a str,
+    /// Offset of the first char
+    start: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Edit {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Word-level diff from `old` to `new`. Changes separated only by whitespace are
+/// merged, so "um, so the" → "The" is one replacement rather than several.
+pub fn word_diff(old: &str, new: &str) -> Vec<DiffOp> {
+    let old_tokens = tokenize(old);
+    let new_tokens = tokenize(new);
+    let old_offsets = offsets(&old_tokens, old);
+    let new_offsets = offsets(&new_tokens, new);
+
+    let edits = edit_script(&old_tokens, &new_tokens);
+
+    // Token ranges of each run of changes, then merge across whitespace
+    let mut hunks: Vec<(usize, usize, usize, usize)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut open: Option<(usize, usize)> = None;
+    for edit in edits.iter().copied().chain(std::iter::once(Edit::Equal)) {
+        match edit {
+            Edit::Equal => {
+                if let Some((i0, j0)) = open.take() {
+                    match hunks.last_mut() {
+                        Some(last) if is_whitespace_between(&old_tokens, last.1, i0) => {
+                            last.1 = i;
+                            last.3 = j;
+                        }
+                        _ => hunks.push((i0, i, j0, j)),
+                    }
+                }
+                i += 1;
+                j += 1;
+            }
+            Edit::Delete => {
+                open.get_or_insert((i, j));
+                i += 1;
+            }
+            Edit::Insert => {
+                open.get_or_insert((i, j));
+                j += 1;
+            }
+        }
+    }
+
+    hunks
+        .into_iter()
+        .map(|(i0, i1, j0, j1)| DiffOp {
+            op: match (i0 == i1, j0 == j1) {
+                (true, _) => DiffKind::Insert,
+                (_, true) => DiffKind::Delete,
+                _ => DiffKind::Replace,
+            },
+            old_start: old_offsets[i0],
+            old_end: old_offsets[i1],
+            new_start: new_offsets[j0],
+            new_end: new_offsets[j1],
+        })
+        .collect()
+}
+
+fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().enumerate().peekable();
+
+    while let Some((start, (byte_start, c))) = chars.next() {
+        let mut byte_end = byte_start + c.len_utf8();
+        if c.is_whitespace() || is_word_char(c) {
+            let whitespace = c.is_whitespace();
+            while let Some(&(_, (i, next))) = chars.peek() {
+                let continues = if whitespace {
+                    next.is_whitespace()
+                } else {
+                    is_word_char(next) || (is_apostrophe(next) && word_follows(text, i))
+                };
+                if !continues {
+                    break;
+                }
+                byte_end = i + next.len_utf8();
+                chars.next();
+            }
+        }
+        tokens.push(Token {
+            text: &text[byte_start..byte_end],
+            start,
+        });
+    }
+
+    tokens
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+fn is_apostrophe(c: char) -> bool {
+    c == '\'' || c == '\u{2019}'
+}
+
+/// Whether the char after the one at byte `i` continues a word ("don't")
+fn word_follows(text: &str, i: usize) -> bool {
+    let mut rest = text[i..].chars().skip(1);
+    rest.next().is_some_and(is_word_char)
+}
+
+/// Char offset of each token's start, plus the total length
+fn offsets(tokens: &[Token], text: &str) -> Vec<usize> {
+    let mut offsets: Vec<usize> = tokens.iter().map(|t| t.start).collect();
+    offsets.push(text.chars().count());
+    offsets
+}
+
+fn is_whitespace_between(tokens: &[Token], from: usize, to: usize) -> bool {
+    tokens[from..to]
+        .iter()
+        .all(|t| t.text.chars().all(char::is_whitespace))
+}
+
+/// Shortest edit script over tokens: the common prefix and suffix are trimmed,
+/// then Myers' algorithm runs on the middle.
+fn edit_script(old: &[Token], new: &[Token]) -> Vec<Edit> {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(a, b)| a.text == b.text)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a.text == b.text)
+        .count();
+
+    let a: Vec<&str> = old[prefix..old.len() - suffix]
+        .iter()
+        .map(|t| t.text)
+        .collect();
+    let b: Vec<&str> = new[prefix..new.len() - suffix]
+        .iter()
+        .map(|t| t.text)
+        .collect();
+
+    let middle = myers(&a, &b).unwrap_or_else(|| {
+        let mut edits = vec![Edit::Delete; a.len()];
+        edits.extend(std::iter::repeat_n(Edit::Insert, b.len()));
+        edits
+    });
+
+    let mut edits = vec![Edit::Equal; prefix];
+    edits.extend(middle);
+    edits.extend(std::iter::repeat_n(Edit::Equal, suffix));
+    edits
+}
+
+/// Myers' O((N+M)D) diff. `None` once the edit distance exceeds `MAX_DIFF_EDITS`.
+fn myers(a: &[&str], b: &[&str]) -> Option<Vec<Edit>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (a.len() + b.len()).min(MAX_DIFF_EDITS) as isize;
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    // trace[d] holds v for diagonals -(d-1)..=(d-1) as it was before step d
+    let mut trace: Vec<Vec<u32>> = Vec::new();
+
+    for d in 0..=max {
+        let diagonals = if d == 0 {
+            &v[..0]
+        } else {
+            &v[(offset - d + 1) as usize..(offset + d) as usize]
+        };
+        trace.push(diagonals.iter().map(|&x| x as u32).collect());
+        for k in (-d..=d).step_by(2) {
+            let idx = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, n, m));
+            }
+        }
+    }
+
+    None
+}
+
+fn backtrack(trace: &[Vec<u32>], n: isize, m: isize) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        if d == 0 {
+            edits.extend(std::iter::repeat_n(Edit::Equal, x as usize));
+            break;
+        }
+
+        let at = |k: isize| v[(k + d - 1) as usize] as isize;
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        edits.push(if x == prev_x {
+            Edit::Insert
+        } else {
+            Edit::Delete
+        });
+        x = prev_x;
+        y = prev_y;
+    }
+
+    edits.reverse();
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rebuild `new` from `old` by applying the ops, checking offsets line up
+    fn apply(old: &str, new: &str, ops: &[DiffOp]) -> String {
+        let old: Vec<char> = old.chars().collect();
+        let new: Vec<char> = new.chars().collect();
+        let mut out = String::new();
+        let mut pos = 0;
+        for op in ops {
+            assert!(op.old_start >= pos);
+            assert_eq!(op.old_start - pos, op.new_start - out.chars().count());
+            out.extend(&old[pos..op.old_start]);
+            out.extend(&new[op.new_start..op.new_end]);
+            pos = op.old_end;
+        }
+        out.extend(&old[pos..]);
+        out
+    }
+
+    fn texts(old: &str, ops: &[DiffOp]) -> Vec<String> {
+        let old: Vec<char> = old.chars().collect();
+        ops.iter()
+            .map(|op| old[op.old_start..op.old_end].iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let tokens: Vec<&str> = tokenize("Don't go,  ok?").iter().map(|t| t.text).collect();
+        assert_eq!(tokens, vec!["Don't", " ", "go", ",", "  ", "ok", "?"]);
+    }
+
+    #[test]
+    fn test_identical_texts_have_no_ops() {
+        assert!(word_diff("Same text.", "Same text.").is_empty());
+        assert!(word_diff("", "").is_empty());
+    }
+
+    #[test]
+    fn test_insert_delete_replace() {
+        let ops = word_diff("the meeting moved", "The meeting has moved.");
+        assert_eq!(
+            ops,
+            vec![
+                DiffOp {
+                    op: DiffKind::Replace,
+                    old_start: 0,
+                    old_end: 3,
+                    new_start: 0,
+                    new_end: 3,
+                },
+                DiffOp {
+                    op: DiffKind::Insert,
+                    old_start: 12,
+                    old_end: 12,
+                    new_start: 12,
+                    new_end: 16,
+                },
+                DiffOp {
+                    op: DiffKind::Insert,
+                    old_start: 17,
+                    old_end: 17,
+                    new_start: 21,
+                    new_end: 22,
+                },
+            ]
+        );
+
+        let ops = word_diff("so um we ship", "so we ship");
+        assert_eq!(ops.len(), 1);
+        assert_eq!(ops[0].op, DiffKind::Delete);
+        assert_eq!(texts("so um we ship", &ops), vec!["um "]);
+    }
+
+    #[test]
+    fn test_merges_changes_separated_by_whitespace() {
+        let old = "um so like the plan is fine";
+        let new = "The plan is fine";
+        let ops = word_diff(old, new);
+        assert_eq!(ops.len(), 1);
+        assert_eq!(ops[0].op, DiffKind::Replace);
+        assert_eq!(texts(old, &ops), vec!["um so like the"]);
+        assert_eq!(apply(old, new, &ops), new);
+    }
+
+    #[test]
+    fn test_offsets_count_chars_not_bytes() {
+        let old = "café über alles";
+        let new = "Café über alles!";
+        let ops = word_diff(old, new);
+        assert_eq!(ops[0].old_end, 4);
+        assert_eq!(ops[1].old_start, 15);
+        assert_eq!(apply(old, new, &ops), new);
+    }
+
+    #[test]
+    fn test_ops_rebuild_new_text() {
+        let cases = [
+            ("", "Hello."),
+            ("hello", ""),
+            ("a b c d e", "e d c b a"),
+            (
+                "we should uh maybe ship it friday",
+                "We should ship it on Friday.",
+            ),
+            (
+                "ok so first thing\n\nsecond thing is the budget",
+                "First, the budget.\n\nSecond: timing.",
+            ),
+        ];
+        for (old, new) in cases {
+            let ops = word_diff(old, new);
+            assert_eq!(apply(old, new, &ops), new, "{:?} -> {:?}", old, new);
+        }
+    }
+
+    #[test]
+    fn test_myers_is_minimal() {
+        let a = ["a", "b", "c", "a", "b", "b", "a"];
+        let b = ["c", "b", "a", "b", "a", "c"];
+        let edits = myers(&a, &b).unwrap();
+        assert_eq!(edits.iter().filter(|e| **e != Edit::Equal).count(), 5);
+    }
+
+    #[test]
+    fn test_large_rewrite_falls_back_to_one_replacement() {
+        let old = (0..MAX_DIFF_EDITS)
+            .map(|i| format!("a{} ", i))
+            .collect::<String>();
+        let new = (0..MAX_DIFF_EDITS)
+            .map(|i| format!("b{} ", i))
+            .collect::<String>();
+        let ops = word_diff(&old, &new);
+        assert_eq!(apply(&old, &new, &ops), new);
+        assert_eq!(ops.len(), 1);
+    }
+}
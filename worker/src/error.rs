@@ -0,0 +1,272 @@
+use crate::models::ApiResponse;
+use crate::provider::{ProviderError, SpentError};
+use serde::Serialize;
+use worker::{Headers, Response};
+
+/// Every failure a handler can report to a client. Each variant maps to one
+/// machine-readable `code` and HTTP status, so clients never parse messages.
+#[derive(Debug)]
+pub enum ApiError {
+    /// Missing, invalid or expired credentials
+    Unauthorized(String),
+    /// Signed in, but the account may not do this (e.g. its email is unverified)
+    Forbidden(String),
+    /// The request itself is malformed or out of bounds
+    Validation {
+        message: String,
+        details: Option<serde_json::Value>,
+    },
+    NotFound(String),
+    Conflict(String),
+    /// Short-term burst limit; retry after the window resets
+    RateLimited {
+        retry_after: u32,
+    },
+    /// Longer-term allowance used up
+    QuotaExceeded {
+        message: String,
+        retry_after: Option<u32>,
+    },
+    /// The AI provider failed. Never carries the upstream body, which may echo keys.
+    Upstream {
+        message: String,
+        details: Option<serde_json::Value>,
+    },
+    /// Anything else; the message is logged but not sent to the client
+    Internal(String),
+}
+
+/// The `error` object in a failed `ApiResponse`
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+impl ApiError {
+    pub fn validation(message: impl Into<String>) -> Self {
+        Self::Validation {
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn upstream(message: impl Into<String>) -> Self {
+        Self::Upstream {
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::Validation { .. } => "validation_error",
+            Self::NotFound(_) => "not_found",
+            Self::Conflict(_) => "conflict",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::QuotaExceeded { .. } => "quota_exceeded",
+            Self::Upstream { .. } => "upstream_error",
+            Self::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Unauthorized(_) => 401,
+            Self::Forbidden(_) => 403,
+            Self::Validation { .. } => 400,
+            Self::NotFound(_) => 404,
+            Self::Conflict(_) => 409,
+            Self::RateLimited { .. } | Self::QuotaExceeded { .. } => 429,
+            Self::Upstream { .. } => 502,
+            Self::Internal(_) => 500,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        let (message, retry_after, details) = match self {
+            Self::Unauthorized(m) | Self::Forbidden(m) | Self::NotFound(m) | Self::Conflict(m) => {
+                (m.clone(), None, None)
+            }
+            Self::Validation { message, details } | Self::Upstream { message, details } => {
+                (message.clone(), None, details.clone())
+            }
+            Self::RateLimited { retry_after } => {
+                ("Rate limit exceeded".to_string(), Some(*retry_after), None)
+            }
+            Self::QuotaExceeded {
+                message,
+                retry_after,
+            } => (message.clone(), *retry_after, None),
+            Self::Internal(_) => ("Internal server error".to_string(), None, None),
+        };
+
+        ErrorBody {
+            code: self.code(),
+            message,
+            retry_after,
+            details,
+        }
+    }
+
+    /// The single conversion from an error into the JSON response clients see
+    pub fn into_response(self) -> worker::Result<Response> {
+        if let Self::Internal(message) = &self {
+            worker::console_error!("Internal error: {}", message);
+        }
+
+        let body = self.body();
+        let headers = Headers::new();
+        headers.set("Content-Type", "application/json")?;
+        if let Some(retry_after) = body.retry_after {
+            headers.set("Retry-After", &retry_after.to_string())?;
+        }
+
+        Ok(Response::from_json(&ApiResponse::<()>::error(body))?
+            .with_status(self.status())
+            .with_headers(headers))
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Internal(message) => write!(f, "{}: {}", self.code(), message),
+            _ => write!(f, "{}: {}", self.code(), self.body().message),
+        }
+    }
+}
+
+impl From<worker::Error> for ApiError {
+    fn from(e: worker::Error) -> Self {
+        Self::Internal(e.to_string())
+    }
+}
+
+impl From<SpentError> for ApiError {
+    fn from(e: SpentError) -> Self {
+        e.error.into()
+    }
+}
+
+/// Provider failures reach clients as a status summary; the upstream body is
+/// only logged, since it can echo request details back.
+impl From<ProviderError> for ApiError {
+    fn from(e: ProviderError) -> Self {
+        match e {
+            ProviderError::Config(message) => Self::Internal(message),
+            ProviderError::Status {
+                provider, status, ..
+            } => {
+                let message = match status {
+                    401 | 403 => "AI provider rejected the API key",
+                    429 => "AI provider is rate limiting requests",
+                    _ => "AI provider request failed",
+                };
+                Self::Upstream {
+                    message: message.to_string(),
+                    details: Some(serde_json::json!({
+                        "provider": provider,
+                        "upstream_status": status,
+                    })),
+                }
+            }
+            ProviderError::Transport(_) => Self::upstream("AI provider request failed"),
+            ProviderError::Timeout(_) => Self::upstream("AI provider timed out"),
+            ProviderError::InvalidResponse(_) => {
+                Self::upstream("AI provider returned an invalid response")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes_and_statuses() {
+        let cases = [
+            (ApiError::Unauthorized("x".into()), "unauthorized", 401),
+            (ApiError::Forbidden("x".into()), "forbidden", 403),
+            (ApiError::validation("x"), "validation_error", 400),
+            (ApiError::NotFound("x".into()), "not_found", 404),
+            (ApiError::Conflict("x".into()), "conflict", 409),
+            (
+                ApiError::RateLimited { retry_after: 10 },
+                "rate_limited",
+                429,
+            ),
+            (
+                ApiError::QuotaExceeded {
+                    message: "x".into(),
+                    retry_after: None,
+                },
+                "quota_exceeded",
+                429,
+            ),
+            (ApiError::upstream("x"), "upstream_error", 502),
+            (ApiError::Internal("x".into()), "internal_error", 500),
+        ];
+
+        for (error, code, status) in cases {
+            assert_eq!(error.code(), code);
+            assert_eq!(error.status(), status);
+        }
+    }
+
+    #[test]
+    fn test_rate_limited_body_has_retry_after() {
+        let json = serde_json::to_value(ApiError::RateLimited { retry_after: 10 }.body()).unwrap();
+        assert_eq!(json["code"], "rate_limited");
+        assert_eq!(json["retry_after"], 10);
+        assert!(json.get("details").is_none());
+    }
+
+    #[test]
+    fn test_internal_message_is_hidden() {
+        let body = ApiError::Internal("D1_ERROR: no such table: users".into()).body();
+        assert_eq!(body.message, "Internal server error");
+    }
+
+    #[test]
+    fn test_provider_status_does_not_echo_body() {
+        let error = ApiError::from(ProviderError::Status {
+            provider: "OpenAI",
+            status: 401,
+            body: r#"{"error":{"message":"Incorrect API key provided: sk-abc123"}}"#.into(),
+        });
+
+        let json = serde_json::to_string(&error.body()).unwrap();
+        assert_eq!(error.status(), 502);
+        assert!(!json.contains("sk-abc123"));
+        assert!(json.contains(r#""upstream_status":401"#));
+        assert!(json.contains("rejected the API key"));
+    }
+
+    #[test]
+    fn test_provider_config_error_is_internal() {
+        let error = ApiError::from(ProviderError::Config("no key".into()));
+        assert_eq!(error.code(), "internal_error");
+        assert_eq!(error.body().message, "Internal server error");
+    }
+
+    #[test]
+    fn test_validation_details_serialized() {
+        let error = ApiError::Validation {
+            message: "Text too long".into(),
+            details: Some(serde_json::json!({ "max_length": 8000 })),
+        };
+        let json = serde_json::to_string(&error.body()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"code":"validation_error","message":"Text too long","details":{"max_length":8000}}"#
+        );
+    }
+}
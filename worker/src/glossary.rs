@@ -0,0 +1,630 @@
+use crate::auth::extract_and_verify_token;
+use crate::error::ApiError;
+use crate::models::{ApiResponse, GlossaryEntry, GlossaryInput};
+use worker::*;
+
+const MAX_TERM_LENGTH: usize = 100;
+const MAX_VARIANTS: usize = 10;
+const MAX_GLOSSARY_ENTRIES: i64 = 200;
+
+const GLOSSARY_COLUMNS: &str = "id, term, variants, replacement, created_at, updated_at";
+
+pub async fn list_entries(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
+        Ok(id) => id,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let entries = load_glossary(&ctx.env.d1("DB")?, &user_id).await?;
+    Response::from_json(&ApiResponse::success(entries))
+}
+
+pub async fn create_entry(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
+        Ok(id) => id,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let body: GlossaryInput = match req.json().await {
+        Ok(b) => b,
+        Err(_) => {
+            return ApiError::validation("Invalid request body").into_response();
+        }
+    };
+
+    let input = match normalize_input(body) {
+        Ok(input) => input,
+        Err(e) => return ApiError::validation(e).into_response(),
+    };
+
+    let db = ctx.env.d1("DB")?;
+
+    let count = db
+        .prepare("SELECT COUNT(*) AS count FROM glossary_terms WHERE user_id = ?1")
+        .bind(&[user_id.clone().into()])?
+        .first::<f64>(Some("count"))
+        .await?
+        .unwrap_or(0.0) as i64;
+
+    if count >= MAX_GLOSSARY_ENTRIES {
+        return ApiError::validation(format!(
+            "Glossary limit reached ({} terms)",
+            MAX_GLOSSARY_ENTRIES
+        ))
+        .into_response();
+    }
+
+    if term_exists(&db, &user_id, &input.term, "").await? {
+        return ApiError::Conflict("Glossary term already exists".to_string()).into_response();
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let entry = GlossaryEntry {
+        id: uuid::Uuid::new_v4().to_string(),
+        term: input.term,
+        variants: input.variants,
+        replacement: input.replacement,
+        created_at: now,
+        updated_at: now,
+    };
+
+    db.prepare("INSERT INTO glossary_terms (id, user_id, term, variants, replacement, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")
+        .bind(&[
+            entry.id.clone().into(),
+            user_id.into(),
+            entry.term.clone().into(),
+            serde_json::to_string(&entry.variants)?.into(),
+            optional(&entry.replacement),
+            (now as f64).into(),
+            (now as f64).into(),
+        ])?
+        .run()
+        .await?;
+
+    Response::from_json(&ApiResponse::success(entry)).map(|r| r.with_status(201))
+}
+
+pub async fn update_entry(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
+        Ok(id) => id,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let entry_id = ctx.param("id").cloned().unwrap_or_default();
+
+    let body: GlossaryInput = match req.json().await {
+        Ok(b) => b,
+        Err(_) => {
+            return ApiError::validation("Invalid request body").into_response();
+        }
+    };
+
+    let input = match normalize_input(body) {
+        Ok(input) => input,
+        Err(e) => return ApiError::validation(e).into_response(),
+    };
+
+    let db = ctx.env.d1("DB")?;
+
+    if term_exists(&db, &user_id, &input.term, &entry_id).await? {
+        return ApiError::Conflict("Glossary term already exists".to_string()).into_response();
+    }
+
+    let now = chrono::Utc::now().timestamp() as f64;
+
+    db.prepare("UPDATE glossary_terms SET term = ?1, variants = ?2, replacement = ?3, updated_at = ?4 WHERE id = ?5 AND user_id = ?6")
+        .bind(&[
+            input.term.into(),
+            serde_json::to_string(&input.variants)?.into(),
+            optional(&input.replacement),
+            now.into(),
+            entry_id.clone().into(),
+            user_id.clone().into(),
+        ])?
+        .run()
+        .await?;
+
+    let row = db
+        .prepare(format!(
+            "SELECT {} FROM glossary_terms WHERE id = ?1 AND user_id = ?2",
+            GLOSSARY_COLUMNS
+        ))
+        .bind(&[entry_id.into(), user_id.into()])?
+        .first::<serde_json::Value>(None)
+        .await?;
+
+    match row {
+        Some(row) => Response::from_json(&ApiResponse::success(entry_from_row(&row))),
+        None => ApiError::NotFound("Glossary term not found".to_string()).into_response(),
+    }
+}
+
+pub async fn delete_entry(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
+        Ok(id) => id,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let entry_id = ctx.param("id").cloned().unwrap_or_default();
+    let db = ctx.env.d1("DB")?;
+
+    let result = db
+        .prepare("DELETE FROM glossary_terms WHERE id = ?1 AND user_id = ?2")
+        .bind(&[entry_id.into(), user_id.into()])?
+        .run()
+        .await?;
+
+    let deleted = result
+        .meta()?
+        .and_then(|m| m.changes)
+        .is_some_and(|changes| changes > 0);
+
+    if !deleted {
+        return ApiError::NotFound("Glossary term not found".to_string()).into_response();
+    }
+
+    Response::from_json(&ApiResponse::success(()))
+}
+
+/// All of the user's glossary entries, oldest first
+pub async fn load_glossary(db: &D1Database, user_id: &str) -> Result<Vec<GlossaryEntry>> {
+    let rows = db
+        .prepare(format!(
+            "SELECT {} FROM glossary_terms WHERE user_id = ?1 ORDER BY created_at",
+            GLOSSARY_COLUMNS
+        ))
+        .bind(&[user_id.into()])?
+        .all()
+        .await?
+        .results::<serde_json::Value>()?;
+
+    Ok(rows.iter().map(entry_from_row).collect())
+}
+
+/// Whether another of the user's entries already has this term (ignoring case)
+async fn term_exists(db: &D1Database, user_id: &str, term: &str, except_id: &str) -> Result<bool> {
+    let row = db
+        .prepare("SELECT id FROM glossary_terms WHERE user_id = ?1 AND lower(term) = lower(?2) AND id != ?3")
+        .bind(&[user_id.into(), term.into(), except_id.into()])?
+        .first::<serde_json::Value>(None)
+        .await?;
+
+    Ok(row.is_some())
+}
+
+fn optional(value: &Option<String>) -> wasm_bindgen::JsValue {
+    match value {
+        Some(value) => value.as_str().into(),
+        None => wasm_bindgen::JsValue::NULL,
+    }
+}
+
+fn entry_from_row(row: &serde_json::Value) -> GlossaryEntry {
+    GlossaryEntry {
+        id: row["id"].as_str().unwrap_or("").to_string(),
+        term: row["term"].as_str().unwrap_or("").to_string(),
+        variants: row["variants"]
+            .as_str()
+            .and_then(|v| serde_json::from_str(v).ok())
+            .unwrap_or_default(),
+        replacement: row["replacement"].as_str().map(str::to_string),
+        created_at: row["created_at"].as_f64().unwrap_or(0.0) as i64,
+        updated_at: row["updated_at"].as_f64().unwrap_or(0.0) as i64,
+    }
+}
+
+/// Trim the input, drop blank or duplicate variants, and check the limits
+fn normalize_input(input: GlossaryInput) -> std::result::Result<GlossaryInput, String> {
+    let term = input.term.trim().to_string();
+    if term.is_empty() {
+        return Err("Glossary term cannot be empty".to_string());
+    }
+
+    let replacement = input
+        .replacement
+        .map(|r| r.trim().to_string())
+        .filter(|r| !r.is_empty() && *r != term);
+
+    let mut variants: Vec<String> = Vec::new();
+    for variant in input.variants {
+        let variant = variant.trim().to_string();
+        if !variant.is_empty()
+            && !variant.eq_ignore_ascii_case(&term)
+            && !variants.iter().any(|v| v.eq_ignore_ascii_case(&variant))
+        {
+            variants.push(variant);
+        }
+    }
+    if variants.len() > MAX_VARIANTS {
+        return Err(format!("At most {} variants allowed", MAX_VARIANTS));
+    }
+
+    let too_long = std::iter::once(&term)
+        .chain(&replacement)
+        .chain(&variants)
+        .any(|t| t.chars().count() > MAX_TERM_LENGTH);
+    if too_long {
+        return Err(format!(
+            "Glossary terms must be at most {} characters",
+            MAX_TERM_LENGTH
+        ));
+    }
+
+    Ok(GlossaryInput {
+        term,
+        variants,
+        replacement,
+    })
+}
+
+/// A user's glossary, compiled for rewriting text
+#[derive(Default)]
+pub struct Glossary {
+    /// (what to look for, what to write), longest needle first
+    rules: Vec<(String, String)>,
+    spellings: Vec<String>,
+    /// Streamed text `apply_partial` hasn't rewritten yet, and the character before it
+    held: String,
+    held_prev: Option<char>,
+}
+
+impl Glossary {
+    pub fn new(entries: &[GlossaryEntry]) -> Self {
+        let mut rules = Vec::new();
+        let mut spellings = Vec::new();
+        for entry in entries {
+            let spelling = entry.spelling().to_string();
+            for needle in std::iter::once(&entry.term)
+                .chain(&entry.replacement)
+                .chain(&entry.variants)
+            {
+                rules.push((needle.clone(), spelling.clone()));
+            }
+            spellings.push(spelling);
+        }
+        rules.sort_by_key(|(needle, _)| std::cmp::Reverse(needle.chars().count()));
+
+        Self {
+            rules,
+            spellings,
+            ..Self::default()
+        }
+    }
+
+    /// Rewrite every whole-word occurrence of a term, its replacement or a misheard
+    /// variant (in any casing, with any whitespace between words) to the glossary
+    /// spelling. A single left-to-right pass, so replacements never cascade.
+    pub fn apply(&self, text: &str) -> String {
+        if self.rules.is_empty() {
+            return text.to_string();
+        }
+        self.rewrite(text, None, true).0
+    }
+
+    /// `apply` for text arriving in pieces. Anything that could still turn into
+    /// a match once more text arrives is held back until the next call or `flush`.
+    pub fn apply_partial(&mut self, text: &str) -> String {
+        if self.rules.is_empty() {
+            return text.to_string();
+        }
+
+        self.held.push_str(text);
+        let (out, used, prev) = self.rewrite(&self.held, self.held_prev, false);
+        self.held.drain(..used);
+        self.held_prev = prev;
+        out
+    }
+
+    /// Whatever `apply_partial` is still holding back, rewritten
+    pub fn flush(&mut self) -> String {
+        let held = std::mem::take(&mut self.held);
+        let prev = self.held_prev.take();
+        let (out, _, _) = self.rewrite(&held, prev, true);
+        out
+    }
+
+    /// Rewrite `text` from the start, returning the output, how many bytes of
+    /// `text` it covers and the last character consumed. Unless `complete`, stops
+    /// where a needle runs past the end of the text.
+    fn rewrite(
+        &self,
+        text: &str,
+        mut prev: Option<char>,
+        complete: bool,
+    ) -> (String, usize, Option<char>) {
+        let mut out = String::with_capacity(text.len());
+        let mut pos = 0;
+        while pos < text.len() {
+            let mut matched = None;
+            for (needle, spelling) in &self.rules {
+                match match_at(text, pos, needle, prev, complete) {
+                    Match::Miss => continue,
+                    Match::Incomplete => return (out, pos, prev),
+                    Match::End(end) => {
+                        matched = Some((end, spelling));
+                        break;
+                    }
+                }
+            }
+            match matched {
+                Some((end, spelling)) => {
+                    out.push_str(spelling);
+                    prev = text[..end].chars().next_back();
+                    pos = end;
+                }
+                None => {
+                    let c = text[pos..].chars().next().unwrap_or_default();
+                    out.push(c);
+                    prev = Some(c);
+                    pos += c.len_utf8();
+                }
+            }
+        }
+
+        (out, pos, prev)
+    }
+
+    /// Every glossary spelling, in entry order
+    pub fn spellings(&self) -> &[String] {
+        &self.spellings
+    }
+
+    /// Glossary spellings that occur in `text`
+    pub fn terms_in(&self, text: &str) -> Vec<String> {
+        self.spellings
+            .iter()
+            .filter(|spelling| text.contains(spelling.as_str()))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Prompt text asking the model to keep `terms` exactly as written
+pub fn prompt_section(terms: &[String]) -> Option<String> {
+    if terms.is_empty() {
+        return None;
+    }
+    let list = terms
+        .iter()
+        .map(|t| format!("\"{}\"", t))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Some(format!(
+        "These names and terms are already spelled correctly. Keep them exactly as written; do not correct, translate or rephrase them: {}.",
+        list
+    ))
+}
+
+/// Which of `terms` no longer appear in the polished text
+pub fn missing_terms(terms: &[String], polished: &str) -> Vec<String> {
+    terms
+        .iter()
+        .filter(|term| !polished.contains(term.as_str()))
+        .cloned()
+        .collect()
+}
+
+enum Match {
+    Miss,
+    /// The text ended before the needle could match or fail
+    Incomplete,
+    /// Matched up to this byte
+    End(usize),
+}
+
+/// Whether `needle` matches at byte `start` of `text`, ignoring case and treating
+/// any run of whitespace as equal. Alphanumeric edges must sit on word boundaries.
+/// Running out of text is a miss when `complete`, and `Incomplete` otherwise.
+fn match_at(text: &str, start: usize, needle: &str, prev: Option<char>, complete: bool) -> Match {
+    let Some(first) = needle.chars().next() else {
+        return Match::Miss;
+    };
+    if first.is_alphanumeric() && prev.is_some_and(char::is_alphanumeric) {
+        return Match::Miss;
+    }
+
+    let ran_out = if complete {
+        Match::Miss
+    } else {
+        Match::Incomplete
+    };
+    let mut haystack = text[start..].char_indices().peekable();
+    let mut needle_chars = needle.chars().peekable();
+    let mut end = 0;
+    let mut last = first;
+
+    while let Some(n) = needle_chars.next() {
+        last = n;
+        let next = haystack.next();
+        let Some((i, c)) = next else {
+            return ran_out;
+        };
+        if n.is_whitespace() {
+            while needle_chars.peek().is_some_and(|c| c.is_whitespace()) {
+                needle_chars.next();
+            }
+            if !c.is_whitespace() {
+                return Match::Miss;
+            }
+            end = i + c.len_utf8();
+            while let Some(&(i, c)) = haystack.peek() {
+                if !c.is_whitespace() {
+                    break;
+                }
+                end = i + c.len_utf8();
+                haystack.next();
+            }
+        } else if c.to_lowercase().eq(n.to_lowercase()) {
+            end = i + c.len_utf8();
+        } else {
+            return Match::Miss;
+        }
+    }
+
+    if last.is_alphanumeric() {
+        match haystack.peek() {
+            Some(&(_, c)) if c.is_alphanumeric() => return Match::Miss,
+            None if !complete => return ran_out,
+            _ => {}
+        }
+    }
+    Match::End(start + end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(term: &str, variants: &[&str], replacement: Option<&str>) -> GlossaryEntry {
+        GlossaryEntry {
+            id: term.to_string(),
+            term: term.to_string(),
+            variants: variants.iter().map(|v| v.to_string()).collect(),
+            replacement: replacement.map(str::to_string),
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    fn input(term: &str, variants: &[&str], replacement: Option<&str>) -> GlossaryInput {
+        GlossaryInput {
+            term: term.to_string(),
+            variants: variants.iter().map(|v| v.to_string()).collect(),
+            replacement: replacement.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_apply_variants() {
+        let glossary = Glossary::new(&[
+            entry("mumble.fish", &["mumble fish", "mumble dot fish"], None),
+            entry("Siobhan", &["shivon", "she von"], None),
+        ]);
+
+        assert_eq!(
+            glossary.apply("Ask Shivon about the Mumble  Fish launch"),
+            "Ask Siobhan about the mumble.fish launch"
+        );
+        assert_eq!(glossary.apply("siobhan"), "Siobhan");
+    }
+
+    #[test]
+    fn test_apply_respects_word_boundaries() {
+        let glossary = Glossary::new(&[entry("Ada", &["ada"], None)]);
+        assert_eq!(
+            glossary.apply("ask ada, not adam or canada"),
+            "ask Ada, not adam or canada"
+        );
+    }
+
+    #[test]
+    fn test_apply_replacement() {
+        let glossary = Glossary::new(&[entry("k8s", &["kates"], Some("Kubernetes"))]);
+        assert_eq!(
+            glossary.apply("deploy to K8s, then kates again"),
+            "deploy to Kubernetes, then Kubernetes again"
+        );
+    }
+
+    #[test]
+    fn test_apply_does_not_cascade() {
+        // "Jon" must not be re-matched inside the replacement "Jon Doe"
+        let glossary = Glossary::new(&[
+            entry("Jon Doe", &["john doe"], None),
+            entry("Jon", &["john"], None),
+        ]);
+        assert_eq!(glossary.apply("john doe and john"), "Jon Doe and Jon");
+    }
+
+    #[test]
+    fn test_apply_partial_matches_apply() {
+        let mut glossary = Glossary::new(&[
+            entry("mumble.fish", &["mumble fish"], None),
+            entry("Ada", &["ada"], None),
+        ]);
+        let text = "Ask ada about the Mumble  fish launch, not adam.";
+        let expected = glossary.apply(text);
+
+        let mut streamed = glossary.apply_partial("Ask a");
+        streamed.push_str(&glossary.apply_partial("da about the Mum"));
+        // "Mum" could still become "Mumble fish"
+        assert_eq!(streamed, "Ask Ada about the ");
+        for piece in ["ble ", " fish launch, not ad", "am."] {
+            streamed.push_str(&glossary.apply_partial(piece));
+        }
+        streamed.push_str(&glossary.flush());
+        assert_eq!(streamed, expected);
+    }
+
+    #[test]
+    fn test_terms_in_and_missing() {
+        let glossary =
+            Glossary::new(&[entry("mumble.fish", &[], None), entry("Siobhan", &[], None)]);
+        let terms = glossary.terms_in("Siobhan said so");
+        assert_eq!(terms, vec!["Siobhan"]);
+        assert_eq!(missing_terms(&terms, "She said so."), vec!["Siobhan"]);
+        assert!(missing_terms(&terms, "Siobhan said so.").is_empty());
+    }
+
+    #[test]
+    fn test_prompt_section() {
+        assert_eq!(prompt_section(&[]), None);
+        let prompt = prompt_section(&["Siobhan".to_string(), "mumble.fish".to_string()]).unwrap();
+        assert!(prompt.contains("\"Siobhan\", \"mumble.fish\""));
+    }
+
+    #[test]
+    fn test_normalize_input() {
+        let input = normalize_input(input(
+            " Siobhan ",
+            &["shivon", " Shivon ", "", "siobhan"],
+            Some(" "),
+        ))
+        .unwrap();
+        assert_eq!(input.term, "Siobhan");
+        assert_eq!(input.variants, vec!["shivon"]);
+        assert_eq!(input.replacement, None);
+    }
+
+    #[test]
+    fn test_normalize_input_limits() {
+        assert!(normalize_input(input(" ", &[], None)).is_err());
+
+        let long = "x".repeat(MAX_TERM_LENGTH + 1);
+        assert!(normalize_input(input(&long, &[], None)).is_err());
+        assert!(normalize_input(input("ok", &[&long], None)).is_err());
+
+        let variants: Vec<String> = (0..=MAX_VARIANTS).map(|i| format!("v{}", i)).collect();
+        let variants: Vec<&str> = variants.iter().map(String::as_str).collect();
+        assert!(normalize_input(input("ok", &variants, None)).is_err());
+    }
+
+    #[test]
+    fn test_entry_from_row() {
+        let row = serde_json::json!({
+            "id": "g1",
+            "term": "k8s",
+            "variants": r#"["kates"]"#,
+            "replacement": "Kubernetes",
+            "created_at": 1700000000.0,
+            "updated_at": 1700000100.0
+        });
+
+        let entry = entry_from_row(&row);
+        assert_eq!(entry.variants, vec!["kates"]);
+        assert_eq!(entry.spelling(), "Kubernetes");
+        assert_eq!(entry.updated_at, 1700000100);
+
+        let row =
+            serde_json::json!({ "id": "g2", "term": "Ada", "variants": "[]", "replacement": null });
+        assert_eq!(entry_from_row(&row).spelling(), "Ada");
+    }
+}
@@ -0,0 +1,489 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use sha2::Sha256;
+use worker::Env;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const ISSUER: &str = "https://mumble.fish";
+/// Audience of access tokens
+pub const API_AUDIENCE: &str = "mumble.fish/api";
+
+const ALGORITHM: &str = "HS256";
+/// `kid` of the key built from `JWT_SECRET` when `JWT_SIGNING_KEYS` isn't set
+const DEFAULT_KID: &str = "default";
+/// Tolerated clock difference for `nbf`
+const CLOCK_SKEW_SECS: i64 = 60;
+
+#[derive(Clone, Debug, PartialEq)]
+struct SigningKey {
+    kid: String,
+    secret: String,
+}
+
+/// The keys tokens are signed and verified with. The first key signs; the rest
+/// only verify, so a secret can be rotated without signing anyone out.
+#[derive(Debug, PartialEq)]
+pub struct Keys {
+    keys: Vec<SigningKey>,
+    /// Verifies tokens in the pre-JWT `claims.signature` format until they expire
+    legacy: Option<String>,
+}
+
+impl Keys {
+    /// `JWT_SIGNING_KEYS` as `kid:secret` pairs separated by commas, falling back
+    /// to `JWT_SECRET` as a single key
+    pub fn from_env(env: &Env) -> std::result::Result<Self, String> {
+        let secret = |name: &str| {
+            env.secret(name)
+                .ok()
+                .map(|s| s.to_string())
+                .filter(|s| !s.trim().is_empty())
+        };
+        Self::from_config(secret("JWT_SIGNING_KEYS").as_deref(), secret("JWT_SECRET"))
+    }
+
+    /// Keys from a `JWT_SIGNING_KEYS` value and the legacy `JWT_SECRET`
+    pub fn from_config(
+        configured: Option<&str>,
+        legacy: Option<String>,
+    ) -> std::result::Result<Self, String> {
+        let mut keys: Vec<SigningKey> = Vec::new();
+        match configured {
+            Some(configured) => {
+                for entry in configured
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|e| !e.is_empty())
+                {
+                    let (kid, secret) = entry
+                        .split_once(':')
+                        .map(|(kid, secret)| (kid.trim(), secret.trim()))
+                        .filter(|(kid, secret)| !kid.is_empty() && !secret.is_empty())
+                        .ok_or("JWT_SIGNING_KEYS entries must be kid:secret")?;
+                    if keys.iter().any(|k| k.kid == kid) {
+                        return Err(format!("Duplicate signing key id \"{}\"", kid));
+                    }
+                    keys.push(SigningKey {
+                        kid: kid.to_string(),
+                        secret: secret.to_string(),
+                    });
+                }
+            }
+            None => {
+                if let Some(secret) = &legacy {
+                    keys.push(SigningKey {
+                        kid: DEFAULT_KID.to_string(),
+                        secret: secret.clone(),
+                    });
+                }
+            }
+        }
+        if keys.is_empty() {
+            return Err("JWT_SECRET not configured".to_string());
+        }
+        Ok(Self { keys, legacy })
+    }
+
+    /// Sign `claims` with the active key as `header.payload.signature`
+    pub fn sign<T: Serialize>(&self, claims: &T) -> serde_json::Result<String> {
+        let key = &self.keys[0];
+        let header = serde_json::json!({ "alg": ALGORITHM, "typ": "JWT", "kid": key.kid });
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?),
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?)
+        );
+        let signature =
+            URL_SAFE_NO_PAD.encode(mac(&key.secret, &signing_input).finalize().into_bytes());
+        Ok(format!("{}.{}", signing_input, signature))
+    }
+
+    /// Check a token's header, signature and registered claims. `iss` and `aud`
+    /// must match; `exp` is required and `nbf` honoured. Legacy tokens carry no
+    /// issuer or audience and are accepted as access tokens only.
+    pub fn verify<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        audience: &str,
+        now: i64,
+    ) -> std::result::Result<T, String> {
+        let parts: Vec<&str> = token.split('.').collect();
+        let claims = match parts[..] {
+            [header, payload, signature] => {
+                self.verify_jwt(header, payload, signature)?;
+                let claims = decode_json(payload).map_err(|_| "Invalid token claims")?;
+                check_registered_claims(&claims, audience, now)?;
+                claims
+            }
+            [payload, signature] if audience == API_AUDIENCE => {
+                let secret = self.legacy.as_deref().ok_or("Invalid token format")?;
+                verify_signature(secret, payload, signature)?;
+                let claims = decode_json(payload).map_err(|_| "Invalid token claims")?;
+                // Legacy tokens were valid through their expiry second
+                let exp = claims["exp"].as_i64().ok_or("Invalid token claims")?;
+                if now > exp {
+                    return Err("Token expired".to_string());
+                }
+                claims
+            }
+            _ => return Err("Invalid token format".to_string()),
+        };
+
+        serde_json::from_value(claims).map_err(|_| "Invalid token claims".to_string())
+    }
+
+    fn verify_jwt(
+        &self,
+        header: &str,
+        payload: &str,
+        signature: &str,
+    ) -> std::result::Result<(), String> {
+        let parsed = decode_json(header).map_err(|_| "Invalid token header")?;
+        // Only ever HS256: never `none`, and never whatever algorithm the token names
+        if parsed["alg"].as_str() != Some(ALGORITHM) {
+            return Err("Unsupported token algorithm".to_string());
+        }
+        if parsed
+            .get("typ")
+            .is_some_and(|typ| typ.as_str() != Some("JWT"))
+        {
+            return Err("Invalid token type".to_string());
+        }
+        let kid = parsed["kid"].as_str().ok_or("Missing token key id")?;
+        let key = self
+            .keys
+            .iter()
+            .find(|k| k.kid == kid)
+            .ok_or("Unknown token key id")?;
+
+        verify_signature(&key.secret, &format!("{}.{}", header, payload), signature)
+    }
+}
+
+fn mac(secret: &str, input: &str) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(input.as_bytes());
+    mac
+}
+
+fn verify_signature(
+    secret: &str,
+    signing_input: &str,
+    signature: &str,
+) -> std::result::Result<(), String> {
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| "Invalid signature encoding")?;
+    mac(secret, signing_input)
+        .verify_slice(&signature)
+        .map_err(|_| "Invalid token signature".to_string())
+}
+
+fn decode_json(segment: &str) -> std::result::Result<serde_json::Value, ()> {
+    let bytes = URL_SAFE_NO_PAD.decode(segment).map_err(|_| ())?;
+    let value: serde_json::Value = serde_json::from_slice(&bytes).map_err(|_| ())?;
+    value.is_object().then_some(value).ok_or(())
+}
+
+fn check_registered_claims(
+    claims: &serde_json::Value,
+    audience: &str,
+    now: i64,
+) -> std::result::Result<(), String> {
+    let exp = claims["exp"].as_i64().ok_or("Invalid token claims")?;
+    if now >= exp {
+        return Err("Token expired".to_string());
+    }
+    if let Some(nbf) = claims.get("nbf") {
+        let nbf = nbf.as_i64().ok_or("Invalid token claims")?;
+        if nbf > now + CLOCK_SKEW_SECS {
+            return Err("Token not yet valid".to_string());
+        }
+    }
+    if claims["iss"].as_str() != Some(ISSUER) {
+        return Err("Invalid token issuer".to_string());
+    }
+    // RFC 7519 allows a single audience or a list of them
+    let audience_ok = match &claims["aud"] {
+        serde_json::Value::String(aud) => aud == audience,
+        serde_json::Value::Array(auds) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+        _ => false,
+    };
+    if !audience_ok {
+        return Err("Invalid token audience".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TokenClaims;
+
+    fn keys(configured: &str) -> Keys {
+        Keys::from_config(Some(configured), Some("legacy-secret".to_string())).unwrap()
+    }
+
+    fn claims(exp: i64) -> TokenClaims {
+        TokenClaims {
+            sub: "user-1".to_string(),
+            exp,
+            sid: Some("session-1".to_string()),
+            iat: Some(exp - 900),
+            nbf: None,
+            iss: Some(ISSUER.to_string()),
+            aud: Some(API_AUDIENCE.to_string()),
+        }
+    }
+
+    fn verify(keys: &Keys, token: &str, now: i64) -> std::result::Result<TokenClaims, String> {
+        keys.verify(token, API_AUDIENCE, now)
+    }
+
+    fn encode(value: serde_json::Value) -> String {
+        URL_SAFE_NO_PAD.encode(value.to_string())
+    }
+
+    fn signed(secret: &str, header: serde_json::Value, payload: serde_json::Value) -> String {
+        let input = format!("{}.{}", encode(header), encode(payload));
+        let signature = URL_SAFE_NO_PAD.encode(mac(secret, &input).finalize().into_bytes());
+        format!("{}.{}", input, signature)
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        let parsed = keys(" new:s3cr3t:with:colons , old:older ");
+        assert_eq!(parsed.keys[0].kid, "new");
+        assert_eq!(parsed.keys[0].secret, "s3cr3t:with:colons");
+        assert_eq!(parsed.keys[1].kid, "old");
+
+        let fallback = Keys::from_config(None, Some("secret".to_string())).unwrap();
+        assert_eq!(fallback.keys[0].kid, DEFAULT_KID);
+
+        assert!(Keys::from_config(None, None).is_err());
+        assert!(Keys::from_config(Some("nokid"), None).is_err());
+        assert!(Keys::from_config(Some("a:x,a:y"), None).is_err());
+        assert!(Keys::from_config(Some(":x"), None).is_err());
+    }
+
+    #[test]
+    fn test_sign_is_a_standard_jwt() {
+        let token = keys("k1:secret").sign(&claims(2_000)).unwrap();
+        let parts: Vec<&str> = token.split('.').collect();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(
+            decode_json(parts[0]).unwrap(),
+            serde_json::json!({ "alg": "HS256", "typ": "JWT", "kid": "k1" })
+        );
+        let payload = decode_json(parts[1]).unwrap();
+        assert_eq!(payload["iss"], ISSUER);
+        assert_eq!(payload["aud"], API_AUDIENCE);
+        assert_eq!(payload["iat"], 1_100);
+        assert!(payload.get("nbf").is_none());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let keys = keys("k1:secret");
+        let token = keys.sign(&claims(2_000)).unwrap();
+        let verified = verify(&keys, &token, 1_000).unwrap();
+        assert_eq!(verified.sub, "user-1");
+        assert_eq!(verified.sid.as_deref(), Some("session-1"));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let old = keys("old:first");
+        let token = old.sign(&claims(2_000)).unwrap();
+
+        // The new key signs, the old one still verifies
+        let rotating = keys("new:second,old:first");
+        assert!(verify(&rotating, &token, 1_000).is_ok());
+        assert!(rotating.sign(&claims(2_000)).unwrap().starts_with(&encode(
+            serde_json::json!({ "alg": "HS256", "typ": "JWT", "kid": "new" })
+        )));
+
+        // Once the old key is dropped its tokens stop verifying
+        assert_eq!(
+            verify(&keys("new:second"), &token, 1_000).unwrap_err(),
+            "Unknown token key id"
+        );
+    }
+
+    #[test]
+    fn test_rejects_alg_none() {
+        let keys = keys("k1:secret");
+        let payload = serde_json::to_value(claims(2_000)).unwrap();
+        let unsigned = format!(
+            "{}.{}.",
+            encode(serde_json::json!({ "alg": "none", "typ": "JWT", "kid": "k1" })),
+            encode(payload.clone())
+        );
+        assert_eq!(
+            verify(&keys, &unsigned, 1_000).unwrap_err(),
+            "Unsupported token algorithm"
+        );
+
+        let no_alg = signed(
+            "secret",
+            serde_json::json!({ "typ": "JWT", "kid": "k1" }),
+            payload.clone(),
+        );
+        assert!(verify(&keys, &no_alg, 1_000).is_err());
+
+        let hs512 = signed(
+            "secret",
+            serde_json::json!({ "alg": "HS512", "kid": "k1" }),
+            payload,
+        );
+        assert_eq!(
+            verify(&keys, &hs512, 1_000).unwrap_err(),
+            "Unsupported token algorithm"
+        );
+    }
+
+    #[test]
+    fn test_rejects_tampered_header() {
+        let keys = keys("k1:secret,k2:other");
+        let token = keys.sign(&claims(2_000)).unwrap();
+        let (_, rest) = token.split_once('.').unwrap();
+
+        // Pointing the token at a different key breaks the signature
+        let swapped = format!(
+            "{}.{}",
+            encode(serde_json::json!({ "alg": "HS256", "typ": "JWT", "kid": "k2" })),
+            rest
+        );
+        assert_eq!(
+            verify(&keys, &swapped, 1_000).unwrap_err(),
+            "Invalid token signature"
+        );
+
+        let missing_kid = format!(
+            "{}.{}",
+            encode(serde_json::json!({ "alg": "HS256", "typ": "JWT" })),
+            rest
+        );
+        assert_eq!(
+            verify(&keys, &missing_kid, 1_000).unwrap_err(),
+            "Missing token key id"
+        );
+
+        assert_eq!(
+            verify(&keys, &format!("not-base64!.{}", rest), 1_000).unwrap_err(),
+            "Invalid token header"
+        );
+    }
+
+    #[test]
+    fn test_rejects_tampered_payload() {
+        let keys = keys("k1:secret");
+        let token = keys.sign(&claims(2_000)).unwrap();
+        let parts: Vec<&str> = token.split('.').collect();
+        let mut payload = serde_json::to_value(claims(2_000)).unwrap();
+        payload["sub"] = "admin".into();
+        let forged = format!("{}.{}.{}", parts[0], encode(payload), parts[2]);
+        assert_eq!(
+            verify(&keys, &forged, 1_000).unwrap_err(),
+            "Invalid token signature"
+        );
+    }
+
+    #[test]
+    fn test_expired_and_not_yet_valid() {
+        let keys = keys("k1:secret");
+        let token = keys.sign(&claims(2_000)).unwrap();
+        assert!(verify(&keys, &token, 1_999).is_ok());
+        assert_eq!(verify(&keys, &token, 2_000).unwrap_err(), "Token expired");
+
+        let early = TokenClaims {
+            nbf: Some(1_500),
+            ..claims(2_000)
+        };
+        let token = keys.sign(&early).unwrap();
+        assert_eq!(
+            verify(&keys, &token, 1_000).unwrap_err(),
+            "Token not yet valid"
+        );
+        // Small clock differences are tolerated
+        assert!(verify(&keys, &token, 1_500 - CLOCK_SKEW_SECS).is_ok());
+
+        let no_exp = signed(
+            "secret",
+            serde_json::json!({ "alg": "HS256", "kid": "k1" }),
+            serde_json::json!({ "sub": "user-1", "iss": ISSUER, "aud": API_AUDIENCE }),
+        );
+        assert_eq!(
+            verify(&keys, &no_exp, 1_000).unwrap_err(),
+            "Invalid token claims"
+        );
+    }
+
+    #[test]
+    fn test_checks_issuer_and_audience() {
+        let keys = keys("k1:secret");
+        let token = keys.sign(&claims(2_000)).unwrap();
+        assert_eq!(
+            keys.verify::<TokenClaims>(&token, "mumble.fish/other", 1_000)
+                .unwrap_err(),
+            "Invalid token audience"
+        );
+
+        let foreign = TokenClaims {
+            iss: Some("https://evil.example".to_string()),
+            ..claims(2_000)
+        };
+        assert_eq!(
+            verify(&keys, &keys.sign(&foreign).unwrap(), 1_000).unwrap_err(),
+            "Invalid token issuer"
+        );
+
+        let listed = signed(
+            "secret",
+            serde_json::json!({ "alg": "HS256", "kid": "k1" }),
+            serde_json::json!({
+                "sub": "user-1",
+                "exp": 2_000,
+                "iss": ISSUER,
+                "aud": ["https://example.com", API_AUDIENCE],
+            }),
+        );
+        assert!(verify(&keys, &listed, 1_000).is_ok());
+    }
+
+    #[test]
+    fn test_legacy_tokens() {
+        let keys = keys("k1:secret");
+        let payload = encode(serde_json::json!({ "sub": "user-1", "exp": 2_000 }));
+        let signature =
+            URL_SAFE_NO_PAD.encode(mac("legacy-secret", &payload).finalize().into_bytes());
+        let legacy = format!("{}.{}", payload, signature);
+
+        let verified = verify(&keys, &legacy, 2_000).unwrap();
+        assert_eq!(verified.sub, "user-1");
+        assert_eq!(verified.sid, None);
+        assert_eq!(verify(&keys, &legacy, 2_001).unwrap_err(), "Token expired");
+
+        // Only as access tokens, and only while the legacy secret is configured
+        assert!(
+            keys.verify::<TokenClaims>(&legacy, "mumble.fish/other", 1_000)
+                .is_err()
+        );
+        let without_legacy = Keys::from_config(Some("k1:secret"), None).unwrap();
+        assert!(verify(&without_legacy, &legacy, 1_000).is_err());
+
+        let forged = format!(
+            "{}.{}",
+            encode(serde_json::json!({ "sub": "admin", "exp": 2_000 })),
+            signature
+        );
+        assert_eq!(
+            verify(&keys, &forged, 1_000).unwrap_err(),
+            "Invalid token signature"
+        );
+    }
+}
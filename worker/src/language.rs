@@ -0,0 +1,137 @@
+/// Longest header line we wait for before giving up on it while streaming
+pub const MAX_HEADER_LENGTH: usize = 64;
+
+const HEADER_PREFIX: &str = "language:";
+
+/// Validate a BCP-47 language tag (`de`, `pt-BR`, `zh-Hant-TW`) and normalize its
+/// casing: language lowercase, script titlecase, region uppercase.
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let mut subtags = tag.trim().split(['-', '_']);
+
+    let language = subtags.next()?;
+    if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let mut normalized = language.to_ascii_lowercase();
+    for subtag in subtags {
+        if subtag.is_empty()
+            || subtag.len() > 8
+            || !subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            return None;
+        }
+        normalized.push('-');
+        match subtag.len() {
+            2 if subtag.chars().all(|c| c.is_ascii_alphabetic()) => {
+                normalized.push_str(&subtag.to_ascii_uppercase())
+            }
+            4 if subtag.chars().all(|c| c.is_ascii_alphabetic()) => {
+                normalized.push_str(&subtag[..1].to_ascii_uppercase());
+                normalized.push_str(&subtag[1..].to_ascii_lowercase());
+            }
+            _ => normalized.push_str(&subtag.to_ascii_lowercase()),
+        }
+    }
+
+    Some(normalized)
+}
+
+/// Prompt text pinning the output language. The first chunk of a text also asks
+/// for a header line naming the input's language, which `split_header` removes.
+pub fn instructions(target: Option<&str>, first_chunk: bool) -> String {
+    let mut text = match target {
+        Some(target) => format!(
+            "Write the output in the language with BCP-47 code \"{}\", translating from the input's language if they differ.",
+            target
+        ),
+        None => {
+            "Write the output in the same language as the input. Do not translate it.".to_string()
+        }
+    };
+
+    if first_chunk {
+        text.push_str(" Before anything else, output one line of the form \"Language: <BCP-47 code of the input's language>\" followed by a blank line.");
+    }
+
+    text
+}
+
+/// Split a leading `Language: xx` line off a completion. Text without a valid
+/// header is returned unchanged.
+pub fn split_header(text: &str) -> (Option<String>, &str) {
+    let trimmed = text.trim_start();
+    let (first, rest) = trimmed.split_once('\n').unwrap_or((trimmed, ""));
+
+    match parse_header_line(first) {
+        Some(language) => (Some(language), rest.trim_start()),
+        None => (None, text),
+    }
+}
+
+fn parse_header_line(line: &str) -> Option<String> {
+    let line = line.trim().trim_matches('*').trim();
+    let prefix = line.get(..HEADER_PREFIX.len())?;
+    if !prefix.eq_ignore_ascii_case(HEADER_PREFIX) {
+        return None;
+    }
+    normalize_tag(
+        line[HEADER_PREFIX.len()..]
+            .trim()
+            .trim_matches(['"', '`', '.']),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_tag() {
+        assert_eq!(normalize_tag("de"), Some("de".to_string()));
+        assert_eq!(normalize_tag("PT-br"), Some("pt-BR".to_string()));
+        assert_eq!(normalize_tag("zh_hant_tw"), Some("zh-Hant-TW".to_string()));
+        assert_eq!(normalize_tag("es-419"), Some("es-419".to_string()));
+    }
+
+    #[test]
+    fn test_normalize_tag_rejects_invalid() {
+        assert_eq!(normalize_tag(""), None);
+        assert_eq!(normalize_tag("english"), None);
+        assert_eq!(normalize_tag("en-"), None);
+        assert_eq!(normalize_tag("en-US; drop table"), None);
+        assert_eq!(normalize_tag("e1"), None);
+    }
+
+    #[test]
+    fn test_instructions() {
+        let translate = instructions(Some("de"), false);
+        assert!(translate.contains("\"de\""));
+        assert!(!translate.contains("Language:"));
+
+        let preserve = instructions(None, true);
+        assert!(preserve.contains("same language as the input"));
+        assert!(preserve.contains("Language:"));
+    }
+
+    #[test]
+    fn test_split_header() {
+        assert_eq!(
+            split_header("Language: fr\n\nBonjour à tous."),
+            (Some("fr".to_string()), "Bonjour à tous.")
+        );
+        assert_eq!(
+            split_header("**Language: EN-us**\nHi."),
+            (Some("en-US".to_string()), "Hi.")
+        );
+    }
+
+    #[test]
+    fn test_split_header_missing() {
+        assert_eq!(split_header("Hello there."), (None, "Hello there."));
+        assert_eq!(
+            split_header("Language: it's complicated\nText"),
+            (None, "Language: it's complicated\nText")
+        );
+    }
+}
@@ -1,26 +1,84 @@
 use worker::*;
 
+mod access;
+mod action_items;
 mod auth;
+mod byok;
+mod catalog;
+mod chunking;
+mod diff;
+mod error;
+mod glossary;
+mod guard;
+mod jwt;
+mod language;
+mod mailer;
 mod models;
+mod notes;
+mod password;
 mod polish;
+mod provider;
+mod redact;
+mod refresh;
+mod resilience;
+mod sessions;
+mod sse;
+mod summarize;
+#[cfg(test)]
+mod testing;
+mod tones;
+mod transcribe;
+mod usage;
+mod verification;
 
 #[event(fetch)]
-async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
     console_error_panic_hook::set_once();
 
     Router::new()
         .get_async("/api/health", health)
         .post_async("/api/v1/auth/register", auth::register)
         .post_async("/api/v1/auth/login", auth::login)
+        .post_async("/api/v1/auth/refresh", refresh::refresh)
+        .post_async("/api/v1/auth/logout", sessions::logout)
+        .post_async("/api/v1/auth/verify-email", verification::verify_email)
+        .post_async("/api/v1/auth/verify-email/resend", verification::resend)
+        .post_async("/api/v1/auth/password/forgot", |req, route| {
+            password::forgot(req, route, &ctx)
+        })
+        .post_async("/api/v1/auth/password/reset", password::reset)
+        .get_async("/api/v1/auth/sessions", sessions::list_sessions)
+        .delete_async("/api/v1/auth/sessions/:id", sessions::delete_session)
         .get_async("/api/v1/auth/me", auth::get_me)
+        .put_async("/api/v1/auth/settings", auth::update_settings)
         .get_async("/api/v1/auth/oauth/:provider", auth::oauth_start)
         .get_async(
             "/api/v1/auth/oauth/:provider/callback",
             auth::oauth_callback,
         )
+        .get_async("/api/v1/models", catalog::list_models)
+        .post_async("/api/v1/byok/validate", byok::validate_key)
         .post_async("/api/v1/polish", polish::polish)
+        .post_async("/api/v1/transcribe", transcribe::transcribe)
+        .post_async("/api/v1/summarize", summarize::summarize)
+        .post_async("/api/v1/action-items", action_items::extract_action_items)
+        .get_async("/api/v1/tones", tones::list_tones)
+        .post_async("/api/v1/tones", tones::create_tone)
+        .put_async("/api/v1/tones/:id", tones::update_tone)
+        .delete_async("/api/v1/tones/:id", tones::delete_tone)
+        .get_async("/api/v1/glossary", glossary::list_entries)
+        .post_async("/api/v1/glossary", glossary::create_entry)
+        .put_async("/api/v1/glossary/:id", glossary::update_entry)
+        .delete_async("/api/v1/glossary/:id", glossary::delete_entry)
+        .get_async("/api/v1/notes", notes::list_notes)
+        .post_async("/api/v1/notes", notes::create_note)
+        .get_async("/api/v1/notes/:id", notes::get_note)
+        .put_async("/api/v1/notes/:id", notes::upsert_note)
+        .delete_async("/api/v1/notes/:id", notes::delete_note)
+        .get_async("/api/v1/usage", usage::get_usage)
         .run(req, env)
         .await
+        .or_else(|e| error::ApiError::from(e).into_response())
 }
 
 async fn health(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
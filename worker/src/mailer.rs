@@ -0,0 +1,190 @@
+use crate::provider::{env_secret, env_string};
+use worker::*;
+
+/// Resend's API; any service accepting the same JSON body works
+const DEFAULT_MAIL_API_URL: &str = "https://api.resend.com/emails";
+const DEFAULT_MAIL_FROM: &str = "mumble.fish <noreply@mumble.fish>";
+
+/// A plain-text email
+#[derive(Clone, Debug, PartialEq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+}
+
+pub trait Mailer {
+    async fn send(&self, email: &Email) -> Result<()>;
+}
+
+/// Writes emails to the worker log instead of sending them, for local development.
+/// Links in them are live, so don't rely on it in production.
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    async fn send(&self, email: &Email) -> Result<()> {
+        console_log!(
+            "Email to {} (not sent, MAIL_MODE=log)\nSubject: {}\n\n{}",
+            email.to,
+            email.subject,
+            email.text
+        );
+        Ok(())
+    }
+}
+
+/// Sends through an HTTP email API with a Resend-style JSON body
+pub struct HttpMailer {
+    url: String,
+    api_key: String,
+    from: String,
+}
+
+impl Mailer for HttpMailer {
+    async fn send(&self, email: &Email) -> Result<()> {
+        let headers = Headers::new();
+        headers.set("Authorization", &format!("Bearer {}", self.api_key))?;
+        headers.set("Content-Type", "application/json")?;
+
+        let body = serde_json::json!({
+            "from": self.from,
+            "to": [email.to],
+            "subject": email.subject,
+            "text": email.text,
+        });
+
+        let mut init = RequestInit::new();
+        init.with_method(Method::Post);
+        init.with_headers(headers);
+        init.with_body(Some(serde_json::to_string(&body)?.into()));
+
+        let req = Request::new_with_init(&self.url, &init)?;
+        let mut resp = Fetch::Request(req).send().await?;
+        if !(200..300).contains(&resp.status_code()) {
+            return Err(Error::RustError(format!(
+                "Mail API error ({}): {}",
+                resp.status_code(),
+                resp.text().await.unwrap_or_default()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Keeps emails in memory so tests can read them back
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryMailer {
+    sent: std::cell::RefCell<Vec<Email>>,
+}
+
+#[cfg(test)]
+impl MemoryMailer {
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+impl Mailer for MemoryMailer {
+    async fn send(&self, email: &Email) -> Result<()> {
+        self.sent.borrow_mut().push(email.clone());
+        Ok(())
+    }
+}
+
+/// How the worker delivers email
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MailMode {
+    /// Through the email API, which must be configured
+    Send,
+    /// To the worker log only, for local development
+    Log,
+}
+
+impl MailMode {
+    /// `MAIL_MODE`: `send` (the default) or `log`
+    pub fn from_env(env: &Env) -> Self {
+        env_string(env, "MAIL_MODE")
+            .and_then(|value| Self::parse(&value))
+            .unwrap_or(Self::Send)
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "send" => Some(Self::Send),
+            "log" => Some(Self::Log),
+            _ => None,
+        }
+    }
+}
+
+/// The mailer the worker is configured for: `MAIL_API_KEY` (with optional
+/// `MAIL_API_URL` and `MAIL_FROM`) sends real email, and `MAIL_MODE=log` only
+/// logs it. Without either, sending fails rather than silently dropping mail.
+pub enum ConfiguredMailer {
+    Log(LogMailer),
+    Http(HttpMailer),
+}
+
+impl ConfiguredMailer {
+    pub fn from_env(env: &Env) -> Result<Self> {
+        if MailMode::from_env(env) == MailMode::Log {
+            return Ok(Self::Log(LogMailer));
+        }
+        let api_key = env_secret(env, "MAIL_API_KEY").ok_or_else(|| {
+            Error::RustError(
+                "MAIL_API_KEY not configured on server (set MAIL_MODE=log to only log emails)"
+                    .to_string(),
+            )
+        })?;
+        Ok(Self::Http(HttpMailer {
+            url: env_string(env, "MAIL_API_URL")
+                .unwrap_or_else(|| DEFAULT_MAIL_API_URL.to_string()),
+            api_key,
+            from: env_string(env, "MAIL_FROM").unwrap_or_else(|| DEFAULT_MAIL_FROM.to_string()),
+        }))
+    }
+}
+
+impl Mailer for ConfiguredMailer {
+    async fn send(&self, email: &Email) -> Result<()> {
+        match self {
+            Self::Log(m) => m.send(email).await,
+            Self::Http(m) => m.send(email).await,
+        }
+    }
+}
+
+/// Seconds until another email of a kind last sent at `sent_at` may go out, if any
+pub fn cooldown_wait(sent_at: Option<i64>, cooldown_secs: i64, now: i64) -> Option<u32> {
+    let wait = sent_at? + cooldown_secs - now;
+    (wait > 0).then_some(wait as u32)
+}
+
+/// Where links in emails point, `APP_URL` or the production site
+pub fn app_url(env: &Env) -> String {
+    env_string(env, "APP_URL")
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or_else(|| "https://mumble.fish".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mail_mode_parse() {
+        assert_eq!(MailMode::parse(" LOG "), Some(MailMode::Log));
+        assert_eq!(MailMode::parse("send"), Some(MailMode::Send));
+        assert_eq!(MailMode::parse("console"), None);
+    }
+
+    #[test]
+    fn test_cooldown_wait() {
+        assert_eq!(cooldown_wait(None, 60, 1_000), None);
+        assert_eq!(cooldown_wait(Some(1_000), 60, 1_000), Some(60));
+        assert_eq!(cooldown_wait(Some(1_000), 60, 1_059), Some(1));
+        assert_eq!(cooldown_wait(Some(1_000), 60, 1_060), None);
+    }
+}
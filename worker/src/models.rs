@@ -1,3 +1,4 @@
+use crate::error::ErrorBody;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize)]
@@ -6,7 +7,7 @@ pub struct ApiResponse<T: Serialize> {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<T>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub error: Option<String>,
+    pub error: Option<ErrorBody>,
 }
 
 impl<T: Serialize> ApiResponse<T> {
@@ -18,11 +19,11 @@ impl<T: Serialize> ApiResponse<T> {
         }
     }
 
-    pub fn error(message: impl Into<String>) -> ApiResponse<()> {
+    pub fn error(error: ErrorBody) -> ApiResponse<()> {
         ApiResponse {
             success: false,
             data: None,
-            error: Some(message.into()),
+            error: Some(error),
         }
     }
 }
@@ -31,30 +32,323 @@ impl<T: Serialize> ApiResponse<T> {
 pub struct AuthCredentials {
     pub email: String,
     pub password: String,
+    /// Shown in the session list; derived from the User-Agent when absent
+    #[serde(default)]
+    pub device_name: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct AuthResponse {
-    pub token: String,
+    #[serde(flatten)]
+    pub tokens: TokenPair,
     pub user: UserInfo,
 }
 
+/// A short-lived access token and the refresh token that replaces it
+#[derive(Serialize)]
+pub struct TokenPair {
+    /// Bearer token for `Authorization`
+    pub token: String,
+    /// Seconds until `token` expires
+    pub expires_in: i64,
+    /// Single use: exchange at `POST /auth/refresh` for a new pair
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+/// One signed-in device
+#[derive(Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub device_name: Option<String>,
+    /// Country of the IP address at sign-in or last refresh
+    pub ip_country: Option<String>,
+    pub created_at: i64,
+    /// Last refresh, accurate to the access token lifetime
+    pub last_seen_at: i64,
+    /// The session making this request
+    pub current: bool,
+}
+
+#[derive(Deserialize, Default)]
+pub struct LogoutRequest {
+    /// Sign out every session of the account, not just this one
+    #[serde(default)]
+    pub everywhere: bool,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct UserInfo {
     pub id: String,
     pub email: String,
+    #[serde(default)]
+    pub email_verified: bool,
 }
 
-#[derive(Serialize, Deserialize)]
+/// `GET /auth/me`: the user plus their hosted quota for this month
+#[derive(Serialize)]
+pub struct MeResponse {
+    #[serde(flatten)]
+    pub user: UserInfo,
+    pub quota: Quota,
+    pub settings: UserSettings,
+}
+
+/// Account-wide preferences, stored on `users`
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq)]
+pub struct UserSettings {
+    /// Redact personal data from every AI request, whatever the request asks for.
+    /// A preference the user can switch off, unless `redact_pii_required`.
+    #[serde(default)]
+    pub redact_pii: bool,
+    /// The operator requires redaction for everyone (`REDACT_PII=required`)
+    #[serde(default, skip_deserializing)]
+    pub redact_pii_required: bool,
+}
+
+/// Billing tier, stored as `users.plan`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Plan {
+    Free,
+    Pro,
+    Team,
+}
+
+impl Plan {
+    /// Unknown values (e.g. a plan that was retired) fall back to free
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "pro" => Plan::Pro,
+            "team" => Plan::Team,
+            _ => Plan::Free,
+        }
+    }
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Plan::Free => "free",
+            Plan::Pro => "pro",
+            Plan::Team => "team",
+        }
+    }
+
+    /// Hosted tokens (prompt + completion) per calendar month. BYOK calls don't count.
+    pub fn monthly_token_allowance(&self) -> u64 {
+        match self {
+            Plan::Free => 100_000,
+            Plan::Pro => 2_000_000,
+            Plan::Team => 10_000_000,
+        }
+    }
+
+    /// Hosted transcription seconds per calendar month. BYOK calls don't count.
+    pub fn monthly_audio_allowance(&self) -> u64 {
+        match self {
+            Plan::Free => 30 * 60,
+            Plan::Pro => 20 * 60 * 60,
+            Plan::Team => 100 * 60 * 60,
+        }
+    }
+}
+
+/// Hosted token allowance for the current UTC month
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Quota {
+    pub plan: Plan,
+    pub limit: u64,
+    pub used: u64,
+    pub remaining: u64,
+    /// Unix seconds when the allowance resets
+    pub resets_at: i64,
+}
+
+impl Quota {
+    pub fn new(plan: Plan, used: u64, resets_at: i64) -> Self {
+        let limit = plan.monthly_token_allowance();
+        Self {
+            plan,
+            limit,
+            used,
+            remaining: limit.saturating_sub(used),
+            resets_at,
+        }
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining == 0
+    }
+
+    /// Account for tokens spent since the quota was read
+    pub fn consume(&mut self, tokens: u64) {
+        self.used += tokens;
+        self.remaining = self.remaining.saturating_sub(tokens);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct TokenClaims {
     pub sub: String, // user_id
     pub exp: i64,    // expiry timestamp
+    /// Session id, absent in tokens issued before sessions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// Issued at; absent in legacy tokens, like the other registered claims below
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// Checked during verification; RFC 7519 also allows a list, so not read back
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct PolishRequest {
     pub text: String,
-    pub tone: ToneStyle,
+    pub tone: ToneRef,
+    /// Stream the result back as server-sent events instead of a single JSON body
+    #[serde(default)]
+    pub stream: bool,
+    /// Persist the result as a synced note (requires sign-in)
+    #[serde(default)]
+    pub save: bool,
+    /// Smooth the transitions between chunks of a long text with an extra pass
+    #[serde(default)]
+    pub stitch: bool,
+    /// Shape of the output, independent of tone
+    #[serde(default)]
+    pub format: OutputFormat,
+    /// BCP-47 tag to translate into. Without one the input's language is kept.
+    #[serde(default)]
+    pub target_language: Option<String>,
+    /// Also generate a short title (stored on the note when saving)
+    #[serde(default)]
+    pub include_title: bool,
+    /// Also generate a one-paragraph summary and key topics
+    #[serde(default)]
+    pub include_summary: bool,
+    /// Return a word-level diff from `text` to the polished result
+    #[serde(default)]
+    pub diff: bool,
+    /// Replace personal data with placeholders before the text leaves the worker
+    #[serde(default)]
+    pub redact: bool,
+    /// One of the models `GET /api/v1/models` lists for the caller's mode
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// Prose, as dictated
+    #[default]
+    Plain,
+    Markdown,
+    BulletList,
+    /// A subject line plus body, returned split as `PolishResponse::email`
+    Email,
+    ChatMessage,
+}
+
+impl OutputFormat {
+    /// Appended to the tone prompt. `continuation` is set for the later chunks of a
+    /// long text, which must not repeat one-off parts like an email's subject line.
+    pub fn instructions(&self, continuation: bool) -> Option<&'static str> {
+        match (self, continuation) {
+            (OutputFormat::Plain, _) => None,
+            (OutputFormat::Markdown, _) => Some(
+                "Format the output as Markdown: use headings, paragraphs, lists and emphasis where they help readability.",
+            ),
+            (OutputFormat::BulletList, _) => Some(
+                "Format the output as a bulleted list, one point per line starting with \"- \". Do not add a heading or introduction.",
+            ),
+            (OutputFormat::Email, false) => Some(
+                "Format the output as an email: a line \"Subject: \" followed by a short subject, then a blank line, then the email body with an appropriate greeting and sign-off.",
+            ),
+            (OutputFormat::Email, true) => Some(
+                "The output continues the body of an email that has already started. Do not add a subject line, greeting or sign-off.",
+            ),
+            (OutputFormat::ChatMessage, _) => Some(
+                "Format the output as a short chat message (e.g. Slack or Teams): no greeting or sign-off, short sentences, and plain text without headings.",
+            ),
+        }
+    }
+
+    /// Combine a tone's system prompt with this format's instructions
+    pub fn apply(&self, tone_prompt: &str, continuation: bool) -> String {
+        match self.instructions(continuation) {
+            Some(instructions) => format!("{}\n\n{}", tone_prompt, instructions),
+            None => tone_prompt.to_string(),
+        }
+    }
+}
+
+/// An `email`-format result split into its parts
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EmailParts {
+    pub subject: String,
+    pub body: String,
+}
+
+impl EmailParts {
+    /// Split a leading `Subject:` line off the text. Without one, the subject is empty.
+    pub fn parse(text: &str) -> Self {
+        let text = text.trim();
+        let (first, rest) = text.split_once('\n').unwrap_or((text, ""));
+        let first = first.trim().trim_start_matches(['*', '#', ' ']);
+
+        match first.get(..8) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("subject:") => Self {
+                subject: first[8..].trim().trim_end_matches('*').trim().to_string(),
+                body: rest.trim().to_string(),
+            },
+            _ => Self {
+                subject: String::new(),
+                body: text.to_string(),
+            },
+        }
+    }
+}
+
+/// A tone picked by id: one of the built-in names, or the id of a user's custom tone
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum ToneRef {
+    BuiltIn(ToneStyle),
+    Custom(String),
+}
+
+impl ToneRef {
+    pub fn id(&self) -> &str {
+        match self {
+            ToneRef::BuiltIn(tone) => tone.id(),
+            ToneRef::Custom(id) => id,
+        }
+    }
 }
 
 #[derive(Deserialize, Clone, Copy)]
@@ -68,6 +362,35 @@ pub enum ToneStyle {
 }
 
 impl ToneStyle {
+    pub const ALL: [ToneStyle; 5] = [
+        ToneStyle::Casual,
+        ToneStyle::Professional,
+        ToneStyle::Formal,
+        ToneStyle::Friendly,
+        ToneStyle::Concise,
+    ];
+
+    /// The id clients send back in `PolishRequest::tone`
+    pub fn id(&self) -> &'static str {
+        match self {
+            ToneStyle::Casual => "casual",
+            ToneStyle::Professional => "professional",
+            ToneStyle::Formal => "formal",
+            ToneStyle::Friendly => "friendly",
+            ToneStyle::Concise => "concise",
+        }
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            ToneStyle::Casual => "Casual",
+            ToneStyle::Professional => "Professional",
+            ToneStyle::Formal => "Formal",
+            ToneStyle::Friendly => "Friendly",
+            ToneStyle::Concise => "Concise",
+        }
+    }
+
     pub fn system_prompt(&self) -> &'static str {
         match self {
             ToneStyle::Casual => {
@@ -89,14 +412,474 @@ impl ToneStyle {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ToneExample {
+    pub input: String,
+    pub output: String,
+}
+
+/// Create/update body for `/api/v1/tones`
+#[derive(Deserialize)]
+pub struct ToneInput {
+    pub name: String,
+    pub instructions: String,
+    #[serde(default)]
+    pub examples: Vec<ToneExample>,
+}
+
+#[derive(Serialize)]
+pub struct CustomTone {
+    pub id: String,
+    pub name: String,
+    pub instructions: String,
+    pub examples: Vec<ToneExample>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl CustomTone {
+    pub fn system_prompt(&self) -> String {
+        let mut prompt = format!(
+            "Rewrite this dictated note following these style instructions:\n{}\n\nFix any grammar or clarity issues while preserving the meaning. Return ONLY the rewritten text, no preamble or explanation.",
+            self.instructions.trim()
+        );
+
+        if !self.examples.is_empty() {
+            prompt.push_str("\n\nExamples of the desired style:");
+            for example in &self.examples {
+                prompt.push_str(&format!(
+                    "\n\nInput: {}\nOutput: {}",
+                    example.input.trim(),
+                    example.output.trim()
+                ));
+            }
+        }
+
+        prompt
+    }
+}
+
+/// A create/update body for a glossary entry
+#[derive(Deserialize)]
+pub struct GlossaryInput {
+    pub term: String,
+    /// Ways speech recognition mishears the term
+    #[serde(default)]
+    pub variants: Vec<String>,
+    /// What to write instead of `term`, when that differs (e.g. `K8s` → `Kubernetes`)
+    #[serde(default)]
+    pub replacement: Option<String>,
+}
+
+/// A name or term polishing must keep as spelled
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct GlossaryEntry {
+    pub id: String,
+    pub term: String,
+    pub variants: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replacement: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl GlossaryEntry {
+    /// The spelling that should appear in polished text
+    pub fn spelling(&self) -> &str {
+        self.replacement.as_deref().unwrap_or(&self.term)
+    }
+}
+
+/// One entry in the tone picker, built-in or custom
+#[derive(Serialize)]
+pub struct ToneInfo {
+    pub id: String,
+    pub name: String,
+    pub builtin: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub examples: Vec<ToneExample>,
+}
+
+impl From<ToneStyle> for ToneInfo {
+    fn from(tone: ToneStyle) -> Self {
+        Self {
+            id: tone.id().to_string(),
+            name: tone.display_name().to_string(),
+            builtin: true,
+            instructions: None,
+            examples: Vec::new(),
+        }
+    }
+}
+
+impl From<CustomTone> for ToneInfo {
+    fn from(tone: CustomTone) -> Self {
+        Self {
+            id: tone.id,
+            name: tone.name,
+            builtin: false,
+            instructions: Some(tone.instructions),
+            examples: tone.examples,
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct PolishResponse {
     pub polished: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+    /// Id of the saved note when the request asked for `save`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note_id: Option<String>,
+    /// How many chunks the text was split into (1 unless it was long)
+    pub chunks: u32,
+    /// BCP-47 tag of the input's language, as detected by the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_language: Option<String>,
+    /// BCP-47 tag of the output: `target_language`, or the detected source language
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_language: Option<String>,
+    /// Subject and body when `format` was `email`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<EmailParts>,
+    #[serde(flatten)]
+    pub summary: SummaryFields,
+    /// Glossary spellings that were in the input but didn't survive polishing
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub glossary_missing: Vec<String>,
+    /// Changes from the request's `text` to `polished` when `diff` was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<Vec<DiffOp>>,
+    /// The provider and model that produced the result
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub served_by: Option<ServedBy>,
+    /// Hosted quota left after this request (absent for BYOK)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<Quota>,
+}
+
+/// Incremental text sent as a `delta` event while streaming
+#[derive(Serialize)]
+pub struct PolishDelta {
+    pub text: String,
+}
+
+/// Client-supplied note fields for create/upsert. Timestamps are Unix seconds;
+/// `updated_at` decides last-writer-wins conflicts.
+#[derive(Deserialize)]
+pub struct NoteInput {
+    pub raw_text: String,
+    pub polished_text: String,
+    pub tone: String,
+    /// Omit to keep the note's current title
+    #[serde(default)]
+    pub title: Option<String>,
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+#[derive(Serialize)]
+pub struct Note {
+    pub id: String,
+    pub raw_text: String,
+    pub polished_text: String,
+    pub tone: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub deleted: bool,
+    /// Server-assigned sync cursor position (Unix milliseconds)
+    pub synced_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct NoteList {
+    pub notes: Vec<Note>,
+    /// Opaque `<synced_at>:<id>`; pass back as `since` to fetch only later changes
+    pub cursor: String,
+    pub has_more: bool,
+}
+
+#[derive(Serialize)]
+pub struct NoteSyncResult {
+    pub note: Note,
+    /// False when the server already had a newer version, which is returned instead
+    pub applied: bool,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffKind {
+    Insert,
+    Delete,
+    Replace,
+}
+
+/// One change from the raw text to the polished text. Offsets are in Unicode
+/// scalar values (Rust `char`s / Swift `unicodeScalars`), end-exclusive. An
+/// insert has an empty old range, a delete an empty new range.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DiffOp {
+    pub op: DiffKind,
+    pub old_start: usize,
+    pub old_end: usize,
+    pub new_start: usize,
+    pub new_end: usize,
+}
+
+/// Generated title, summary and key topics for a transcript
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NoteSummary {
+    pub title: String,
+    pub summary: String,
+    #[serde(default)]
+    pub topics: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SummarizeRequest {
+    pub text: String,
+    /// Store the generated title on this note (requires sign-in)
+    #[serde(default)]
+    pub note_id: Option<String>,
+    /// Replace personal data with placeholders before the text leaves the worker
+    #[serde(default)]
+    pub redact: bool,
+}
+
+#[derive(Serialize)]
+pub struct SummarizeResponse {
+    #[serde(flatten)]
+    pub summary: NoteSummary,
+    pub served_by: ServedBy,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<Quota>,
+}
+
+/// The parts of a `NoteSummary` a polish request asked for with
+/// `include_title` / `include_summary`
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct SummaryFields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topics: Option<Vec<String>>,
+}
+
+impl SummaryFields {
+    pub fn select(summary: NoteSummary, include_title: bool, include_summary: bool) -> Self {
+        Self {
+            title: include_title.then_some(summary.title),
+            summary: include_summary.then_some(summary.summary),
+            topics: include_summary.then_some(summary.topics),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ActionItemsRequest {
+    pub text: String,
+    /// IANA timezone (e.g. `Europe/Berlin`) that relative due dates are resolved in
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// The client's current time as RFC 3339; defaults to the server's clock
+    #[serde(default)]
+    pub now: Option<String>,
+    /// Replace personal data with placeholders before the text leaves the worker
+    #[serde(default)]
+    pub redact: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    Medium,
+    High,
+}
+
+/// A to-do extracted from a transcript, with its due date resolved
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ActionItem {
+    pub task: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Local date in the request's timezone (`YYYY-MM-DD`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub due_date: Option<String>,
+    /// RFC 3339 timestamp with offset, when a time of day was mentioned
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub due_at: Option<String>,
+    pub priority: Priority,
+}
+
+#[derive(Serialize)]
+pub struct ActionItemsResponse {
+    pub action_items: Vec<ActionItem>,
+    /// The timezone due dates were resolved in
+    pub timezone: String,
+    pub served_by: ServedBy,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<Quota>,
+}
+
+/// Usage totals for one UTC day (`YYYY-MM-DD`) or month (`YYYY-MM`)
+#[derive(Serialize, Debug, PartialEq)]
+pub struct UsagePeriod {
+    pub period: String,
+    pub requests: u64,
+    /// Requests made with the caller's own API key
+    pub byok_requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+#[derive(Serialize)]
+pub struct UsageReport {
+    pub daily: Vec<UsagePeriod>,
+    pub monthly: Vec<UsagePeriod>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+impl TokenUsage {
+    pub fn add(&mut self, other: &TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+
+    /// Tokens charged against the hosted quota for a model with this cost weight
+    pub fn billed(&self, cost_weight: f64) -> u64 {
+        (self.total_tokens as f64 * cost_weight).ceil() as u64
+    }
+}
+
+/// Hosted transcription allowance for the current UTC month, in audio seconds
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AudioQuota {
+    pub plan: Plan,
+    pub limit: u64,
+    pub used: u64,
+    pub remaining: u64,
+    /// Unix seconds when the allowance resets
+    pub resets_at: i64,
+}
+
+impl AudioQuota {
+    pub fn new(plan: Plan, used: u64, resets_at: i64) -> Self {
+        let limit = plan.monthly_audio_allowance();
+        Self {
+            plan,
+            limit,
+            used,
+            remaining: limit.saturating_sub(used),
+            resets_at,
+        }
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining == 0
+    }
+
+    pub fn consume(&mut self, seconds: u64) {
+        self.used += seconds;
+        self.remaining = self.remaining.saturating_sub(seconds);
+    }
+}
+
+/// One timed stretch of a transcript, in seconds from the start of the audio
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TranscriptSegment {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+#[derive(Serialize)]
+pub struct TranscribeResponse {
+    pub text: String,
+    /// Spoken language as reported by the transcription model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Length of the audio in seconds, estimated from the upload when the
+    /// transcription server doesn't report it
+    pub duration: f64,
+    pub segments: Vec<TranscriptSegment>,
+    pub served_by: ServedBy,
+    /// Hosted transcription allowance left after this request (absent for BYOK)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_quota: Option<AudioQuota>,
+    /// The polished transcript when the request included `polish` options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub polish: Option<PolishResponse>,
+    /// Why polishing failed; the transcript is still returned
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub polish_error: Option<String>,
+}
+
+/// Which backend answered, after any retries and fallbacks
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ServedBy {
+    /// `openai`, `openai-compatible` or `anthropic`
+    pub provider: &'static str,
+    pub model: String,
+    /// Quota tokens charged per token this backend used (1 for BYOK)
+    #[serde(skip)]
+    pub cost_weight: f64,
+}
+
+#[derive(Serialize)]
+pub struct ModelInfo {
+    pub id: String,
+    /// Used when a request doesn't name a model
+    pub default: bool,
+    /// Quota tokens charged per token used, hosted only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost_weight: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct ModelsResponse {
+    pub hosted: Vec<ModelInfo>,
+    pub byok: Vec<ModelInfo>,
+}
+
+/// The outcome of checking a BYOK key against OpenAI
+#[derive(Serialize)]
+pub struct ByokValidation {
+    pub valid: bool,
+    /// The key's type prefix and last four characters, e.g. `sk-proj-…x7Qa`
+    pub fingerprint: String,
+    /// Allowed BYOK models the key has access to
+    pub models: Vec<String>,
+    /// Why the key was rejected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::ApiError;
 
     #[test]
     fn test_api_response_success() {
@@ -118,27 +901,31 @@ mod tests {
 
     #[test]
     fn test_api_response_error() {
-        let response = ApiResponse::<()>::error("something went wrong");
+        let response = ApiResponse::<()>::error(ApiError::NotFound("Note not found".into()).body());
         assert!(!response.success);
         assert!(response.data.is_none());
-        assert_eq!(response.error, Some("something went wrong".to_string()));
+        let error = response.error.unwrap();
+        assert_eq!(error.code, "not_found");
+        assert_eq!(error.message, "Note not found");
     }
 
     #[test]
     fn test_api_response_error_serialization() {
-        let response = ApiResponse::<()>::error("bad request");
+        let response = ApiResponse::<()>::error(ApiError::validation("bad request").body());
         let json = serde_json::to_string(&response).unwrap();
 
         assert!(json.contains("\"success\":false"));
-        assert!(json.contains("\"error\":\"bad request\""));
+        assert!(
+            json.contains("\"error\":{\"code\":\"validation_error\",\"message\":\"bad request\"}")
+        );
         assert!(!json.contains("\"data\""));
     }
 
     #[test]
     fn test_api_response_error_from_string() {
         let msg = String::from("dynamic error");
-        let response = ApiResponse::<()>::error(msg);
-        assert_eq!(response.error, Some("dynamic error".to_string()));
+        let response = ApiResponse::<()>::error(ApiError::Conflict(msg).body());
+        assert_eq!(response.error.unwrap().message, "dynamic error");
     }
 
     #[test]
@@ -204,23 +991,232 @@ mod tests {
     fn test_tone_style_deserialization() {
         let json = r#"{"text": "hello", "tone": "casual"}"#;
         let req: PolishRequest = serde_json::from_str(json).unwrap();
-        assert!(matches!(req.tone, ToneStyle::Casual));
+        assert!(matches!(req.tone, ToneRef::BuiltIn(ToneStyle::Casual)));
 
         let json = r#"{"text": "hello", "tone": "professional"}"#;
         let req: PolishRequest = serde_json::from_str(json).unwrap();
-        assert!(matches!(req.tone, ToneStyle::Professional));
+        assert!(matches!(
+            req.tone,
+            ToneRef::BuiltIn(ToneStyle::Professional)
+        ));
 
         let json = r#"{"text": "hello", "tone": "formal"}"#;
         let req: PolishRequest = serde_json::from_str(json).unwrap();
-        assert!(matches!(req.tone, ToneStyle::Formal));
+        assert!(matches!(req.tone, ToneRef::BuiltIn(ToneStyle::Formal)));
 
         let json = r#"{"text": "hello", "tone": "friendly"}"#;
         let req: PolishRequest = serde_json::from_str(json).unwrap();
-        assert!(matches!(req.tone, ToneStyle::Friendly));
+        assert!(matches!(req.tone, ToneRef::BuiltIn(ToneStyle::Friendly)));
 
         let json = r#"{"text": "hello", "tone": "concise"}"#;
         let req: PolishRequest = serde_json::from_str(json).unwrap();
-        assert!(matches!(req.tone, ToneStyle::Concise));
+        assert!(matches!(req.tone, ToneRef::BuiltIn(ToneStyle::Concise)));
+    }
+
+    #[test]
+    fn test_tone_ref_custom_id() {
+        let json = r#"{"text": "hello", "tone": "4f1c2d3e-0000-4000-8000-000000000000"}"#;
+        let req: PolishRequest = serde_json::from_str(json).unwrap();
+        assert!(
+            matches!(req.tone, ToneRef::Custom(id) if id == "4f1c2d3e-0000-4000-8000-000000000000")
+        );
+    }
+
+    #[test]
+    fn test_builtin_tone_ids_roundtrip() {
+        for tone in ToneStyle::ALL {
+            let parsed: ToneStyle = serde_json::from_value(tone.id().into()).unwrap();
+            assert_eq!(parsed.id(), tone.id());
+        }
+    }
+
+    #[test]
+    fn test_custom_tone_prompt_includes_examples() {
+        let tone = CustomTone {
+            id: "t1".to_string(),
+            name: "Pirate".to_string(),
+            instructions: "Talk like a pirate.".to_string(),
+            examples: vec![ToneExample {
+                input: "hello everyone".to_string(),
+                output: "Ahoy, mateys!".to_string(),
+            }],
+            created_at: 0,
+            updated_at: 0,
+        };
+
+        let prompt = tone.system_prompt();
+        assert!(prompt.contains("Talk like a pirate."));
+        assert!(prompt.contains("Input: hello everyone\nOutput: Ahoy, mateys!"));
+        assert!(prompt.contains("ONLY the rewritten text"));
+    }
+
+    #[test]
+    fn test_tone_info_from_builtin() {
+        let json = serde_json::to_string(&ToneInfo::from(ToneStyle::Friendly)).unwrap();
+        assert_eq!(
+            json,
+            r#"{"id":"friendly","name":"Friendly","builtin":true}"#
+        );
+    }
+
+    #[test]
+    fn test_tone_ref_id() {
+        assert_eq!(ToneRef::BuiltIn(ToneStyle::Formal).id(), "formal");
+        assert_eq!(ToneRef::Custom("abc".to_string()).id(), "abc");
+    }
+
+    #[test]
+    fn test_note_input_defaults() {
+        let json = r#"{"raw_text": "um hi", "polished_text": "Hi.", "tone": "casual"}"#;
+        let input: NoteInput = serde_json::from_str(json).unwrap();
+        assert!(input.created_at.is_none());
+        assert!(input.updated_at.is_none());
+        assert!(!input.deleted);
+    }
+
+    #[test]
+    fn test_polish_request_stream_flag() {
+        let json = r#"{"text": "hello", "tone": "casual"}"#;
+        let req: PolishRequest = serde_json::from_str(json).unwrap();
+        assert!(!req.stream);
+
+        let json = r#"{"text": "hello", "tone": "casual", "stream": true}"#;
+        let req: PolishRequest = serde_json::from_str(json).unwrap();
+        assert!(req.stream);
+    }
+
+    #[test]
+    fn test_polish_response_omits_missing_usage() {
+        let response = PolishResponse {
+            polished: "Hi.".to_string(),
+            usage: None,
+            note_id: None,
+            chunks: 1,
+            source_language: None,
+            output_language: None,
+            email: None,
+            summary: SummaryFields::default(),
+            glossary_missing: Vec::new(),
+            diff: None,
+            served_by: None,
+            quota: None,
+        };
+        let json = serde_json::to_string(&response).unwrap();
+        assert_eq!(json, r#"{"polished":"Hi.","chunks":1}"#);
+    }
+
+    #[test]
+    fn test_polish_request_format_defaults_to_plain() {
+        let req: PolishRequest = serde_json::from_str(r#"{"text":"hi","tone":"casual"}"#).unwrap();
+        assert_eq!(req.format, OutputFormat::Plain);
+
+        let req: PolishRequest =
+            serde_json::from_str(r#"{"text":"hi","tone":"casual","format":"bullet_list"}"#)
+                .unwrap();
+        assert_eq!(req.format, OutputFormat::BulletList);
+    }
+
+    #[test]
+    fn test_output_format_apply() {
+        let tone = ToneStyle::Casual.system_prompt();
+        assert_eq!(OutputFormat::Plain.apply(tone, false), tone);
+
+        let email = OutputFormat::Email.apply(tone, false);
+        assert!(email.starts_with(tone));
+        assert!(email.contains("Subject: "));
+
+        let continuation = OutputFormat::Email.apply(tone, true);
+        assert!(continuation.contains("Do not add a subject line"));
+    }
+
+    #[test]
+    fn test_email_parts_parse() {
+        let parts = EmailParts::parse("Subject: Meeting moved\n\nHi team,\n\nIt's at 3pm now.");
+        assert_eq!(parts.subject, "Meeting moved");
+        assert_eq!(parts.body, "Hi team,\n\nIt's at 3pm now.");
+
+        let parts = EmailParts::parse("**SUBJECT: Lunch**\nAnyone hungry?");
+        assert_eq!(parts.subject, "Lunch");
+        assert_eq!(parts.body, "Anyone hungry?");
+    }
+
+    #[test]
+    fn test_email_parts_parse_without_subject() {
+        let parts = EmailParts::parse("Hi team, the meeting moved.");
+        assert_eq!(parts.subject, "");
+        assert_eq!(parts.body, "Hi team, the meeting moved.");
+    }
+
+    #[test]
+    fn test_summary_fields_select() {
+        let summary = NoteSummary {
+            title: "Standup".to_string(),
+            summary: "Team synced.".to_string(),
+            topics: vec!["planning".to_string()],
+        };
+
+        let fields = SummaryFields::select(summary.clone(), true, false);
+        assert_eq!(
+            serde_json::to_string(&fields).unwrap(),
+            r#"{"title":"Standup"}"#
+        );
+
+        let fields = SummaryFields::select(summary, false, true);
+        assert_eq!(fields.title, None);
+        assert_eq!(fields.topics, Some(vec!["planning".to_string()]));
+    }
+
+    #[test]
+    fn test_plan_parse_defaults_to_free() {
+        assert_eq!(Plan::parse("pro"), Plan::Pro);
+        assert_eq!(Plan::parse("team"), Plan::Team);
+        assert_eq!(Plan::parse("enterprise"), Plan::Free);
+        assert!(Plan::Pro.monthly_token_allowance() > Plan::Free.monthly_token_allowance());
+    }
+
+    #[test]
+    fn test_quota_remaining_saturates() {
+        let mut quota = Quota::new(Plan::Free, 99_000, 0);
+        assert_eq!(quota.remaining, 1_000);
+        assert!(!quota.is_exhausted());
+
+        quota.consume(1_500);
+        assert_eq!(quota.used, 100_500);
+        assert_eq!(quota.remaining, 0);
+        assert!(quota.is_exhausted());
+
+        assert!(Quota::new(Plan::Free, 250_000, 0).is_exhausted());
+    }
+
+    #[test]
+    fn test_token_usage_billed_rounds_up() {
+        let usage = TokenUsage {
+            prompt_tokens: 70,
+            completion_tokens: 31,
+            total_tokens: 101,
+        };
+        assert_eq!(usage.billed(1.0), 101);
+        assert_eq!(usage.billed(4.0), 404);
+        assert_eq!(usage.billed(0.5), 51);
+    }
+
+    #[test]
+    fn test_me_response_flattens_user() {
+        let me = MeResponse {
+            user: UserInfo {
+                id: "u1".to_string(),
+                email: "a@b.c".to_string(),
+                email_verified: true,
+            },
+            quota: Quota::new(Plan::Pro, 0, 1700000000),
+            settings: UserSettings::default(),
+        };
+        let json = serde_json::to_value(&me).unwrap();
+        assert_eq!(json["id"], "u1");
+        assert_eq!(json["settings"]["redact_pii"], false);
+        assert_eq!(json["settings"]["redact_pii_required"], false);
+        assert_eq!(json["quota"]["plan"], "pro");
+        assert_eq!(json["quota"]["remaining"], 2_000_000);
     }
 
     #[test]
@@ -228,6 +1224,11 @@ mod tests {
         let claims = TokenClaims {
             sub: "user-123".to_string(),
             exp: 1700000000,
+            sid: None,
+            iat: None,
+            nbf: None,
+            iss: None,
+            aud: None,
         };
 
         let json = serde_json::to_string(&claims).unwrap();
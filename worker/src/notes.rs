@@ -0,0 +1,496 @@
+use crate::auth::{extract_and_verify_token, get_query_param};
+use crate::error::ApiError;
+use crate::models::{ApiResponse, Note, NoteInput, NoteList, NoteSyncResult};
+use worker::*;
+
+const MAX_NOTE_TEXT_LENGTH: usize = 100_000;
+const MAX_TONE_ID_LENGTH: usize = 100;
+const MAX_NOTE_TITLE_LENGTH: usize = 200;
+const MAX_NOTE_ID_LENGTH: usize = 64;
+const DEFAULT_PAGE_SIZE: u32 = 100;
+const MAX_PAGE_SIZE: u32 = 500;
+/// How far a client clock may run ahead before its `updated_at` is rejected
+const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+const NOTE_COLUMNS: &str =
+    "id, raw_text, polished_text, tone, title, created_at, updated_at, deleted, synced_at";
+
+/// A position in the change feed. `synced_at` isn't unique, so the note id
+/// breaks ties between notes synced in the same millisecond.
+#[derive(Debug, PartialEq)]
+struct SyncCursor {
+    synced_at: i64,
+    /// Absent in bare-timestamp cursors from older clients
+    id: Option<String>,
+}
+
+impl SyncCursor {
+    /// `<synced_at>:<id>`, or a bare `<synced_at>`
+    fn parse(value: &str) -> Option<Self> {
+        let (synced_at, id) = match value.split_once(':') {
+            Some((synced_at, id)) => (synced_at, Some(id.to_string())),
+            None => (value, None),
+        };
+        Some(Self {
+            synced_at: synced_at.parse().ok()?,
+            id,
+        })
+    }
+
+    fn after(note: &Note) -> Self {
+        Self {
+            synced_at: note.synced_at,
+            id: Some(note.id.clone()),
+        }
+    }
+}
+
+impl std::fmt::Display for SyncCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.id {
+            Some(id) => write!(f, "{}:{}", self.synced_at, id),
+            None => write!(f, "{}", self.synced_at),
+        }
+    }
+}
+
+/// List notes. With `since`, returns every change after that cursor including
+/// soft-deleted tombstones; without it, returns live notes only.
+pub async fn list_notes(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
+        Ok(id) => id,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let url = req.url()?;
+    let since = match get_query_param(&url, "since").map(|s| SyncCursor::parse(&s)) {
+        Some(Some(since)) => Some(since),
+        Some(None) => {
+            return ApiError::validation("Invalid since cursor").into_response();
+        }
+        None => None,
+    };
+    let limit = get_query_param(&url, "limit")
+        .and_then(|l| l.parse::<u32>().ok())
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let db = ctx.env.d1("DB")?;
+
+    let statement = match &since {
+        Some(SyncCursor {
+            synced_at,
+            id: Some(id),
+        }) => db
+            .prepare(format!(
+                "SELECT {} FROM notes WHERE user_id = ?1 AND (synced_at > ?2 OR (synced_at = ?2 AND id > ?3)) ORDER BY synced_at, id LIMIT ?4",
+                NOTE_COLUMNS
+            ))
+            .bind(&[
+                user_id.into(),
+                (*synced_at as f64).into(),
+                id.into(),
+                (limit as f64).into(),
+            ])?,
+        Some(SyncCursor {
+            synced_at,
+            id: None,
+        }) => db
+            .prepare(format!(
+                "SELECT {} FROM notes WHERE user_id = ?1 AND synced_at > ?2 ORDER BY synced_at, id LIMIT ?3",
+                NOTE_COLUMNS
+            ))
+            .bind(&[
+                user_id.into(),
+                (*synced_at as f64).into(),
+                (limit as f64).into(),
+            ])?,
+        None => db
+            .prepare(format!(
+                "SELECT {} FROM notes WHERE user_id = ?1 AND deleted = 0 ORDER BY synced_at, id LIMIT ?2",
+                NOTE_COLUMNS
+            ))
+            .bind(&[user_id.into(), (limit as f64).into()])?,
+    };
+
+    let rows = statement.all().await?.results::<serde_json::Value>()?;
+    let notes: Vec<Note> = rows.iter().map(note_from_row).collect();
+
+    let cursor = notes
+        .last()
+        .map(SyncCursor::after)
+        .or(since)
+        .map(|c| c.to_string())
+        .unwrap_or_else(|| "0".to_string());
+
+    Response::from_json(&ApiResponse::success(NoteList {
+        has_more: notes.len() == limit as usize,
+        notes,
+        cursor,
+    }))
+}
+
+pub async fn get_note(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
+        Ok(id) => id,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let note_id = ctx.param("id").cloned().unwrap_or_default();
+    let db = ctx.env.d1("DB")?;
+
+    match find_note(&db, &user_id, &note_id).await? {
+        Some(note) if !note.deleted => Response::from_json(&ApiResponse::success(note)),
+        _ => ApiError::NotFound("Note not found".to_string()).into_response(),
+    }
+}
+
+/// Create a note with a server-generated id
+pub async fn create_note(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
+        Ok(id) => id,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let body: NoteInput = match req.json().await {
+        Ok(b) => b,
+        Err(_) => {
+            return ApiError::validation("Invalid request body").into_response();
+        }
+    };
+
+    if let Err(e) = validate_note(&body, chrono::Utc::now().timestamp()) {
+        return ApiError::validation(e).into_response();
+    }
+
+    let db = ctx.env.d1("DB")?;
+    let note_id = uuid::Uuid::new_v4().to_string();
+    save_note(&db, &user_id, &note_id, &body).await?;
+
+    match find_note(&db, &user_id, &note_id).await? {
+        Some(note) => Response::from_json(&ApiResponse::success(note)).map(|r| r.with_status(201)),
+        None => ApiError::Internal("Failed to save note".to_string()).into_response(),
+    }
+}
+
+/// Create or update a note under a client-chosen id, last writer (by `updated_at`) wins
+pub async fn upsert_note(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
+        Ok(id) => id,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let note_id = ctx.param("id").cloned().unwrap_or_default();
+    if note_id.is_empty() || note_id.len() > MAX_NOTE_ID_LENGTH {
+        return ApiError::validation("Invalid note id").into_response();
+    }
+
+    let body: NoteInput = match req.json().await {
+        Ok(b) => b,
+        Err(_) => {
+            return ApiError::validation("Invalid request body").into_response();
+        }
+    };
+
+    if let Err(e) = validate_note(&body, chrono::Utc::now().timestamp()) {
+        return ApiError::validation(e).into_response();
+    }
+
+    let db = ctx.env.d1("DB")?;
+    let applied = save_note(&db, &user_id, &note_id, &body).await?;
+
+    match find_note(&db, &user_id, &note_id).await? {
+        Some(note) => Response::from_json(&ApiResponse::success(NoteSyncResult { note, applied })),
+        // The id exists but belongs to someone else
+        None => ApiError::Conflict("Note id already in use".to_string()).into_response(),
+    }
+}
+
+/// Soft-delete a note so the tombstone syncs to other devices.
+/// Accepts an optional `updated_at` query param for last-writer-wins.
+pub async fn delete_note(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
+        Ok(id) => id,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let note_id = ctx.param("id").cloned().unwrap_or_default();
+    let now = chrono::Utc::now();
+    let updated_at = get_query_param(&req.url()?, "updated_at")
+        .and_then(|u| u.parse::<i64>().ok())
+        .unwrap_or(now.timestamp());
+
+    if updated_at > now.timestamp() + MAX_CLOCK_SKEW_SECS {
+        return ApiError::validation("updated_at is in the future").into_response();
+    }
+
+    let db = ctx.env.d1("DB")?;
+
+    db.prepare("UPDATE notes SET deleted = 1, updated_at = ?1, synced_at = ?2 WHERE id = ?3 AND user_id = ?4 AND updated_at <= ?1")
+        .bind(&[
+            (updated_at as f64).into(),
+            (now.timestamp_millis() as f64).into(),
+            note_id.clone().into(),
+            user_id.clone().into(),
+        ])?
+        .run()
+        .await?;
+
+    match find_note(&db, &user_id, &note_id).await? {
+        Some(note) => {
+            let applied = note.deleted;
+            Response::from_json(&ApiResponse::success(NoteSyncResult { note, applied }))
+        }
+        None => ApiError::NotFound("Note not found".to_string()).into_response(),
+    }
+}
+
+/// Insert or update a note, keeping the stored version if it has a newer `updated_at`.
+/// Returns whether the write was applied.
+pub async fn save_note(
+    db: &D1Database,
+    user_id: &str,
+    note_id: &str,
+    input: &NoteInput,
+) -> Result<bool> {
+    let now = chrono::Utc::now();
+    let updated_at = input.updated_at.unwrap_or(now.timestamp());
+    let created_at = input.created_at.unwrap_or(updated_at);
+
+    let result = db
+        .prepare("INSERT INTO notes (id, user_id, raw_text, polished_text, tone, created_at, updated_at, deleted, synced_at, title) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10) \
+            ON CONFLICT(id) DO UPDATE SET raw_text = excluded.raw_text, polished_text = excluded.polished_text, tone = excluded.tone, \
+            title = COALESCE(excluded.title, notes.title), \
+            created_at = excluded.created_at, updated_at = excluded.updated_at, deleted = excluded.deleted, synced_at = excluded.synced_at \
+            WHERE notes.user_id = excluded.user_id AND notes.updated_at <= excluded.updated_at")
+        .bind(&[
+            note_id.into(),
+            user_id.into(),
+            input.raw_text.clone().into(),
+            input.polished_text.clone().into(),
+            input.tone.clone().into(),
+            (created_at as f64).into(),
+            (updated_at as f64).into(),
+            (if input.deleted { 1.0 } else { 0.0 }).into(),
+            (now.timestamp_millis() as f64).into(),
+            match &input.title {
+                Some(title) => title.trim().into(),
+                None => wasm_bindgen::JsValue::NULL,
+            },
+        ])?
+        .run()
+        .await?;
+
+    Ok(result
+        .meta()?
+        .and_then(|m| m.changes)
+        .is_some_and(|changes| changes > 0))
+}
+
+/// Set a generated title on a live note without touching its `updated_at`, so a
+/// concurrent client edit still wins. Bumps `synced_at` so other devices pick it up.
+pub async fn set_note_title(
+    db: &D1Database,
+    user_id: &str,
+    note_id: &str,
+    title: &str,
+) -> Result<bool> {
+    let result = db
+        .prepare("UPDATE notes SET title = ?1, synced_at = ?2 WHERE id = ?3 AND user_id = ?4 AND deleted = 0")
+        .bind(&[
+            title.into(),
+            (chrono::Utc::now().timestamp_millis() as f64).into(),
+            note_id.into(),
+            user_id.into(),
+        ])?
+        .run()
+        .await?;
+
+    Ok(result
+        .meta()?
+        .and_then(|m| m.changes)
+        .is_some_and(|changes| changes > 0))
+}
+
+async fn find_note(db: &D1Database, user_id: &str, note_id: &str) -> Result<Option<Note>> {
+    let row = db
+        .prepare(format!(
+            "SELECT {} FROM notes WHERE id = ?1 AND user_id = ?2",
+            NOTE_COLUMNS
+        ))
+        .bind(&[note_id.into(), user_id.into()])?
+        .first::<serde_json::Value>(None)
+        .await?;
+
+    Ok(row.as_ref().map(note_from_row))
+}
+
+fn note_from_row(row: &serde_json::Value) -> Note {
+    Note {
+        id: row["id"].as_str().unwrap_or("").to_string(),
+        raw_text: row["raw_text"].as_str().unwrap_or("").to_string(),
+        polished_text: row["polished_text"].as_str().unwrap_or("").to_string(),
+        tone: row["tone"].as_str().unwrap_or("").to_string(),
+        title: row["title"].as_str().map(str::to_string),
+        created_at: row["created_at"].as_f64().unwrap_or(0.0) as i64,
+        updated_at: row["updated_at"].as_f64().unwrap_or(0.0) as i64,
+        deleted: row["deleted"].as_f64().unwrap_or(0.0) != 0.0,
+        synced_at: row["synced_at"].as_f64().unwrap_or(0.0) as i64,
+    }
+}
+
+fn validate_note(input: &NoteInput, now: i64) -> std::result::Result<(), String> {
+    if input.raw_text.chars().count() > MAX_NOTE_TEXT_LENGTH
+        || input.polished_text.chars().count() > MAX_NOTE_TEXT_LENGTH
+    {
+        return Err(format!(
+            "Note text must be at most {} characters",
+            MAX_NOTE_TEXT_LENGTH
+        ));
+    }
+
+    let tone = input.tone.trim();
+    if tone.is_empty() || tone.len() > MAX_TONE_ID_LENGTH {
+        return Err("Invalid tone".to_string());
+    }
+
+    if input
+        .title
+        .as_ref()
+        .is_some_and(|t| t.chars().count() > MAX_NOTE_TITLE_LENGTH)
+    {
+        return Err(format!(
+            "Note title must be at most {} characters",
+            MAX_NOTE_TITLE_LENGTH
+        ));
+    }
+
+    if input.created_at.is_some_and(|t| t < 0) || input.updated_at.is_some_and(|t| t < 0) {
+        return Err("Timestamps must be Unix seconds".to_string());
+    }
+
+    // A fast client clock would otherwise win every future conflict
+    if input
+        .updated_at
+        .is_some_and(|t| t > now + MAX_CLOCK_SKEW_SECS)
+    {
+        return Err("updated_at is in the future".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input() -> NoteInput {
+        NoteInput {
+            raw_text: "um so hi".to_string(),
+            polished_text: "Hi.".to_string(),
+            tone: "casual".to_string(),
+            title: None,
+            created_at: Some(1_700_000_000),
+            updated_at: Some(1_700_000_100),
+            deleted: false,
+        }
+    }
+
+    #[test]
+    fn test_validate_note_ok() {
+        assert!(validate_note(&input(), 1_700_000_100).is_ok());
+    }
+
+    #[test]
+    fn test_validate_note_rejects_future_updated_at() {
+        let now = 1_700_000_000;
+        let mut note = input();
+
+        note.updated_at = Some(now + MAX_CLOCK_SKEW_SECS);
+        assert!(validate_note(&note, now).is_ok());
+
+        note.updated_at = Some(now + MAX_CLOCK_SKEW_SECS + 1);
+        assert!(validate_note(&note, now).is_err());
+    }
+
+    #[test]
+    fn test_validate_note_rejects_bad_fields() {
+        let mut note = input();
+        note.tone = " ".to_string();
+        assert!(validate_note(&note, 1_700_000_100).is_err());
+
+        let mut note = input();
+        note.raw_text = "x".repeat(MAX_NOTE_TEXT_LENGTH + 1);
+        assert!(validate_note(&note, 1_700_000_100).is_err());
+
+        let mut note = input();
+        note.created_at = Some(-1);
+        assert!(validate_note(&note, 1_700_000_100).is_err());
+    }
+
+    #[test]
+    fn test_note_from_row() {
+        let row = serde_json::json!({
+            "id": "n1",
+            "raw_text": "um hi",
+            "polished_text": "Hi.",
+            "tone": "casual",
+            "created_at": 1700000000.0,
+            "updated_at": 1700000050.0,
+            "deleted": 1.0,
+            "synced_at": 1700000050123.0
+        });
+
+        let note = note_from_row(&row);
+        assert_eq!(note.id, "n1");
+        assert!(note.deleted);
+        assert_eq!(note.updated_at, 1_700_000_050);
+        assert_eq!(note.synced_at, 1_700_000_050_123);
+        assert_eq!(note.title, None);
+    }
+
+    #[test]
+    fn test_note_from_row_with_title() {
+        let row = serde_json::json!({ "id": "n1", "title": "Standup notes" });
+        assert_eq!(note_from_row(&row).title.as_deref(), Some("Standup notes"));
+    }
+
+    #[test]
+    fn test_sync_cursor_round_trip() {
+        let cursor = SyncCursor::parse("1700000050123:n1").unwrap();
+        assert_eq!(cursor.synced_at, 1_700_000_050_123);
+        assert_eq!(cursor.id.as_deref(), Some("n1"));
+        assert_eq!(cursor.to_string(), "1700000050123:n1");
+
+        // Client-chosen ids may themselves contain colons
+        let cursor = SyncCursor::parse("5:a:b").unwrap();
+        assert_eq!(cursor.id.as_deref(), Some("a:b"));
+
+        let legacy = SyncCursor::parse("1700000050123").unwrap();
+        assert_eq!(legacy.id, None);
+        assert_eq!(legacy.to_string(), "1700000050123");
+
+        assert_eq!(SyncCursor::parse("abc"), None);
+        assert_eq!(SyncCursor::parse(":n1"), None);
+    }
+
+    #[test]
+    fn test_validate_note_title_length() {
+        let mut note = input();
+        note.title = Some("x".repeat(MAX_NOTE_TITLE_LENGTH));
+        assert!(validate_note(&note, 1_700_000_000).is_ok());
+
+        note.title = Some("x".repeat(MAX_NOTE_TITLE_LENGTH + 1));
+        assert!(validate_note(&note, 1_700_000_000).is_err());
+    }
+}
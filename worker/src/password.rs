@@ -0,0 +1,288 @@
+use crate::access::RATE_LIMIT_PERIOD_SECS;
+use crate::auth::{MIN_PASSWORD_LENGTH, hash_password};
+use crate::error::ApiError;
+use crate::mailer::{ConfiguredMailer, Email, Mailer, app_url, cooldown_wait};
+use crate::models::{ApiResponse, ForgotPasswordRequest, ResetPasswordRequest};
+use crate::refresh::{hash_token, new_token};
+use crate::sessions;
+use worker::*;
+
+const RESET_TOKEN_TTL_SECS: i64 = 3600;
+/// Minimum gap between reset emails to one account
+const RESET_COOLDOWN_SECS: i64 = 60;
+
+/// Guard for the statements of a reset: the link with id `?3` is still unused
+const UNUSED_LINK: &str =
+    "EXISTS (SELECT 1 FROM password_resets WHERE id = ?3 AND used_at IS NULL)";
+
+/// A stored reset token, known only by its hash
+#[derive(Debug, PartialEq)]
+pub struct ResetRecord {
+    pub id: String,
+    pub user_id: String,
+    pub expires_at: i64,
+    pub used_at: Option<i64>,
+}
+
+impl ResetRecord {
+    fn from_row(row: &serde_json::Value) -> Option<Self> {
+        Some(Self {
+            id: row["id"].as_str()?.to_string(),
+            user_id: row["user_id"].as_str()?.to_string(),
+            expires_at: row["expires_at"].as_f64()? as i64,
+            used_at: row["used_at"].as_f64().map(|t| t as i64),
+        })
+    }
+
+    pub fn is_usable(&self, now: i64) -> bool {
+        self.used_at.is_none() && now < self.expires_at
+    }
+}
+
+fn reset_email(to: &str, link: &str) -> Email {
+    Email {
+        to: to.to_string(),
+        subject: "Reset your mumble.fish password".to_string(),
+        text: format!(
+            "Someone asked to reset the password for your mumble.fish account. Choose a new one here:\n\n{}\n\nThe link expires in an hour and works once. Resetting signs out all your devices. If you didn't ask, you can ignore this email.",
+            link
+        ),
+    }
+}
+
+/// Email the plaintext token as a link; only its hash is stored
+async fn send_reset(mailer: &impl Mailer, app_url: &str, email: &str, token: &str) -> Result<()> {
+    let link = format!("{}/reset-password?token={}", app_url, token);
+    mailer.send(&reset_email(email, &link)).await
+}
+
+/// Email a reset link if the address has an account. The response is the same
+/// either way, and the lookup and sending happen after it is sent, so neither
+/// its body nor its timing says who has one.
+pub async fn forgot(
+    mut req: Request,
+    ctx: RouteContext<()>,
+    worker_ctx: &Context,
+) -> Result<Response> {
+    let body: ForgotPasswordRequest = match req.json().await {
+        Ok(b) => b,
+        Err(_) => {
+            return ApiError::validation("Invalid request body").into_response();
+        }
+    };
+
+    let client = req
+        .headers()
+        .get("CF-Connecting-IP")?
+        .unwrap_or_else(|| "unknown".to_string());
+    if !ctx
+        .rate_limiter("RATE_LIMIT")?
+        .limit(format!("password-forgot:{}", client))
+        .await?
+        .success
+    {
+        return ApiError::RateLimited {
+            retry_after: RATE_LIMIT_PERIOD_SECS,
+        }
+        .into_response();
+    }
+
+    let env = ctx.env.clone();
+    let email = body.email.trim().to_string();
+    worker_ctx.wait_until(async move {
+        if let Err(e) = request_reset(&env, &email).await {
+            console_error!("Failed to send password reset email: {:?}", e);
+        }
+    });
+
+    Response::from_json(&ApiResponse::success(serde_json::json!({ "sent": true })))
+}
+
+/// Start a reset for the account with this address, unless there is none or
+/// one was started too recently
+async fn request_reset(env: &Env, email: &str) -> Result<()> {
+    let db = env.d1("DB")?;
+    let Some(user) = db
+        .prepare("SELECT id, email, (SELECT MAX(created_at) FROM password_resets WHERE user_id = users.id) AS last_reset_at FROM users WHERE email = ?1")
+        .bind(&[email.into()])?
+        .first::<serde_json::Value>(None)
+        .await?
+    else {
+        return Ok(());
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    let last_reset_at = user["last_reset_at"].as_f64().map(|t| t as i64);
+    if cooldown_wait(last_reset_at, RESET_COOLDOWN_SECS, now).is_some() {
+        return Ok(());
+    }
+    let user_id = user["id"].as_str().unwrap_or_default();
+    let email = user["email"].as_str().unwrap_or_default();
+    start_reset(&db, env, user_id, email, now).await
+}
+
+async fn start_reset(
+    db: &D1Database,
+    env: &Env,
+    user_id: &str,
+    email: &str,
+    now: i64,
+) -> Result<()> {
+    // Opportunistic cleanup: expired tokens can never be used again
+    if let Err(e) = db
+        .prepare("DELETE FROM password_resets WHERE expires_at < ?1")
+        .bind(&[(now as f64).into()])?
+        .run()
+        .await
+    {
+        console_log!("Failed to cleanup expired password resets: {:?}", e);
+    }
+
+    let token = new_token();
+    db.prepare("INSERT INTO password_resets (id, user_id, token_hash, created_at, expires_at) VALUES (?1, ?2, ?3, ?4, ?5)")
+        .bind(&[
+            uuid::Uuid::new_v4().to_string().into(),
+            user_id.into(),
+            hash_token(&token).into(),
+            (now as f64).into(),
+            ((now + RESET_TOKEN_TTL_SECS) as f64).into(),
+        ])?
+        .run()
+        .await?;
+
+    send_reset(
+        &ConfiguredMailer::from_env(env)?,
+        &app_url(env),
+        email,
+        &token,
+    )
+    .await
+}
+
+/// Set a new password from the token in a reset link and sign out every session
+pub async fn reset(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let body: ResetPasswordRequest = match req.json().await {
+        Ok(b) => b,
+        Err(_) => {
+            return ApiError::validation("Invalid request body").into_response();
+        }
+    };
+
+    if body.password.len() < MIN_PASSWORD_LENGTH {
+        return ApiError::validation("Password must be at least 8 characters").into_response();
+    }
+
+    let db = ctx.env.d1("DB")?;
+    let now = chrono::Utc::now().timestamp();
+
+    let record = db
+        .prepare(
+            "SELECT id, user_id, expires_at, used_at FROM password_resets WHERE token_hash = ?1",
+        )
+        .bind(&[hash_token(body.token.trim()).into()])?
+        .first::<serde_json::Value>(None)
+        .await?
+        .as_ref()
+        .and_then(ResetRecord::from_row);
+
+    let Some(record) = record.filter(|r| r.is_usable(now)) else {
+        return ApiError::validation("Invalid or expired reset link").into_response();
+    };
+
+    // One batch, so a failure leaves the link usable and nothing changed. Each
+    // statement only applies while this link is unused and the link is claimed
+    // last, so of two concurrent resets with one link only the first succeeds.
+    // Following the link proved ownership of the address; other links sent
+    // before this reset stop working and every session is signed out.
+    let now_value: wasm_bindgen::JsValue = (now as f64).into();
+    let mut statements = vec![
+        db.prepare(format!(
+            "UPDATE users SET password_hash = ?1, email_verified_at = COALESCE(email_verified_at, ?2) WHERE id = ?4 AND {}",
+            UNUSED_LINK
+        ))
+        .bind(&[
+            hash_password(&body.password)?.into(),
+            now_value.clone(),
+            record.id.clone().into(),
+            record.user_id.clone().into(),
+        ])?,
+    ];
+    statements.extend(sessions::revoke_all_statements_if(
+        &db,
+        &record.user_id,
+        now,
+        UNUSED_LINK,
+        &record.id,
+    )?);
+    statements.push(
+        db.prepare(
+            "UPDATE password_resets SET used_at = ?1 WHERE user_id = ?2 AND used_at IS NULL",
+        )
+        .bind(&[now_value, record.user_id.clone().into()])?,
+    );
+    let results = db.batch(statements).await?;
+
+    let reset = match results.first() {
+        Some(result) => result
+            .meta()?
+            .and_then(|m| m.changes)
+            .is_some_and(|changes| changes > 0),
+        None => false,
+    };
+    if !reset {
+        return ApiError::validation("Invalid or expired reset link").into_response();
+    }
+
+    Response::from_json(&ApiResponse::success(serde_json::json!({ "reset": true })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mailer::MemoryMailer;
+    use crate::testing::block_on;
+
+    #[test]
+    fn test_send_reset() {
+        let mailer = MemoryMailer::default();
+        let token = new_token();
+        block_on(send_reset(
+            &mailer,
+            "https://app.test",
+            "ada@example.com",
+            &token,
+        ))
+        .unwrap();
+
+        let sent = mailer.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "ada@example.com");
+        assert!(
+            sent[0]
+                .text
+                .contains(&format!("https://app.test/reset-password?token={}", token))
+        );
+        assert!(!sent[0].text.contains(&hash_token(&token)));
+    }
+
+    #[test]
+    fn test_from_row_and_is_usable() {
+        let row = serde_json::json!({
+            "id": "r1",
+            "user_id": "u1",
+            "expires_at": 2000.0,
+            "used_at": null,
+        });
+        let record = ResetRecord::from_row(&row).unwrap();
+        assert_eq!((record.id.as_str(), record.user_id.as_str()), ("r1", "u1"));
+        assert!(record.is_usable(1_999));
+        assert!(!record.is_usable(2_000));
+
+        let used = ResetRecord {
+            used_at: Some(1_500),
+            ..record
+        };
+        assert!(!used.is_usable(1_600));
+        assert_eq!(ResetRecord::from_row(&serde_json::json!({})), None);
+    }
+}
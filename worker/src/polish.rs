@@ -1,137 +1,945 @@
-use crate::auth::extract_and_verify_token;
-use crate::models::{ApiResponse, PolishRequest, PolishResponse};
+use crate::access::{self, AiAccess, Estimate};
+use crate::chunking;
+use crate::diff::word_diff;
+use crate::error::ApiError;
+use crate::glossary::{self, Glossary, load_glossary};
+use crate::guard;
+use crate::language;
+use crate::models::{
+    ApiResponse, EmailParts, NoteInput, OutputFormat, PolishDelta, PolishRequest, PolishResponse,
+    Quota, ServedBy, SummaryFields, TokenUsage, ToneRef,
+};
+use crate::notes::save_note;
+use crate::provider::{
+    Completion, CompletionOptions, LlmProvider, Provider, ProviderError, Spend, SpentError,
+    StreamEvent,
+};
+use crate::redact::Redaction;
+use crate::sse::{self, SseParser};
+use crate::summarize::summarize_text;
+use crate::tones::find_tone;
+use crate::usage::PendingUsage;
+use futures_util::StreamExt;
+use futures_util::future::join_all;
+use std::collections::VecDeque;
 use worker::*;
 
-/// Max input length for hosted API (~10 mins of speech, ~2000 tokens)
+/// Max input length for a single hosted call (~10 mins of speech, ~2000 tokens).
+/// Longer texts are split into chunks of at most this size.
 const MAX_TEXT_LENGTH_HOSTED: usize = 8000;
 
-pub async fn polish(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let byok_key = req.headers().get("X-OpenAI-Key")?;
-
-    let (api_key, is_byok) = match byok_key {
-        Some(key) => (key, true),
-        None => {
-            match extract_and_verify_token(&req, &ctx) {
-                Ok(user_id) => {
-                    let rate_limiter = ctx.rate_limiter("RATE_LIMIT")?;
-                    let outcome = rate_limiter.limit(user_id).await?;
-                    if !outcome.success {
-                        return Response::from_json(&ApiResponse::<()>::error(
-                            "Rate limit exceeded",
-                        ))
-                        .map(|r| r.with_status(429));
-                    }
-                }
-                Err(e) => {
-                    return Response::from_json(&ApiResponse::<()>::error(format!(
-                        "Authentication required: {}. Use X-OpenAI-Key header for BYOK mode.",
-                        e
-                    )))
-                    .map(|r| r.with_status(401));
-                }
-            }
+/// Max chunks per hosted request (~80 mins of speech)
+const MAX_CHUNKS_HOSTED: usize = 8;
 
-            let key = ctx
-                .env
-                .secret("OPENAI_API_KEY")
-                .map(|k| k.to_string())
-                .map_err(|_| {
-                    Error::RustError("OpenAI API key not configured on server".to_string())
-                })?;
+/// Workers allow six simultaneous outbound connections per request. The text
+/// budget for every chunk is reserved up front, so this is the only bound.
+const MAX_PARALLEL_CHUNKS: usize = 6;
 
-            (key, false)
-        }
+const STITCH_PROMPT: &str = "The following text spans the boundary between two consecutive sections of the same note that were rewritten separately. Smooth the transition so it reads naturally: fix repetition, connectives and flow across the boundary, but keep the tone and wording otherwise unchanged and do not add new content. Return ONLY the revised text, no preamble or explanation.";
+
+pub async fn polish(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let access = match access::authorize(&req, &ctx).await {
+        Ok(access) => access,
+        Err(e) => return e.into_response(),
     };
 
     let body: PolishRequest = match req.json().await {
         Ok(b) => b,
         Err(_) => {
-            return Response::from_json(&ApiResponse::<()>::error("Invalid request body"))
-                .map(|r| r.with_status(400));
+            return ApiError::validation("Invalid request body").into_response();
         }
     };
 
+    let stream = body.stream || accepts_event_stream(&req);
+    match polish_text(&ctx, access, body, stream).await {
+        Ok(Polished::Streaming(response)) => Ok(response),
+        Ok(Polished::Done(response)) => Response::from_json(&ApiResponse::success(response)),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// What `polish_text` produced
+pub enum Polished {
+    /// A `text/event-stream` response already under way
+    Streaming(Response),
+    Done(Box<PolishResponse>),
+}
+
+/// Polish `body.text` for an authorized caller, either streamed or as a finished
+/// result. Shared by `/polish` and transcription requests that chain into it.
+pub async fn polish_text(
+    ctx: &RouteContext<()>,
+    mut access: AiAccess,
+    body: PolishRequest,
+    stream: bool,
+) -> std::result::Result<Polished, ApiError> {
     let trimmed_text = body.text.trim();
     if trimmed_text.is_empty() {
-        return Response::from_json(&ApiResponse::<()>::error("Text cannot be empty"))
-            .map(|r| r.with_status(400));
+        return Err(ApiError::validation("Text cannot be empty"));
+    }
+
+    // Streamed chunks are sent as they finish, before any seam could be smoothed
+    if stream && body.stitch {
+        return Err(ApiError::validation(
+            "stitch can't be combined with streaming",
+        ));
+    }
+
+    if let Some(model) = &body.model {
+        access.select_model(&ctx.env, model)?;
     }
 
+    let target_language = match body.target_language.as_deref().map(language::normalize_tag) {
+        Some(Some(tag)) => Some(tag),
+        Some(None) => {
+            return Err(ApiError::validation(
+                "Invalid target_language, expected a BCP-47 tag such as \"de\" or \"pt-BR\"",
+            ));
+        }
+        None => None,
+    };
+
+    // Misheard glossary terms are fixed before the model ever sees them
+    let glossary = match &access.user_id {
+        Some(user_id) => Glossary::new(&load_glossary(&ctx.env.d1("DB")?, user_id).await?),
+        None => Glossary::default(),
+    };
+    let text = glossary.apply(trimmed_text);
+    let glossary_terms = glossary.terms_in(&text);
+
+    // Personal data is swapped for placeholders before the text leaves the worker
+    let mut redaction = Redaction::new(access.redacts(body.redact));
+    let text = redaction.redact(&text);
+
+    let chunks = chunking::split_into_chunks(&text, MAX_TEXT_LENGTH_HOSTED);
+
     // Enforce length limit for hosted API (BYOK has no limit)
-    if !is_byok && trimmed_text.len() > MAX_TEXT_LENGTH_HOSTED {
-        return Response::from_json(&ApiResponse::<()>::error(format!(
-            "Text too long ({} chars). Maximum is {} chars (~10 mins of speech). Use your own API key for longer texts.",
-            trimmed_text.len(),
-            MAX_TEXT_LENGTH_HOSTED
-        )))
-        .map(|r| r.with_status(400));
+    if !access.is_byok && chunks.len() > MAX_CHUNKS_HOSTED {
+        let max_length = MAX_TEXT_LENGTH_HOSTED * MAX_CHUNKS_HOSTED;
+        return Err(ApiError::Validation {
+            message: format!(
+                "Text too long ({} chars). Maximum is about {} chars (~80 mins of speech). Use your own API key for longer texts.",
+                trimmed_text.len(),
+                max_length
+            ),
+            details: Some(serde_json::json!({ "max_length": max_length })),
+        });
+    }
+
+    let tone_prompt = match &body.tone {
+        ToneRef::BuiltIn(tone) => tone.system_prompt().to_string(),
+        ToneRef::Custom(tone_id) => {
+            let Some(user_id) = &access.user_id else {
+                return Err(ApiError::Unauthorized(
+                    "Sign in to use custom tones".to_string(),
+                ));
+            };
+            let db = ctx.env.d1("DB")?;
+            match find_tone(&db, user_id, tone_id).await? {
+                Some(tone) => tone.system_prompt(),
+                None => {
+                    return Err(ApiError::validation("Unknown tone"));
+                }
+            }
+        }
+    };
+    let tone_prompt = match glossary::prompt_section(&glossary_terms) {
+        Some(section) => format!("{}\n\n{}", tone_prompt, section),
+        None => tone_prompt,
+    };
+    let tone_prompt = redaction.extend_prompt(&tone_prompt);
+    let prompts = SystemPrompts::new(&tone_prompt, body.format, target_language.as_deref());
+
+    let pending_note = if body.save {
+        let Some(user_id) = &access.user_id else {
+            return Err(ApiError::Unauthorized("Sign in to save notes".to_string()));
+        };
+        Some(PendingNote {
+            db: ctx.env.d1("DB")?,
+            user_id: user_id.clone(),
+            note_id: uuid::Uuid::new_v4().to_string(),
+            raw_text: body.text.clone(),
+            tone: body.tone.id().to_string(),
+        })
+    } else {
+        None
+    };
+
+    let mut estimate = Estimate::rewrite(text.chars().count(), chunks.len());
+    // One stitching call per seam; never with streaming, refused above
+    if body.stitch {
+        estimate.calls += chunks.len() - 1;
+    }
+    if body.include_title || body.include_summary {
+        estimate = estimate.and(Estimate::read(text.chars().count()));
+    }
+    access.check_estimate(estimate)?;
+
+    // Hosted text also draws on a per-user character budget, one unit per
+    // chunk's worth of characters, separate from the request burst limit
+    if let (Some(user_id), false) = (&access.user_id, access.is_byok) {
+        reserve_text_budget(ctx, user_id, text_budget_units(trimmed_text)).await?;
+    }
+
+    let pending_usage = PendingUsage::start(ctx.env.d1("DB")?, &access, body.tone.id());
+    let summary_options = SummaryOptions::from_request(&body);
+
+    if stream {
+        // The polish is metered before `done`; a summary is metered on its own
+        let summary = match summary_options {
+            Some(options) => Some((
+                options,
+                PendingUsage::start(ctx.env.d1("DB")?, &access, "summarize"),
+            )),
+            None => None,
+        };
+        let translator = StreamTranslator {
+            quota: access.quota,
+            cost_weight: access.cost_weight,
+            format: body.format,
+            awaiting_header: true,
+            target_language,
+            diff_source: body.diff.then(|| body.text.clone()),
+            input: guard::normalize(&body.text),
+            glossary_terms,
+            glossary,
+            redaction,
+            ..StreamTranslator::new(
+                pending_note.as_ref().map(|n| n.note_id.clone()),
+                chunks.len(),
+            )
+        };
+        let response = stream_polish(
+            access.provider,
+            prompts,
+            chunks,
+            translator,
+            pending_note,
+            pending_usage,
+            summary,
+        )
+        .await?;
+        return Ok(Polished::Streaming(response));
+    }
+
+    let chunk_count = chunks.len() as u32;
+    let (mut completion, source_language) =
+        match polish_chunks(&access.provider, &prompts, chunks, body.stitch).await {
+            Ok(result) => result,
+            Err(e) => {
+                console_error!("Provider error: {}", e);
+                pending_usage.record_spent(e.spend.clone()).await;
+                return Err(ApiError::from(e));
+            }
+        };
+
+    // The model can still reintroduce a variant or change a term's casing
+    completion.text = glossary.apply(&redaction.restore(&completion.text));
+    let glossary_missing = glossary::missing_terms(&glossary_terms, &completion.text);
+    if !glossary_missing.is_empty() {
+        // Only the count: the terms are often names, and the response has them
+        console_log!(
+            "Glossary terms lost in polishing: {}",
+            glossary_missing.len()
+        );
+    }
+
+    let mut spend = completion.spend;
+    let summary = match summary_options {
+        Some(options) => summarize_polished(
+            &access.provider,
+            &completion.text,
+            options,
+            &mut spend,
+            &mut redaction,
+        )
+        .await
+        .unwrap_or_default(),
+        None => SummaryFields::default(),
+    };
+
+    let served_by = access.provider.served_by(&spend);
+    pending_usage.record(spend.clone()).await;
+
+    let note_id = match pending_note {
+        Some(note) => note.save(&completion.text, summary.title.clone()).await,
+        None => None,
+    };
+
+    Ok(Polished::Done(Box::new(PolishResponse {
+        quota: access
+            .quota
+            .map(|quota| spend_quota(quota, &spend, access.cost_weight)),
+        email: email_parts(body.format, &completion.text),
+        summary,
+        glossary_missing,
+        diff: body.diff.then(|| word_diff(&body.text, &completion.text)),
+        served_by: Some(served_by),
+        polished: completion.text,
+        usage: spend.usage(),
+        note_id,
+        chunks: chunk_count,
+        output_language: target_language.or_else(|| source_language.clone()),
+        source_language,
+    })))
+}
+
+/// Units of the character budget a hosted text takes: one per chunk's worth
+fn text_budget_units(text: &str) -> usize {
+    text.chars().count().div_ceil(MAX_TEXT_LENGTH_HOSTED)
+}
+
+/// Take `units` from the `TEXT_RATE_LIMIT` binding, refusing the request if any
+/// is denied. The most one request can need fits in an empty window, so a
+/// refused request succeeds once retried after `retry_after`.
+async fn reserve_text_budget(
+    ctx: &RouteContext<()>,
+    user_id: &str,
+    units: usize,
+) -> std::result::Result<(), ApiError> {
+    let rate_limiter = ctx.rate_limiter("TEXT_RATE_LIMIT")?;
+    for _ in 0..units {
+        if !rate_limiter.limit(user_id.to_string()).await?.success {
+            return Err(ApiError::RateLimited {
+                retry_after: access::TEXT_RATE_LIMIT_PERIOD_SECS,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The system prompt for a text's first chunk, and for the chunks that continue it
+struct SystemPrompts {
+    first: String,
+    rest: String,
+}
+
+impl SystemPrompts {
+    fn new(tone_prompt: &str, format: OutputFormat, target_language: Option<&str>) -> Self {
+        Self {
+            first: format!(
+                "{}\n\n{}",
+                format.apply(tone_prompt, false),
+                language::instructions(target_language, true)
+            ),
+            rest: format!(
+                "{}\n\n{}",
+                format.apply(tone_prompt, true),
+                language::instructions(target_language, false)
+            ),
+        }
+    }
+
+    fn for_chunk(&self, index: usize) -> &str {
+        if index == 0 { &self.first } else { &self.rest }
+    }
+}
+
+/// Polish each chunk (a few at a time), then optionally smooth the
+/// seams between them. Also returns the source language the first chunk reported.
+/// On failure, the error carries the usage of the chunks that did finish.
+async fn polish_chunks(
+    provider: &Provider,
+    prompts: &SystemPrompts,
+    chunks: Vec<String>,
+    stitch: bool,
+) -> std::result::Result<(Completion, Option<String>), SpentError> {
+    let options = CompletionOptions::default();
+    let parallelism = MAX_PARALLEL_CHUNKS;
+    let mut spend = Spend::default();
+    let mut source_language = None;
+
+    let mut polished = Vec::with_capacity(chunks.len());
+    for (wave_index, wave) in chunks.chunks(parallelism).enumerate() {
+        let results = join_all(wave.iter().enumerate().map(|(i, chunk)| {
+            let system_prompt = prompts.for_chunk(wave_index * parallelism + i);
+            guard::complete(provider, system_prompt, chunk, &options)
+        }))
+        .await;
+        let mut failed = None;
+        for result in results {
+            match result {
+                Ok(completion) => {
+                    spend.merge(completion.spend);
+                    let mut text = completion.text.as_str();
+                    if polished.is_empty() {
+                        (source_language, text) = language::split_header(text);
+                    }
+                    polished.push(text.trim().to_string());
+                }
+                Err(e) => {
+                    spend.merge(e.spend);
+                    failed.get_or_insert(e.error);
+                }
+            }
+        }
+        if let Some(error) = failed {
+            return Err(SpentError { error, spend });
+        }
+    }
+
+    if polished.len() == 1 {
+        let completion = Completion {
+            text: polished.remove(0),
+            spend,
+        };
+        return Ok((completion, source_language));
+    }
+
+    let mut seams = vec![None; polished.len() - 1];
+    if stitch {
+        seams = chunking::seams(&polished);
+        let stitched: Vec<usize> = (0..seams.len()).filter(|&i| seams[i].is_some()).collect();
+        for wave in stitched.chunks(parallelism) {
+            let results = join_all(wave.iter().map(|&i| {
+                guard::complete(
+                    provider,
+                    STITCH_PROMPT,
+                    seams[i].as_deref().unwrap_or(""),
+                    &options,
+                )
+            }))
+            .await;
+            for (&i, result) in wave.iter().zip(results) {
+                // A failed seam just falls back to a plain paragraph break
+                seams[i] = match result {
+                    Ok(completion) => {
+                        spend.merge(completion.spend);
+                        Some(completion.text)
+                    }
+                    Err(e) => {
+                        console_error!("Stitching failed: {}", e);
+                        spend.merge(e.spend);
+                        None
+                    }
+                };
+            }
+        }
     }
 
-    let polished = match call_openai(&api_key, &body).await {
-        Ok(text) => text,
+    let completion = Completion {
+        text: chunking::stitch(&polished, &seams),
+        spend,
+    };
+    Ok((completion, source_language))
+}
+
+/// Which parts of a summary a polish request asked for
+#[derive(Clone, Copy)]
+struct SummaryOptions {
+    title: bool,
+    summary: bool,
+}
+
+impl SummaryOptions {
+    fn from_request(body: &PolishRequest) -> Option<Self> {
+        (body.include_title || body.include_summary).then_some(Self {
+            title: body.include_title,
+            summary: body.include_summary,
+        })
+    }
+}
+
+/// Summarize the polished text, adding to `spend`. Failures are logged and leave
+/// the polish result without a summary.
+async fn summarize_polished(
+    provider: &Provider,
+    polished: &str,
+    options: SummaryOptions,
+    spend: &mut Spend,
+    redaction: &mut Redaction,
+) -> Option<SummaryFields> {
+    match summarize_text(provider, polished, redaction).await {
+        Ok((summary, summary_spend)) => {
+            spend.merge(summary_spend);
+            Some(SummaryFields::select(
+                summary,
+                options.title,
+                options.summary,
+            ))
+        }
         Err(e) => {
-            console_error!("OpenAI error: {:?}", e);
-            return Response::from_json(&ApiResponse::<()>::error(format!(
-                "AI processing failed: {}",
-                e
-            )))
-            .map(|r| r.with_status(500));
+            console_error!("Summarizing failed: {}", e);
+            spend.merge(e.spend);
+            None
+        }
+    }
+}
+
+fn email_parts(format: OutputFormat, polished: &str) -> Option<EmailParts> {
+    (format == OutputFormat::Email).then(|| EmailParts::parse(polished))
+}
+
+/// Charge `spend` to the quota, at `cost_weight` for usage without a backend
+fn spend_quota(mut quota: Quota, spend: &Spend, cost_weight: f64) -> Quota {
+    quota.consume(spend.billed(cost_weight));
+    quota
+}
+
+/// A polish result to persist as a note once the text is known
+struct PendingNote {
+    db: D1Database,
+    user_id: String,
+    note_id: String,
+    raw_text: String,
+    tone: String,
+}
+
+impl PendingNote {
+    /// Save the note, returning its id. Failures are logged rather than failing the polish.
+    async fn save(self, polished: &str, title: Option<String>) -> Option<String> {
+        let input = NoteInput {
+            raw_text: self.raw_text,
+            polished_text: polished.to_string(),
+            tone: self.tone,
+            title,
+            created_at: None,
+            updated_at: None,
+            deleted: false,
+        };
+
+        match save_note(&self.db, &self.user_id, &self.note_id, &input).await {
+            Ok(_) => Some(self.note_id),
+            Err(e) => {
+                console_error!("Failed to save note: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+fn accepts_event_stream(req: &Request) -> bool {
+    req.headers()
+        .get("Accept")
+        .ok()
+        .flatten()
+        .is_some_and(|accept| accept.contains("text/event-stream"))
+}
+
+/// Forward the provider's streamed deltas to the client as server-sent events.
+/// Long texts stream chunk after chunk, separated by a paragraph break.
+async fn stream_polish(
+    provider: Provider,
+    prompts: SystemPrompts,
+    chunks: Vec<String>,
+    mut translator: StreamTranslator,
+    pending_note: Option<PendingNote>,
+    pending_usage: PendingUsage,
+    summary: Option<(SummaryOptions, PendingUsage)>,
+) -> Result<Response> {
+    let mut remaining = VecDeque::from(chunks);
+    let first = remaining.pop_front().unwrap_or_default();
+
+    let upstream = match provider
+        .stream(
+            &guard::system_prompt(&prompts.first),
+            &guard::wrap(&first),
+            &CompletionOptions::default(),
+        )
+        .await
+    {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            console_error!("Provider error: {}", e);
+            pending_usage.record_spent(translator.spent()).await;
+            return ApiError::from(e).into_response();
         }
     };
+    translator.served_by = Some(provider.stream_served_by());
 
-    Response::from_json(&ApiResponse::success(PolishResponse { polished }))
-}
-
-async fn call_openai(api_key: &str, request: &PolishRequest) -> Result<String> {
-    let headers = Headers::new();
-    headers.set("Content-Type", "application/json")?;
-    headers.set("Authorization", &format!("Bearer {}", api_key))?;
-
-    // Using gpt-5-nano-2025-08-07 for fast, cost-effective text polishing
-    let openai_request = serde_json::json!({
-        "model": "gpt-5-nano-2025-08-07",
-        "messages": [
-            {
-                "role": "system",
-                "content": request.tone.system_prompt()
-            },
-            {
-                "role": "user",
-                "content": request.text
+    let state = PolishStream {
+        translator,
+        provider,
+        continuation_prompt: prompts.rest,
+        remaining,
+        upstream,
+        parser: SseParser::new(),
+        pending_note,
+        pending_usage: Some(pending_usage),
+        summary,
+        title: None,
+    };
+    let body = futures_util::stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(chunk) = state.translator.pending.pop_front() {
+                return Some((Ok::<Vec<u8>, Error>(chunk), state));
+            }
+            if state.translator.finished {
+                // Summarize and persist after the client already has the `done` event
+                if state.translator.succeeded {
+                    if let Some((options, pending_usage)) = state.summary.take() {
+                        let mut spend = Spend::default();
+                        let fields = summarize_polished(
+                            &state.provider,
+                            &state.translator.polished,
+                            options,
+                            &mut spend,
+                            &mut state.translator.redaction,
+                        )
+                        .await;
+                        match fields {
+                            Some(fields) => {
+                                pending_usage.record(spend).await;
+                                state.title = fields.title.clone();
+                                state
+                                    .translator
+                                    .pending
+                                    .push_back(sse::event("summary", &fields));
+                                continue;
+                            }
+                            None => pending_usage.record_spent(spend).await,
+                        }
+                    }
+                    if let Some(note) = state.pending_note.take() {
+                        note.save(&state.translator.polished, state.title.take())
+                            .await;
+                    }
+                }
+                return None;
             }
-        ]
+            state.poll_upstream().await;
+        }
     });
 
-    let mut init = RequestInit::new();
-    init.with_method(Method::Post);
-    init.with_headers(headers);
-    init.with_body(Some(serde_json::to_string(&openai_request)?.into()));
+    let mut response = Response::from_stream(body)?;
+    response
+        .headers_mut()
+        .set("Content-Type", "text/event-stream")?;
+    response.headers_mut().set("Cache-Control", "no-cache")?;
+    Ok(response)
+}
+
+struct PolishStream {
+    provider: Provider,
+    /// System prompt for every chunk after the first
+    continuation_prompt: String,
+    remaining: VecDeque<String>,
+    upstream: ByteStream,
+    parser: SseParser,
+    translator: StreamTranslator,
+    pending_note: Option<PendingNote>,
+    /// Taken when the polish is metered
+    pending_usage: Option<PendingUsage>,
+    /// What to summarize after `done`, and its own metering
+    summary: Option<(SummaryOptions, PendingUsage)>,
+    /// Generated title to store on the saved note
+    title: Option<String>,
+}
+
+impl PolishStream {
+    /// Read the next upstream chunk and queue the resulting client events
+    async fn poll_upstream(&mut self) {
+        let translator = &mut self.translator;
+        match self.upstream.next().await {
+            Some(Ok(bytes)) => {
+                for data in self.parser.feed(&bytes) {
+                    match self.provider.parse_stream_data(&data) {
+                        Ok(events) => events.into_iter().for_each(|e| translator.on_event(e)),
+                        Err(e) => {
+                            console_error!("Provider stream error: {}", e);
+                            translator.fail(ApiError::upstream("AI provider stream failed"));
+                        }
+                    }
+                }
+            }
+            Some(Err(e)) => {
+                console_error!("Provider stream error: {:?}", e);
+                translator.fail(ApiError::upstream("AI provider stream failed"));
+            }
+            None => translator.fail(ApiError::upstream("AI provider stream ended early")),
+        }
+
+        if self.translator.advance {
+            self.translator.advance = false;
+            self.start_next_chunk().await;
+        }
+        if self.translator.finished {
+            self.record_usage().await;
+        }
+    }
+
+    /// Meter the polish as soon as it ends, before the client gets `done` or
+    /// `error` and can disconnect. A failed stream is billed for what it used.
+    async fn record_usage(&mut self) {
+        let Some(pending_usage) = self.pending_usage.take() else {
+            return;
+        };
+        let spend = self.translator.spent();
+        if self.translator.succeeded {
+            pending_usage.record(spend).await;
+        } else {
+            pending_usage.record_spent(spend).await;
+        }
+    }
+
+    async fn start_next_chunk(&mut self) {
+        let Some(chunk) = self.remaining.pop_front() else {
+            self.translator
+                .fail(ApiError::Internal("Missing chunk".to_string()));
+            return;
+        };
+
+        match self
+            .provider
+            .stream(
+                &guard::system_prompt(&self.continuation_prompt),
+                &guard::wrap(&chunk),
+                &CompletionOptions::default(),
+            )
+            .await
+        {
+            Ok(upstream) => {
+                self.upstream = upstream;
+                self.parser = SseParser::new();
+                self.translator.served_by = Some(self.provider.stream_served_by());
+            }
+            Err(e) => {
+                console_error!("Provider error: {}", e);
+                self.translator.fail(ApiError::from(e));
+            }
+        }
+    }
+}
+
+/// Turns provider stream events into our `delta` / `done` / `error` events
+#[derive(Default)]
+struct StreamTranslator {
+    polished: String,
+    /// Usage of finished chunks, by the backend that served each
+    spend: Spend,
+    /// Usage of the chunk currently streaming
+    chunk_usage: Option<TokenUsage>,
+    note_id: Option<String>,
+    /// Hosted quota as read before the request, absent for BYOK
+    quota: Option<Quota>,
+    /// Quota tokens charged per token of the selected model
+    cost_weight: f64,
+    format: OutputFormat,
+    /// Hold output back until the first chunk's `Language:` header line is complete
+    awaiting_header: bool,
+    header_buffer: String,
+    /// Drop whitespace left over after the header
+    trim_leading: bool,
+    /// Hold output back while the chunk's first line could be a preamble
+    /// such as "Here is the rewritten text:"
+    awaiting_preamble: bool,
+    preamble_buffer: String,
+    /// The request text, normalized for the guard's phrase checks
+    input: String,
+    /// The start of the current chunk's output, checked for a refusal
+    chunk_start: String,
+    /// Output held back because it may be closing remarks, dropped if the chunk ends there
+    remarks_buffer: String,
+    source_language: Option<String>,
+    target_language: Option<String>,
+    /// The raw text to diff the result against, when the request asked for `diff`
+    diff_source: Option<String>,
+    /// Glossary spellings in the input, checked against the result
+    glossary_terms: Vec<String>,
+    /// Applied to the output as it streams, like to a non-streamed result
+    glossary: Glossary,
+    /// Placeholders to restore in the output as it streams
+    redaction: Redaction,
+    /// The provider and model of the chunk currently streaming
+    served_by: Option<ServedBy>,
+    chunks: usize,
+    chunks_left: usize,
+    pending: VecDeque<Vec<u8>>,
+    /// Set when a chunk finished and the next one should be started
+    advance: bool,
+    finished: bool,
+    succeeded: bool,
+}
+
+impl StreamTranslator {
+    fn new(note_id: Option<String>, chunks: usize) -> Self {
+        Self {
+            note_id,
+            chunks,
+            chunks_left: chunks.saturating_sub(1),
+            awaiting_preamble: true,
+            cost_weight: 1.0,
+            ..Self::default()
+        }
+    }
+
+    fn on_event(&mut self, event: StreamEvent) {
+        if self.finished {
+            return;
+        }
+
+        match event {
+            StreamEvent::Delta(text) => self.on_delta(text),
+            StreamEvent::Usage(usage) => {
+                // Some providers report input and output tokens in separate events
+                let merged = match self.chunk_usage.take() {
+                    Some(prev) => {
+                        let prompt_tokens = usage.prompt_tokens.max(prev.prompt_tokens);
+                        let completion_tokens = usage.completion_tokens.max(prev.completion_tokens);
+                        TokenUsage {
+                            prompt_tokens,
+                            completion_tokens,
+                            total_tokens: prompt_tokens + completion_tokens,
+                        }
+                    }
+                    None => usage,
+                };
+                self.chunk_usage = Some(merged);
+            }
+            StreamEvent::Done => {
+                if self.awaiting_header {
+                    self.finish_header();
+                }
+                if self.awaiting_preamble {
+                    self.finish_preamble();
+                }
+                if self.finished {
+                    return;
+                }
+                let held = std::mem::take(&mut self.remarks_buffer);
+                let kept = guard::strip_closing_remarks(&held, &self.input).to_string();
+                self.output(kept);
+                self.chunk_start.clear();
+                let held = self.redaction.flush();
+                let mut held = self.glossary.apply_partial(&held);
+                held.push_str(&self.glossary.flush());
+                self.push_delta(held);
+                self.spend
+                    .add(self.served_by.as_ref(), self.chunk_usage.take());
+
+                if self.chunks_left > 0 {
+                    self.chunks_left -= 1;
+                    self.advance = true;
+                    self.awaiting_preamble = true;
+                    self.push_delta("\n\n".to_string());
+                    return;
+                }
 
-    let req = Request::new_with_init("https://api.openai.com/v1/chat/completions", &init)?;
-    let mut resp = Fetch::Request(req).send().await?;
+                let done = PolishResponse {
+                    polished: self.polished.clone(),
+                    usage: self.spend.usage(),
+                    note_id: self.note_id.clone(),
+                    chunks: self.chunks as u32,
+                    source_language: self.source_language.clone(),
+                    output_language: self
+                        .target_language
+                        .clone()
+                        .or_else(|| self.source_language.clone()),
+                    email: email_parts(self.format, &self.polished),
+                    quota: self
+                        .quota
+                        .clone()
+                        .map(|quota| spend_quota(quota, &self.spend, self.cost_weight)),
+                    summary: SummaryFields::default(),
+                    glossary_missing: glossary::missing_terms(&self.glossary_terms, &self.polished),
+                    diff: self
+                        .diff_source
+                        .as_deref()
+                        .map(|raw| word_diff(raw, &self.polished)),
+                    served_by: self.served_by.clone(),
+                };
+                self.pending.push_back(sse::event("done", &done));
+                self.finished = true;
+                self.succeeded = true;
+            }
+        }
+    }
 
-    if resp.status_code() != 200 {
-        let error_text = resp.text().await?;
-        return Err(Error::RustError(format!(
-            "OpenAI API error ({}): {}",
-            resp.status_code(),
-            error_text
-        )));
+    /// Usage of finished chunks plus whatever the current chunk reported
+    fn spent(&self) -> Spend {
+        let mut spend = self.spend.clone();
+        spend.add(self.served_by.as_ref(), self.chunk_usage.clone());
+        spend
     }
 
-    let response_data: serde_json::Value = resp.json().await?;
+    fn on_delta(&mut self, text: String) {
+        if !self.awaiting_header {
+            return self.emit(text);
+        }
+
+        self.header_buffer.push_str(&text);
+        let line_complete = self.header_buffer.trim_start().contains('\n');
+        if line_complete || self.header_buffer.len() > language::MAX_HEADER_LENGTH {
+            self.finish_header();
+        }
+    }
 
-    let content = response_data["choices"][0]["message"]["content"]
-        .as_str()
-        .ok_or_else(|| Error::RustError("No content in response".to_string()))?
-        .to_string();
+    fn finish_header(&mut self) {
+        self.awaiting_header = false;
+        let buffered = std::mem::take(&mut self.header_buffer);
+        let (source_language, rest) = language::split_header(&buffered);
+        if source_language.is_some() {
+            self.source_language = source_language;
+            self.trim_leading = true;
+        }
+        self.emit(rest.to_string());
+    }
+
+    fn finish_preamble(&mut self) {
+        self.awaiting_preamble = false;
+        let buffered = std::mem::take(&mut self.preamble_buffer);
+        self.trim_leading = true;
+        self.emit(guard::strip_preamble(&buffered, &self.input).to_string());
+    }
 
-    Ok(content)
+    fn emit(&mut self, mut text: String) {
+        if self.awaiting_preamble {
+            self.preamble_buffer.push_str(&text);
+            if !guard::may_be_preamble(&self.preamble_buffer) {
+                self.finish_preamble();
+            }
+            return;
+        }
+        if self.trim_leading {
+            text = text.trim_start().to_string();
+            self.trim_leading = text.is_empty();
+        }
+        // Deltas already sent can't be taken back; the error tells the client to drop them
+        if self.chunk_start.len() < guard::REFUSAL_WINDOW {
+            self.chunk_start.push_str(&text);
+            if guard::is_refusal(&self.chunk_start, &self.input) {
+                return self.fail(ApiError::from(ProviderError::InvalidResponse(
+                    "Model refused to process the text".to_string(),
+                )));
+            }
+        }
+
+        self.remarks_buffer.push_str(&text);
+        let split = guard::closing_remarks_start(&self.remarks_buffer, &self.input)
+            .unwrap_or(self.remarks_buffer.len());
+        let ready: String = self.remarks_buffer.drain(..split).collect();
+        self.output(ready);
+    }
+
+    fn output(&mut self, text: String) {
+        let text = self.redaction.restore_partial(&text);
+        let text = self.glossary.apply_partial(&text);
+        self.push_delta(text);
+    }
+
+    fn push_delta(&mut self, text: String) {
+        if text.is_empty() {
+            return;
+        }
+        self.polished.push_str(&text);
+        self.pending
+            .push_back(sse::event("delta", &PolishDelta { text }));
+    }
+
+    fn fail(&mut self, error: ApiError) {
+        if self.finished {
+            return;
+        }
+        self.pending
+            .push_back(sse::event("error", &ApiResponse::<()>::error(error.body())));
+        self.finished = true;
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::{GlossaryEntry, Plan};
 
     #[test]
     fn test_max_text_length_hosted_constant() {
@@ -140,10 +948,381 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_max_text_length_reasonable_range() {
         // Should be at least 1000 chars (reasonable minimum for text polishing)
         assert!(MAX_TEXT_LENGTH_HOSTED >= 1000);
         // Should be at most 50000 chars (reasonable max to prevent abuse)
         assert!(MAX_TEXT_LENGTH_HOSTED <= 50000);
     }
+
+    #[test]
+    fn test_text_budget_units() {
+        assert_eq!(text_budget_units("short"), 1);
+        assert_eq!(text_budget_units(&"a".repeat(8_000)), 1);
+        assert_eq!(text_budget_units(&"é".repeat(8_001)), 2);
+        // The largest hosted request must fit in one window of TEXT_RATE_LIMIT
+        assert_eq!(
+            text_budget_units(&"a".repeat(MAX_TEXT_LENGTH_HOSTED * MAX_CHUNKS_HOSTED)),
+            8
+        );
+    }
+
+    fn drain(translator: &mut StreamTranslator) -> Vec<String> {
+        translator
+            .pending
+            .drain(..)
+            .map(|e| String::from_utf8(e).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_stream_translator_deltas_and_done() {
+        let mut translator = StreamTranslator::new(None, 1);
+        translator.on_event(StreamEvent::Delta("Hello".to_string()));
+        translator.on_event(StreamEvent::Delta(" there.".to_string()));
+        translator.on_event(StreamEvent::Usage(TokenUsage {
+            prompt_tokens: 10,
+            completion_tokens: 3,
+            total_tokens: 13,
+        }));
+        translator.on_event(StreamEvent::Done);
+
+        let events = drain(&mut translator);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], "event: delta\ndata: {\"text\":\"Hello\"}\n\n");
+        assert_eq!(events[1], "event: delta\ndata: {\"text\":\" there.\"}\n\n");
+        assert!(events[2].starts_with("event: done\n"));
+        assert!(events[2].contains("\"polished\":\"Hello there.\""));
+        assert!(events[2].contains("\"total_tokens\":13"));
+        assert!(!events[2].contains("note_id"));
+        assert!(translator.finished);
+        assert!(translator.succeeded);
+    }
+
+    #[test]
+    fn test_stream_translator_merges_split_usage() {
+        let mut translator = StreamTranslator::new(None, 1);
+        translator.on_event(StreamEvent::Usage(TokenUsage {
+            prompt_tokens: 20,
+            completion_tokens: 1,
+            total_tokens: 21,
+        }));
+        translator.on_event(StreamEvent::Usage(TokenUsage {
+            prompt_tokens: 0,
+            completion_tokens: 8,
+            total_tokens: 8,
+        }));
+
+        let usage = translator.chunk_usage.unwrap();
+        assert_eq!(usage.prompt_tokens, 20);
+        assert_eq!(usage.completion_tokens, 8);
+        assert_eq!(usage.total_tokens, 28);
+    }
+
+    #[test]
+    fn test_stream_translator_error_is_terminal() {
+        let mut translator = StreamTranslator::new(None, 1);
+        translator.on_event(StreamEvent::Delta("Par".to_string()));
+        translator.fail(ApiError::upstream("AI provider stream failed"));
+        translator.on_event(StreamEvent::Delta("tial".to_string()));
+        translator.on_event(StreamEvent::Done);
+
+        let events = drain(&mut translator);
+        assert_eq!(events.len(), 2);
+        assert!(events[1].starts_with("event: error\n"));
+        assert!(events[1].contains("\"success\":false"));
+        assert!(events[1].contains("\"code\":\"upstream_error\""));
+        assert!(!translator.succeeded);
+    }
+
+    #[test]
+    fn test_stream_translator_failure_keeps_spent_usage() {
+        let usage = |total_tokens| TokenUsage {
+            prompt_tokens: total_tokens,
+            completion_tokens: 0,
+            total_tokens,
+        };
+        let mut translator = StreamTranslator::new(None, 2);
+        translator.on_event(StreamEvent::Delta("Part one.".to_string()));
+        translator.on_event(StreamEvent::Usage(usage(10)));
+        translator.on_event(StreamEvent::Done);
+        translator.advance = false;
+
+        translator.on_event(StreamEvent::Delta("Part".to_string()));
+        translator.on_event(StreamEvent::Usage(usage(7)));
+        translator.fail(ApiError::upstream("AI provider stream failed"));
+
+        assert!(!translator.succeeded);
+        assert_eq!(translator.spent().usage().unwrap().total_tokens, 17);
+    }
+
+    #[test]
+    fn test_stream_translator_bills_each_chunk_at_its_backend_weight() {
+        let served_by = |model: &str, cost_weight| ServedBy {
+            provider: "openai",
+            model: model.to_string(),
+            cost_weight,
+        };
+        let usage = TokenUsage {
+            prompt_tokens: 10,
+            completion_tokens: 0,
+            total_tokens: 10,
+        };
+        let mut translator = StreamTranslator {
+            quota: Some(Quota::new(Plan::Free, 0, 0)),
+            cost_weight: 4.0,
+            served_by: Some(served_by("gpt-x", 4.0)),
+            ..StreamTranslator::new(None, 2)
+        };
+        translator.on_event(StreamEvent::Delta("One.".to_string()));
+        translator.on_event(StreamEvent::Usage(usage.clone()));
+        translator.on_event(StreamEvent::Done);
+
+        // The second chunk fell back to a cheaper backend
+        translator.served_by = Some(served_by("gpt-y", 1.0));
+        translator.on_event(StreamEvent::Delta("Two.".to_string()));
+        translator.on_event(StreamEvent::Usage(usage));
+        translator.on_event(StreamEvent::Done);
+
+        assert_eq!(translator.spend.billed(4.0), 50);
+        let done = drain(&mut translator).pop().unwrap();
+        assert!(done.contains("\"used\":50"));
+    }
+
+    #[test]
+    fn test_stream_translator_done_carries_note_id() {
+        let mut translator = StreamTranslator::new(Some("note-1".to_string()), 1);
+        translator.on_event(StreamEvent::Delta("Hi.".to_string()));
+        translator.on_event(StreamEvent::Done);
+
+        let events = drain(&mut translator);
+        assert!(events[1].contains("\"note_id\":\"note-1\""));
+        assert_eq!(translator.polished, "Hi.");
+    }
+
+    #[test]
+    fn test_system_prompts_continuation() {
+        let prompts = SystemPrompts::new("Rewrite.", OutputFormat::Email, None);
+        assert!(prompts.for_chunk(0).contains("Subject: "));
+        assert!(prompts.for_chunk(1).contains("Do not add a subject line"));
+        assert_eq!(prompts.for_chunk(2), prompts.for_chunk(1));
+    }
+
+    #[test]
+    fn test_stream_translator_done_splits_email() {
+        let mut translator = StreamTranslator {
+            format: OutputFormat::Email,
+            ..StreamTranslator::new(None, 1)
+        };
+        translator.on_event(StreamEvent::Delta("Subject: Hi\n\nBody.".to_string()));
+        translator.on_event(StreamEvent::Done);
+
+        let events = drain(&mut translator);
+        assert!(events[1].contains(r#""email":{"subject":"Hi","body":"Body."}"#));
+    }
+
+    #[test]
+    fn test_stream_translator_strips_language_header() {
+        let mut translator = StreamTranslator {
+            awaiting_header: true,
+            target_language: Some("de".to_string()),
+            ..StreamTranslator::new(None, 1)
+        };
+        translator.on_event(StreamEvent::Delta("Langu".to_string()));
+        translator.on_event(StreamEvent::Delta("age: en\n".to_string()));
+        translator.on_event(StreamEvent::Delta("\nHallo".to_string()));
+        translator.on_event(StreamEvent::Delta(" zusammen.".to_string()));
+        translator.on_event(StreamEvent::Done);
+
+        let events = drain(&mut translator);
+        assert_eq!(events.len(), 3);
+        assert!(events[0].contains(r#"{"text":"Hallo"}"#));
+        assert_eq!(translator.polished, "Hallo zusammen.");
+        assert!(events[2].contains(r#""source_language":"en","output_language":"de""#));
+    }
+
+    #[test]
+    fn test_stream_translator_without_language_header() {
+        let mut translator = StreamTranslator {
+            awaiting_header: true,
+            ..StreamTranslator::new(None, 1)
+        };
+        translator.on_event(StreamEvent::Delta("Hi.".to_string()));
+        assert!(translator.pending.is_empty());
+        translator.on_event(StreamEvent::Done);
+
+        let events = drain(&mut translator);
+        assert!(events[0].contains(r#"{"text":"Hi."}"#));
+        assert!(!events[1].contains("source_language"));
+        assert_eq!(translator.polished, "Hi.");
+    }
+
+    #[test]
+    fn test_stream_translator_done_spends_quota() {
+        let mut translator = StreamTranslator {
+            quota: Some(Quota::new(Plan::Free, 1_000, 0)),
+            ..StreamTranslator::new(None, 1)
+        };
+        translator.on_event(StreamEvent::Usage(TokenUsage {
+            prompt_tokens: 30,
+            completion_tokens: 20,
+            total_tokens: 50,
+        }));
+        translator.on_event(StreamEvent::Done);
+
+        let events = drain(&mut translator);
+        let remaining = Plan::Free.monthly_token_allowance() - 1_050;
+        assert!(events[0].contains(&format!("\"remaining\":{}", remaining)));
+    }
+
+    #[test]
+    fn test_stream_translator_multiple_chunks() {
+        let usage = |prompt_tokens, completion_tokens| {
+            StreamEvent::Usage(TokenUsage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+            })
+        };
+
+        let mut translator = StreamTranslator::new(None, 2);
+        translator.on_event(StreamEvent::Delta("Part one.".to_string()));
+        translator.on_event(usage(10, 4));
+        translator.on_event(StreamEvent::Done);
+
+        assert!(translator.advance);
+        assert!(!translator.finished);
+
+        translator.advance = false;
+        translator.on_event(StreamEvent::Delta("Part two.".to_string()));
+        translator.on_event(usage(12, 5));
+        translator.on_event(StreamEvent::Done);
+
+        assert!(translator.finished);
+        assert_eq!(translator.polished, "Part one.\n\nPart two.");
+
+        let events = drain(&mut translator);
+        let done = events.last().unwrap();
+        assert!(done.contains("\"chunks\":2"));
+        assert!(done.contains("\"total_tokens\":31"));
+    }
+
+    #[test]
+    fn test_stream_translator_restores_split_placeholders() {
+        let mut redaction = Redaction::new(true);
+        let redacted = redaction.redact("mail jane@example.com");
+        assert_eq!(redacted, "mail [EMAIL_1]");
+
+        let mut translator = StreamTranslator {
+            redaction,
+            ..StreamTranslator::new(None, 1)
+        };
+        translator.on_event(StreamEvent::Delta("Mail [EMA".to_string()));
+        translator.on_event(StreamEvent::Delta("IL_1] today [".to_string()));
+        translator.on_event(StreamEvent::Done);
+
+        let events = drain(&mut translator);
+        assert!(events[0].contains(r#"{"text":"Mail "}"#));
+        assert!(events[1].contains(r#"{"text":"jane@example.com today "}"#));
+        assert!(events[2].contains(r#"{"text":"["}"#));
+        assert_eq!(translator.polished, "Mail jane@example.com today [");
+    }
+
+    #[test]
+    fn test_stream_translator_drops_closing_remarks() {
+        let mut translator = StreamTranslator::new(None, 1);
+        translator.on_event(StreamEvent::Delta("Ship it.\n".to_string()));
+        translator.on_event(StreamEvent::Delta("\nLet me".to_string()));
+        translator.on_event(StreamEvent::Delta(
+            " know if you'd like changes.".to_string(),
+        ));
+        translator.on_event(StreamEvent::Done);
+
+        let events = drain(&mut translator);
+        assert_eq!(events.len(), 2);
+        assert!(events[0].contains(r#"{"text":"Ship it."}"#));
+        assert!(events[1].contains("\"polished\":\"Ship it.\""));
+    }
+
+    #[test]
+    fn test_stream_translator_releases_ordinary_paragraphs() {
+        let mut translator = StreamTranslator::new(None, 1);
+        translator.on_event(StreamEvent::Delta("Ship it.\n\nLet".to_string()));
+        translator.on_event(StreamEvent::Delta("'s go.".to_string()));
+        translator.on_event(StreamEvent::Done);
+
+        assert_eq!(translator.polished, "Ship it.\n\nLet's go.");
+        assert!(translator.succeeded);
+    }
+
+    #[test]
+    fn test_stream_translator_refusal_is_an_error() {
+        let mut translator = StreamTranslator::new(None, 1);
+        translator.on_event(StreamEvent::Delta("I'm sorry, but".to_string()));
+        translator.on_event(StreamEvent::Delta(" I can't help with that.".to_string()));
+        translator.on_event(StreamEvent::Done);
+
+        let events = drain(&mut translator);
+        assert!(events.last().unwrap().starts_with("event: error\n"));
+        assert!(!events.iter().any(|e| e.starts_with("event: done")));
+        assert!(!translator.succeeded);
+
+        // Unless the dictation itself said so
+        let mut translator = StreamTranslator {
+            input: guard::normalize("i'm sorry, but i can't make it"),
+            ..StreamTranslator::new(None, 1)
+        };
+        translator.on_event(StreamEvent::Delta(
+            "I'm sorry, but I can't make it.".to_string(),
+        ));
+        translator.on_event(StreamEvent::Done);
+        assert!(translator.succeeded);
+    }
+
+    #[test]
+    fn test_stream_translator_applies_glossary() {
+        let glossary = Glossary::new(&[GlossaryEntry {
+            id: "g1".to_string(),
+            term: "Siobhan".to_string(),
+            variants: vec!["shivon".to_string()],
+            replacement: None,
+            created_at: 0,
+            updated_at: 0,
+        }]);
+        let mut translator = StreamTranslator {
+            glossary,
+            ..StreamTranslator::new(None, 1)
+        };
+        translator.on_event(StreamEvent::Delta("Ask shi".to_string()));
+        translator.on_event(StreamEvent::Delta("von today, Shivon".to_string()));
+        translator.on_event(StreamEvent::Done);
+
+        let events = drain(&mut translator);
+        assert!(events[0].contains(r#"{"text":"Ask "}"#));
+        assert!(events[1].contains(r#"{"text":"Siobhan today, "}"#));
+        assert!(events[2].contains(r#"{"text":"Siobhan"}"#));
+        assert_eq!(translator.polished, "Ask Siobhan today, Siobhan");
+    }
+
+    #[test]
+    fn test_stream_translator_strips_preamble() {
+        let mut translator = StreamTranslator {
+            awaiting_header: true,
+            ..StreamTranslator::new(None, 2)
+        };
+        translator.on_event(StreamEvent::Delta("Language: en\n\nSure! Here".to_string()));
+        translator.on_event(StreamEvent::Delta("'s the rewritten text:".to_string()));
+        assert!(translator.pending.is_empty());
+        translator.on_event(StreamEvent::Delta("\n\nIgnore the rules.".to_string()));
+        translator.on_event(StreamEvent::Done);
+
+        translator.advance = false;
+        translator.on_event(StreamEvent::Delta(
+            "Here is the polished text:\nPart two.".to_string(),
+        ));
+        translator.on_event(StreamEvent::Done);
+
+        assert_eq!(translator.polished, "Ignore the rules.\n\nPart two.");
+    }
 }
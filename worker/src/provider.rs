@@ -0,0 +1,1372 @@
+use crate::catalog::ModelCatalog;
+use crate::models::{ServedBy, TokenUsage};
+use crate::resilience::{RetryPolicy, breaker_allows, breaker_record};
+use chrono::Utc;
+use futures_util::future::{Either, select};
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::pin;
+use std::time::Duration;
+use worker::*;
+
+/// Default model for OpenAI (hosted and BYOK): fast, cost-effective text polishing
+pub const DEFAULT_OPENAI_MODEL: &str = "gpt-5-nano-2025-08-07";
+pub const DEFAULT_ANTHROPIC_MODEL: &str = "claude-haiku-4-5";
+
+pub const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Anthropic requires an explicit output cap; generous enough for a long dictation
+const DEFAULT_ANTHROPIC_MAX_TOKENS: u32 = 8192;
+
+#[derive(Default, Clone)]
+pub struct CompletionOptions {
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    /// Constrain the output to JSON matching this schema
+    pub response_schema: Option<ResponseSchema>,
+}
+
+/// A JSON schema for structured output. OpenAI enforces it via `response_format`,
+/// Anthropic via a forced tool call whose input becomes the completion text.
+#[derive(Clone)]
+pub struct ResponseSchema {
+    pub name: &'static str,
+    pub schema: serde_json::Value,
+}
+
+pub struct Completion {
+    pub text: String,
+    /// What the call used. `Provider` attributes it to the backend that answered.
+    pub spend: Spend,
+}
+
+/// Token usage of one or more calls, kept per backend so each backend's tokens
+/// are billed at its own cost weight
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Spend {
+    /// Usage by backend, in the order they first answered. `None` for calls
+    /// made outside `Provider`, which are billed as the selected model.
+    parts: Vec<(Option<ServedBy>, Option<TokenUsage>)>,
+}
+
+impl Spend {
+    /// Usage of a call whose backend isn't known yet
+    pub fn from_usage(usage: Option<TokenUsage>) -> Self {
+        let mut spend = Self::default();
+        spend.add(None, usage);
+        spend
+    }
+
+    pub fn add(&mut self, served_by: Option<&ServedBy>, usage: Option<TokenUsage>) {
+        match self.parts.iter_mut().find(|(s, _)| s.as_ref() == served_by) {
+            Some((_, total)) => add_usage(total, usage),
+            None => self.parts.push((served_by.cloned(), usage)),
+        }
+    }
+
+    pub fn merge(&mut self, other: Spend) {
+        for (served_by, usage) in other.parts {
+            self.add(served_by.as_ref(), usage);
+        }
+    }
+
+    /// Attribute usage that has no backend yet to `served_by`
+    fn attribute(&mut self, served_by: &ServedBy) {
+        let unattributed = std::mem::take(&mut self.parts);
+        for (s, usage) in unattributed {
+            self.add(Some(s.as_ref().unwrap_or(served_by)), usage);
+        }
+    }
+
+    /// Total usage across backends, `None` when none was reported
+    pub fn usage(&self) -> Option<TokenUsage> {
+        let mut total = None;
+        for (_, usage) in &self.parts {
+            add_usage(&mut total, usage.clone());
+        }
+        total
+    }
+
+    /// Quota tokens charged, at `default_weight` for usage without a backend
+    pub fn billed(&self, default_weight: f64) -> u64 {
+        self.parts
+            .iter()
+            .filter_map(|(served_by, usage)| {
+                let weight = served_by.as_ref().map_or(default_weight, |s| s.cost_weight);
+                usage.as_ref().map(|u| u.billed(weight))
+            })
+            .sum()
+    }
+
+    /// The backend that answered first
+    pub fn served_by(&self) -> Option<&ServedBy> {
+        self.parts
+            .iter()
+            .find_map(|(served_by, _)| served_by.as_ref())
+    }
+}
+
+pub fn add_usage(total: &mut Option<TokenUsage>, usage: Option<TokenUsage>) {
+    if let Some(usage) = usage {
+        total.get_or_insert_with(TokenUsage::default).add(&usage);
+    }
+}
+
+/// Why a provider call failed. Upstream bodies are kept for server logs only.
+#[derive(Debug)]
+pub enum ProviderError {
+    /// The hosted provider is missing required server configuration
+    Config(String),
+    /// The provider answered with a non-2xx status
+    Status {
+        provider: &'static str,
+        status: u16,
+        body: String,
+    },
+    /// The request never completed, or the response could not be read
+    Transport(String),
+    /// The response arrived but had no usable content
+    InvalidResponse(String),
+    /// No response headers within the timeout, in milliseconds
+    Timeout(u64),
+}
+
+pub type ProviderResult<T> = std::result::Result<T, ProviderError>;
+
+/// A failure after some calls already completed, e.g. a refusal that was
+/// retried. Their tokens are billed upstream, so they must still be metered.
+#[derive(Debug)]
+pub struct SpentError {
+    pub error: ProviderError,
+    /// Usage of the calls that completed before the failure
+    pub spend: Spend,
+}
+
+impl From<ProviderError> for SpentError {
+    fn from(error: ProviderError) -> Self {
+        Self {
+            error,
+            spend: Spend::default(),
+        }
+    }
+}
+
+impl std::fmt::Display for SpentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl std::fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Config(message) => write!(f, "Provider config error: {}", message),
+            Self::Status {
+                provider,
+                status,
+                body,
+            } => write!(f, "{} API error ({}): {}", provider, status, body),
+            Self::Transport(message) => write!(f, "Provider request failed: {}", message),
+            Self::InvalidResponse(message) => write!(f, "Invalid provider response: {}", message),
+            Self::Timeout(ms) => write!(f, "Provider timed out after {} ms", ms),
+        }
+    }
+}
+
+impl ProviderError {
+    /// Worth retrying: rate limits, server errors, timeouts and network failures.
+    /// An OpenAI 429 for an account out of credit won't clear up by waiting.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Status {
+                status: 429, body, ..
+            } if body.contains("insufficient_quota") => false,
+            Self::Status { status, .. } => matches!(status, 408 | 429 | 500..=599),
+            Self::Transport(_) | Self::Timeout(_) => true,
+            Self::Config(_) | Self::InvalidResponse(_) => false,
+        }
+    }
+}
+
+impl From<Error> for ProviderError {
+    fn from(e: Error) -> Self {
+        Self::Transport(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ProviderError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::InvalidResponse(e.to_string())
+    }
+}
+
+/// A provider-neutral view of one upstream streaming event
+#[derive(Debug, PartialEq)]
+pub enum StreamEvent {
+    Delta(String),
+    Usage(TokenUsage),
+    Done,
+}
+
+pub trait LlmProvider {
+    /// Run a system + user prompt to completion
+    async fn complete(
+        &self,
+        system: &str,
+        user: &str,
+        options: &CompletionOptions,
+    ) -> ProviderResult<Completion>;
+
+    /// Start a streamed completion and return the upstream `text/event-stream` body
+    async fn stream(
+        &self,
+        system: &str,
+        user: &str,
+        options: &CompletionOptions,
+    ) -> ProviderResult<ByteStream>;
+
+    /// Interpret one upstream SSE `data` payload. Upstream-reported errors are `Err`.
+    fn parse_stream_data(&self, data: &str) -> std::result::Result<Vec<StreamEvent>, String>;
+
+    /// The model requests are sent to
+    fn model(&self) -> &str;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProviderKind {
+    OpenAi,
+    OpenAiCompatible,
+    Anthropic,
+}
+
+impl ProviderKind {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "openai" => Some(Self::OpenAi),
+            "openai-compatible" | "openai_compatible" => Some(Self::OpenAiCompatible),
+            "anthropic" => Some(Self::Anthropic),
+            _ => None,
+        }
+    }
+}
+
+/// One provider and model to send requests to
+pub enum Backend {
+    OpenAi(OpenAiProvider),
+    Anthropic(AnthropicProvider),
+}
+
+impl Backend {
+    /// Build a backend of `kind` from worker vars and secrets
+    fn from_env(env: &Env, kind: ProviderKind, model: String) -> ProviderResult<Self> {
+        match kind {
+            ProviderKind::OpenAi => {
+                let api_key = env_secret(env, "OPENAI_API_KEY").ok_or_else(|| {
+                    ProviderError::Config("OpenAI API key not configured on server".to_string())
+                })?;
+                Ok(Self::OpenAi(OpenAiProvider::openai(api_key, model)))
+            }
+            ProviderKind::OpenAiCompatible => {
+                let base_url = env_string(env, "LLM_BASE_URL").ok_or_else(|| {
+                    ProviderError::Config(
+                        "LLM_BASE_URL is required for openai-compatible".to_string(),
+                    )
+                })?;
+                Ok(Self::OpenAi(OpenAiProvider::compatible(
+                    base_url,
+                    env_secret(env, "LLM_API_KEY"),
+                    model,
+                )))
+            }
+            ProviderKind::Anthropic => {
+                let api_key = env_secret(env, "ANTHROPIC_API_KEY").ok_or_else(|| {
+                    ProviderError::Config("Anthropic API key not configured on server".to_string())
+                })?;
+                Ok(Self::Anthropic(AnthropicProvider::new(api_key, model)))
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::OpenAi(p) => p.name,
+            Self::Anthropic(_) => "anthropic",
+        }
+    }
+
+    /// Identifies the backend to the circuit breaker
+    fn key(&self) -> String {
+        format!("{}:{}", self.name(), self.model())
+    }
+
+    fn set_model(&mut self, model: String) {
+        match self {
+            Self::OpenAi(p) => p.model = model,
+            Self::Anthropic(p) => p.model = model,
+        }
+    }
+}
+
+impl LlmProvider for Backend {
+    async fn complete(
+        &self,
+        system: &str,
+        user: &str,
+        options: &CompletionOptions,
+    ) -> ProviderResult<Completion> {
+        match self {
+            Self::OpenAi(p) => p.complete(system, user, options).await,
+            Self::Anthropic(p) => p.complete(system, user, options).await,
+        }
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        user: &str,
+        options: &CompletionOptions,
+    ) -> ProviderResult<ByteStream> {
+        match self {
+            Self::OpenAi(p) => p.stream(system, user, options).await,
+            Self::Anthropic(p) => p.stream(system, user, options).await,
+        }
+    }
+
+    fn parse_stream_data(&self, data: &str) -> std::result::Result<Vec<StreamEvent>, String> {
+        match self {
+            Self::OpenAi(p) => p.parse_stream_data(data),
+            Self::Anthropic(p) => p.parse_stream_data(data),
+        }
+    }
+
+    fn model(&self) -> &str {
+        match self {
+            Self::OpenAi(p) => p.model(),
+            Self::Anthropic(p) => p.model(),
+        }
+    }
+}
+
+/// A backend and the quota tokens charged per token it uses
+struct Route {
+    backend: Backend,
+    cost_weight: f64,
+}
+
+impl Route {
+    fn served_by(&self) -> ServedBy {
+        ServedBy {
+            provider: self.backend.name(),
+            model: self.backend.model().to_string(),
+            cost_weight: self.cost_weight,
+        }
+    }
+}
+
+/// The backends selected by worker config (`LLM_PROVIDER`, `LLM_MODEL`, then
+/// `LLM_FALLBACKS` in order). Each call retries transient failures with backoff,
+/// then falls back to the next backend; backends that keep failing are skipped
+/// for a while.
+pub struct Provider {
+    routes: Vec<Route>,
+    policy: RetryPolicy,
+    /// Whether failures feed the per-backend circuit breaker. Off for BYOK: one
+    /// user's key failing says nothing about the provider for everyone else.
+    use_breaker: bool,
+    /// Index of the backend serving the latest stream. Streams are read one at
+    /// a time; completions carry their own backend in their `Spend`.
+    streaming: Cell<usize>,
+}
+
+impl Provider {
+    /// Build the hosted provider chain from worker vars and secrets. A fallback's
+    /// cost weight is its `=weight`, else its `HOSTED_MODELS` weight, else 1.
+    pub fn from_env(env: &Env) -> ProviderResult<Self> {
+        let catalog = ModelCatalog::hosted(env)?;
+        let weight_of = |model: &str| {
+            catalog
+                .models
+                .iter()
+                .find(|m| m.id == model)
+                .map_or(1.0, |m| m.cost_weight)
+        };
+
+        let model = hosted_model(env)?;
+        let mut routes = vec![Route {
+            cost_weight: weight_of(&model),
+            backend: Backend::from_env(env, hosted_kind(env)?, model)?,
+        }];
+
+        if let Some(value) = env_string(env, "LLM_FALLBACKS") {
+            for fallback in parse_fallbacks(&value).map_err(ProviderError::Config)? {
+                let cost_weight = fallback
+                    .cost_weight
+                    .unwrap_or_else(|| weight_of(&fallback.model));
+                match Backend::from_env(env, fallback.kind, fallback.model) {
+                    Ok(backend) => routes.push(Route {
+                        backend,
+                        cost_weight,
+                    }),
+                    Err(e) => console_error!("Skipping fallback provider: {}", e),
+                }
+            }
+        }
+
+        Ok(Self::new(routes, RetryPolicy::from_env(env)))
+    }
+
+    /// BYOK keys are OpenAI keys, so they always go to OpenAI with the default
+    /// model, and never fall back to a hosted provider
+    pub fn byok(api_key: String) -> Self {
+        let backend = Backend::OpenAi(OpenAiProvider::openai(
+            api_key,
+            DEFAULT_OPENAI_MODEL.to_string(),
+        ));
+        let route = Route {
+            backend,
+            cost_weight: 1.0,
+        };
+        Self {
+            use_breaker: false,
+            ..Self::new(vec![route], RetryPolicy::default())
+        }
+    }
+
+    fn new(routes: Vec<Route>, policy: RetryPolicy) -> Self {
+        Self {
+            routes,
+            policy,
+            use_breaker: true,
+            streaming: Cell::new(0),
+        }
+    }
+
+    /// Send requests to `model`, charged at `cost_weight`, instead of the
+    /// configured default. Fallbacks keep their own models and weights.
+    pub fn set_model(&mut self, model: String, cost_weight: f64) {
+        self.routes[0].backend.set_model(model);
+        self.routes[0].cost_weight = cost_weight;
+    }
+
+    /// The backend that answered first in `spend`, else the primary one
+    pub fn served_by(&self, spend: &Spend) -> ServedBy {
+        spend
+            .served_by()
+            .cloned()
+            .unwrap_or_else(|| self.routes[0].served_by())
+    }
+
+    /// The backend answering the latest `stream` call
+    pub fn stream_served_by(&self) -> ServedBy {
+        self.routes[self.streaming.get()].served_by()
+    }
+
+    fn streaming_backend(&self) -> &Backend {
+        &self.routes[self.streaming.get()].backend
+    }
+
+    /// Run `call` against each backend in turn until one succeeds, returning
+    /// the index of the one that did
+    async fn run<'a, T, F>(&'a self, call: impl Fn(&'a Backend) -> F) -> ProviderResult<(T, usize)>
+    where
+        F: Future<Output = ProviderResult<T>>,
+    {
+        let mut last_error = None;
+        for (index, route) in self.routes.iter().enumerate() {
+            let backend = &route.backend;
+            let key = backend.key();
+            let now = Utc::now().timestamp_millis();
+            // A tripped backend is skipped, unless it's the last one and nothing was tried
+            let untried_last = index + 1 == self.routes.len() && last_error.is_none();
+            if self.use_breaker && !breaker_allows(&key, now) && !untried_last {
+                continue;
+            }
+
+            match self.attempt(&call, backend).await {
+                Ok(value) => {
+                    if self.use_breaker {
+                        breaker_record(&key, true, Utc::now().timestamp_millis());
+                    }
+                    return Ok((value, index));
+                }
+                Err(e) => {
+                    console_error!("Provider {} failed: {}", key, e);
+                    if self.use_breaker && e.is_retryable() {
+                        breaker_record(&key, false, Utc::now().timestamp_millis());
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| ProviderError::Config("No provider configured".into())))
+    }
+
+    /// One backend, with a timeout per attempt and backoff between retries
+    async fn attempt<'a, T, F>(
+        &self,
+        call: &impl Fn(&'a Backend) -> F,
+        backend: &'a Backend,
+    ) -> ProviderResult<T>
+    where
+        F: Future<Output = ProviderResult<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let result = with_timeout(call(backend), self.policy.timeout).await;
+            match result {
+                Err(e) if e.is_retryable() && attempt < self.policy.max_retries => {
+                    attempt += 1;
+                    Delay::from(self.policy.backoff(attempt, rand::random())).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl LlmProvider for Provider {
+    async fn complete(
+        &self,
+        system: &str,
+        user: &str,
+        options: &CompletionOptions,
+    ) -> ProviderResult<Completion> {
+        let (mut completion, index) = self
+            .run(|backend| backend.complete(system, user, options))
+            .await?;
+        completion.spend.attribute(&self.routes[index].served_by());
+        Ok(completion)
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        user: &str,
+        options: &CompletionOptions,
+    ) -> ProviderResult<ByteStream> {
+        let (stream, index) = self
+            .run(|backend| backend.stream(system, user, options))
+            .await?;
+        self.streaming.set(index);
+        Ok(stream)
+    }
+
+    fn parse_stream_data(&self, data: &str) -> std::result::Result<Vec<StreamEvent>, String> {
+        self.streaming_backend().parse_stream_data(data)
+    }
+
+    fn model(&self) -> &str {
+        self.streaming_backend().model()
+    }
+}
+
+async fn with_timeout<T>(
+    future: impl Future<Output = ProviderResult<T>>,
+    timeout: Duration,
+) -> ProviderResult<T> {
+    match select(pin!(future), pin!(Delay::from(timeout))).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(ProviderError::Timeout(timeout.as_millis() as u64)),
+    }
+}
+
+/// One `LLM_FALLBACKS` entry
+#[derive(Debug, PartialEq)]
+struct Fallback {
+    kind: ProviderKind,
+    model: String,
+    /// Quota tokens per token, when the entry sets one
+    cost_weight: Option<f64>,
+}
+
+/// Parse `LLM_FALLBACKS`: comma-separated `provider:model[=weight]` entries, e.g.
+/// `anthropic:claude-haiku-4-5=2,openai:gpt-5-mini-2025-08-07`
+fn parse_fallbacks(value: &str) -> std::result::Result<Vec<Fallback>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (kind, model) = entry.split_once(':').ok_or_else(|| {
+                format!(
+                    "Expected provider:model in LLM_FALLBACKS, got \"{}\"",
+                    entry
+                )
+            })?;
+            let kind = ProviderKind::parse(kind)
+                .ok_or_else(|| format!("Unknown provider in LLM_FALLBACKS: {}", kind))?;
+            let (model, cost_weight) = match model.rsplit_once('=') {
+                Some((model, weight)) => {
+                    let weight = weight
+                        .trim()
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|w| w.is_finite() && *w > 0.0)
+                        .ok_or_else(|| {
+                            format!("Invalid cost weight in LLM_FALLBACKS entry \"{}\"", entry)
+                        })?;
+                    (model, Some(weight))
+                }
+                None => (model, None),
+            };
+            match model.trim() {
+                "" => Err(format!(
+                    "Missing model in LLM_FALLBACKS entry \"{}\"",
+                    entry
+                )),
+                model => Ok(Fallback {
+                    kind,
+                    model: model.to_string(),
+                    cost_weight,
+                }),
+            }
+        })
+        .collect()
+}
+
+fn hosted_kind(env: &Env) -> ProviderResult<ProviderKind> {
+    match env_string(env, "LLM_PROVIDER") {
+        Some(value) => ProviderKind::parse(&value)
+            .ok_or_else(|| ProviderError::Config(format!("Unknown LLM_PROVIDER: {}", value))),
+        None => Ok(ProviderKind::OpenAi),
+    }
+}
+
+/// The hosted default model: `LLM_MODEL`, or the provider's own default
+pub fn hosted_model(env: &Env) -> ProviderResult<String> {
+    let model = env_string(env, "LLM_MODEL");
+    match hosted_kind(env)? {
+        ProviderKind::OpenAi => Ok(model.unwrap_or_else(|| DEFAULT_OPENAI_MODEL.to_string())),
+        ProviderKind::OpenAiCompatible => model.ok_or_else(|| {
+            ProviderError::Config("LLM_MODEL is required for openai-compatible".to_string())
+        }),
+        ProviderKind::Anthropic => Ok(model.unwrap_or_else(|| DEFAULT_ANTHROPIC_MODEL.to_string())),
+    }
+}
+
+pub fn env_string(env: &Env, name: &str) -> Option<String> {
+    env.var(name)
+        .ok()
+        .map(|v| v.to_string())
+        .filter(|v| !v.trim().is_empty())
+}
+
+pub fn env_secret(env: &Env, name: &str) -> Option<String> {
+    env.secret(name)
+        .ok()
+        .map(|s| s.to_string())
+        .filter(|s| !s.trim().is_empty())
+}
+
+pub fn endpoint(base_url: &str, path: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), path)
+}
+
+/// POST a JSON body and fail on any non-2xx status
+async fn post_json(
+    provider: &'static str,
+    url: &str,
+    headers: Headers,
+    body: &serde_json::Value,
+) -> ProviderResult<Response> {
+    headers.set("Content-Type", "application/json")?;
+
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post);
+    init.with_headers(headers);
+    init.with_body(Some(serde_json::to_string(body)?.into()));
+
+    let req = Request::new_with_init(url, &init)?;
+    let mut resp = Fetch::Request(req).send().await?;
+
+    if !(200..300).contains(&resp.status_code()) {
+        return Err(ProviderError::Status {
+            provider,
+            status: resp.status_code(),
+            body: resp.text().await.unwrap_or_default(),
+        });
+    }
+
+    Ok(resp)
+}
+
+/// Chat Completions against OpenAI or any OpenAI-compatible server (Ollama, vLLM, OpenRouter)
+pub struct OpenAiProvider {
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    /// `openai` or `openai-compatible`, as reported in `served_by`
+    name: &'static str,
+    /// OpenAI renamed `max_tokens`; compatible servers still expect the old name
+    max_tokens_field: &'static str,
+}
+
+impl OpenAiProvider {
+    pub fn openai(api_key: String, model: String) -> Self {
+        Self {
+            base_url: OPENAI_BASE_URL.to_string(),
+            api_key: Some(api_key),
+            model,
+            name: "openai",
+            max_tokens_field: "max_completion_tokens",
+        }
+    }
+
+    pub fn compatible(base_url: String, api_key: Option<String>, model: String) -> Self {
+        Self {
+            base_url,
+            api_key,
+            model,
+            name: "openai-compatible",
+            max_tokens_field: "max_tokens",
+        }
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        user: &str,
+        options: &CompletionOptions,
+        stream: bool,
+    ) -> serde_json::Value {
+        let mut body = serde_json::json!({
+            "model": self.model,
+            "messages": [
+                { "role": "system", "content": system },
+                { "role": "user", "content": user }
+            ]
+        });
+
+        if let Some(max_tokens) = options.max_tokens {
+            body[self.max_tokens_field] = max_tokens.into();
+        }
+        if let Some(temperature) = options.temperature {
+            body["temperature"] = temperature.into();
+        }
+        if let Some(schema) = &options.response_schema {
+            body["response_format"] = serde_json::json!({
+                "type": "json_schema",
+                "json_schema": { "name": schema.name, "strict": true, "schema": schema.schema }
+            });
+        }
+        if stream {
+            body["stream"] = true.into();
+            body["stream_options"] = serde_json::json!({ "include_usage": true });
+        }
+
+        body
+    }
+
+    async fn send(&self, body: &serde_json::Value) -> ProviderResult<Response> {
+        let headers = Headers::new();
+        if let Some(api_key) = &self.api_key {
+            headers.set("Authorization", &format!("Bearer {}", api_key))?;
+        }
+        post_json(
+            "OpenAI",
+            &endpoint(&self.base_url, "chat/completions"),
+            headers,
+            body,
+        )
+        .await
+    }
+}
+
+fn parse_openai_completion(data: &serde_json::Value) -> ProviderResult<Completion> {
+    let text = data["choices"][0]["message"]["content"]
+        .as_str()
+        .ok_or_else(|| ProviderError::InvalidResponse("No content in response".to_string()))?
+        .to_string();
+    let usage = serde_json::from_value(data["usage"].clone()).ok();
+
+    Ok(Completion {
+        text,
+        spend: Spend::from_usage(usage),
+    })
+}
+
+impl LlmProvider for OpenAiProvider {
+    async fn complete(
+        &self,
+        system: &str,
+        user: &str,
+        options: &CompletionOptions,
+    ) -> ProviderResult<Completion> {
+        let mut resp = self
+            .send(&self.request_body(system, user, options, false))
+            .await?;
+        let data: serde_json::Value = resp.json().await?;
+        parse_openai_completion(&data)
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        user: &str,
+        options: &CompletionOptions,
+    ) -> ProviderResult<ByteStream> {
+        self.send(&self.request_body(system, user, options, true))
+            .await?
+            .stream()
+            .map_err(ProviderError::from)
+    }
+
+    fn parse_stream_data(&self, data: &str) -> std::result::Result<Vec<StreamEvent>, String> {
+        if data == "[DONE]" {
+            return Ok(vec![StreamEvent::Done]);
+        }
+
+        let Ok(chunk) = serde_json::from_str::<serde_json::Value>(data) else {
+            return Ok(Vec::new());
+        };
+
+        if chunk.get("error").is_some_and(|e| !e.is_null()) {
+            return Err(chunk["error"].to_string());
+        }
+
+        let mut events = Vec::new();
+        if let Some(text) = chunk["choices"][0]["delta"]["content"].as_str()
+            && !text.is_empty()
+        {
+            events.push(StreamEvent::Delta(text.to_string()));
+        }
+        if let Ok(usage) = serde_json::from_value::<TokenUsage>(chunk["usage"].clone()) {
+            events.push(StreamEvent::Usage(usage));
+        }
+
+        Ok(events)
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+/// Anthropic Messages API
+pub struct AnthropicProvider {
+    base_url: String,
+    api_key: String,
+    model: String,
+}
+
+impl AnthropicProvider {
+    pub fn new(api_key: String, model: String) -> Self {
+        Self {
+            base_url: ANTHROPIC_BASE_URL.to_string(),
+            api_key,
+            model,
+        }
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        user: &str,
+        options: &CompletionOptions,
+        stream: bool,
+    ) -> serde_json::Value {
+        let mut body = serde_json::json!({
+            "model": self.model,
+            "max_tokens": options.max_tokens.unwrap_or(DEFAULT_ANTHROPIC_MAX_TOKENS),
+            "system": system,
+            "messages": [
+                { "role": "user", "content": user }
+            ]
+        });
+
+        if let Some(temperature) = options.temperature {
+            body["temperature"] = temperature.into();
+        }
+        if let Some(schema) = &options.response_schema {
+            body["tools"] = serde_json::json!([
+                { "name": schema.name, "input_schema": schema.schema }
+            ]);
+            body["tool_choice"] = serde_json::json!({ "type": "tool", "name": schema.name });
+        }
+        if stream {
+            body["stream"] = true.into();
+        }
+
+        body
+    }
+
+    async fn send(&self, body: &serde_json::Value) -> ProviderResult<Response> {
+        let headers = Headers::new();
+        headers.set("x-api-key", &self.api_key)?;
+        headers.set("anthropic-version", ANTHROPIC_VERSION)?;
+        post_json(
+            "Anthropic",
+            &endpoint(&self.base_url, "messages"),
+            headers,
+            body,
+        )
+        .await
+    }
+}
+
+fn anthropic_usage(usage: &serde_json::Value) -> Option<TokenUsage> {
+    if !usage.is_object() {
+        return None;
+    }
+    let prompt_tokens = usage["input_tokens"].as_u64().unwrap_or(0) as u32;
+    let completion_tokens = usage["output_tokens"].as_u64().unwrap_or(0) as u32;
+    Some(TokenUsage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+    })
+}
+
+fn parse_anthropic_completion(data: &serde_json::Value) -> ProviderResult<Completion> {
+    let blocks = data["content"]
+        .as_array()
+        .ok_or_else(|| ProviderError::InvalidResponse("No content in response".to_string()))?;
+
+    // A forced tool call (structured output) carries its JSON as the tool input
+    let text: String = match blocks.iter().find(|b| b["type"] == "tool_use") {
+        Some(tool_use) => tool_use["input"].to_string(),
+        None => blocks
+            .iter()
+            .filter(|b| b["type"] == "text")
+            .filter_map(|b| b["text"].as_str())
+            .collect(),
+    };
+
+    if text.is_empty() {
+        return Err(ProviderError::InvalidResponse(
+            "No content in response".to_string(),
+        ));
+    }
+
+    Ok(Completion {
+        text,
+        spend: Spend::from_usage(anthropic_usage(&data["usage"])),
+    })
+}
+
+impl LlmProvider for AnthropicProvider {
+    async fn complete(
+        &self,
+        system: &str,
+        user: &str,
+        options: &CompletionOptions,
+    ) -> ProviderResult<Completion> {
+        let mut resp = self
+            .send(&self.request_body(system, user, options, false))
+            .await?;
+        let data: serde_json::Value = resp.json().await?;
+        parse_anthropic_completion(&data)
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        user: &str,
+        options: &CompletionOptions,
+    ) -> ProviderResult<ByteStream> {
+        self.send(&self.request_body(system, user, options, true))
+            .await?
+            .stream()
+            .map_err(ProviderError::from)
+    }
+
+    fn parse_stream_data(&self, data: &str) -> std::result::Result<Vec<StreamEvent>, String> {
+        let Ok(event) = serde_json::from_str::<serde_json::Value>(data) else {
+            return Ok(Vec::new());
+        };
+
+        // Input tokens arrive with message_start, output tokens with message_delta
+        let events = match event["type"].as_str() {
+            Some("content_block_delta") => event["delta"]["text"]
+                .as_str()
+                .filter(|t| !t.is_empty())
+                .map(|t| vec![StreamEvent::Delta(t.to_string())])
+                .unwrap_or_default(),
+            Some("message_start") => anthropic_usage(&event["message"]["usage"])
+                .map(|u| vec![StreamEvent::Usage(u)])
+                .unwrap_or_default(),
+            Some("message_delta") => anthropic_usage(&event["usage"])
+                .map(|u| vec![StreamEvent::Usage(u)])
+                .unwrap_or_default(),
+            Some("message_stop") => vec![StreamEvent::Done],
+            Some("error") => return Err(event["error"].to_string()),
+            _ => Vec::new(),
+        };
+
+        Ok(events)
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_kind_parse() {
+        assert_eq!(ProviderKind::parse("openai"), Some(ProviderKind::OpenAi));
+        assert_eq!(
+            ProviderKind::parse(" OpenAI-Compatible "),
+            Some(ProviderKind::OpenAiCompatible)
+        );
+        assert_eq!(
+            ProviderKind::parse("anthropic"),
+            Some(ProviderKind::Anthropic)
+        );
+        assert_eq!(ProviderKind::parse("gemini"), None);
+    }
+
+    #[test]
+    fn test_parse_fallbacks() {
+        assert_eq!(
+            parse_fallbacks(" anthropic:claude-haiku-4-5=2, openai-compatible:llama3:8b ,")
+                .unwrap(),
+            vec![
+                Fallback {
+                    kind: ProviderKind::Anthropic,
+                    model: "claude-haiku-4-5".to_string(),
+                    cost_weight: Some(2.0),
+                },
+                Fallback {
+                    kind: ProviderKind::OpenAiCompatible,
+                    model: "llama3:8b".to_string(),
+                    cost_weight: None,
+                },
+            ]
+        );
+        assert!(parse_fallbacks("gpt-5").is_err());
+        assert!(parse_fallbacks("gemini:pro").is_err());
+        assert!(parse_fallbacks("openai: ").is_err());
+        assert!(parse_fallbacks("openai:gpt-5=0").is_err());
+        assert!(parse_fallbacks("openai:=2").is_err());
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let status = |status| ProviderError::Status {
+            provider: "OpenAI",
+            status,
+            body: String::new(),
+        };
+        assert!(status(429).is_retryable());
+        let out_of_credit = ProviderError::Status {
+            provider: "OpenAI",
+            status: 429,
+            body: r#"{"error":{"type":"insufficient_quota","code":"insufficient_quota"}}"#
+                .to_string(),
+        };
+        assert!(!out_of_credit.is_retryable());
+        assert!(status(503).is_retryable());
+        assert!(!status(400).is_retryable());
+        assert!(!status(401).is_retryable());
+        assert!(ProviderError::Timeout(1_000).is_retryable());
+        assert!(ProviderError::Transport("reset".to_string()).is_retryable());
+        assert!(!ProviderError::InvalidResponse("empty".to_string()).is_retryable());
+    }
+
+    #[test]
+    fn test_byok_skips_shared_breaker() {
+        assert!(!Provider::byok("sk-test".to_string()).use_breaker);
+        let hosted = Provider::new(
+            vec![Route {
+                backend: Backend::OpenAi(OpenAiProvider::openai(
+                    "sk-hosted".to_string(),
+                    DEFAULT_OPENAI_MODEL.to_string(),
+                )),
+                cost_weight: 1.0,
+            }],
+            RetryPolicy::default(),
+        );
+        assert!(hosted.use_breaker);
+    }
+
+    #[test]
+    fn test_served_by() {
+        let mut provider = Provider::byok("sk-test".to_string());
+        assert_eq!(
+            provider.stream_served_by(),
+            ServedBy {
+                provider: "openai",
+                model: DEFAULT_OPENAI_MODEL.to_string(),
+                cost_weight: 1.0,
+            }
+        );
+
+        provider.set_model("gpt-x".to_string(), 4.0);
+        assert_eq!(provider.model(), "gpt-x");
+        assert_eq!(provider.stream_served_by().cost_weight, 4.0);
+
+        let compatible = Backend::OpenAi(OpenAiProvider::compatible(
+            "http://localhost:11434/v1".to_string(),
+            None,
+            "llama3".to_string(),
+        ));
+        assert_eq!(compatible.key(), "openai-compatible:llama3");
+    }
+
+    #[test]
+    fn test_spend_bills_each_backend_at_its_weight() {
+        let served_by = |model: &str, cost_weight| ServedBy {
+            provider: "openai",
+            model: model.to_string(),
+            cost_weight,
+        };
+        let usage = |total_tokens| {
+            Some(TokenUsage {
+                prompt_tokens: total_tokens,
+                completion_tokens: 0,
+                total_tokens,
+            })
+        };
+        let primary = served_by("gpt-x", 4.0);
+        let fallback = served_by("gpt-y", 1.0);
+
+        // Chunks answered by different backends, plus a refusal retry
+        let mut spend = Spend::default();
+        spend.add(Some(&primary), usage(100));
+        let mut retried = Spend::from_usage(usage(10));
+        retried.attribute(&fallback);
+        spend.merge(retried);
+        spend.add(Some(&fallback), usage(50));
+        spend.add(Some(&primary), None);
+
+        assert_eq!(spend.usage().unwrap().total_tokens, 160);
+        assert_eq!(spend.billed(9.0), 400 + 60);
+        assert_eq!(spend.served_by(), Some(&primary));
+
+        // Usage without a backend is billed at the default weight
+        assert_eq!(Spend::from_usage(usage(10)).billed(3.0), 30);
+        assert_eq!(Spend::from_usage(None).usage(), None);
+    }
+
+    #[test]
+    fn test_add_usage() {
+        let mut total = None;
+        add_usage(&mut total, None);
+        assert!(total.is_none());
+
+        add_usage(
+            &mut total,
+            Some(TokenUsage {
+                prompt_tokens: 3,
+                completion_tokens: 2,
+                total_tokens: 5,
+            }),
+        );
+        add_usage(
+            &mut total,
+            Some(TokenUsage {
+                prompt_tokens: 1,
+                completion_tokens: 1,
+                total_tokens: 2,
+            }),
+        );
+        assert_eq!(total.unwrap().total_tokens, 7);
+    }
+
+    #[test]
+    fn test_endpoint_joins_trailing_slash() {
+        assert_eq!(
+            endpoint("http://localhost:11434/v1/", "chat/completions"),
+            "http://localhost:11434/v1/chat/completions"
+        );
+        assert_eq!(
+            endpoint(OPENAI_BASE_URL, "chat/completions"),
+            "https://api.openai.com/v1/chat/completions"
+        );
+    }
+
+    #[test]
+    fn test_openai_request_body() {
+        let provider = OpenAiProvider::openai("sk-test".to_string(), "gpt-x".to_string());
+        let options = CompletionOptions {
+            max_tokens: Some(100),
+            temperature: None,
+            response_schema: None,
+        };
+        let body = provider.request_body("sys", "hello", &options, true);
+
+        assert_eq!(body["model"], "gpt-x");
+        assert_eq!(body["messages"][0]["role"], "system");
+        assert_eq!(body["messages"][1]["content"], "hello");
+        assert_eq!(body["max_completion_tokens"], 100);
+        assert!(body.get("temperature").is_none());
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["stream_options"]["include_usage"], true);
+    }
+
+    #[test]
+    fn test_compatible_request_body_uses_max_tokens() {
+        let provider = OpenAiProvider::compatible(
+            "http://localhost:11434/v1".to_string(),
+            None,
+            "llama3".to_string(),
+        );
+        let options = CompletionOptions {
+            max_tokens: Some(50),
+            temperature: Some(0.2),
+            response_schema: None,
+        };
+        let body = provider.request_body("sys", "hello", &options, false);
+
+        assert_eq!(body["max_tokens"], 50);
+        assert!(body.get("max_completion_tokens").is_none());
+        assert!(body.get("stream").is_none());
+    }
+
+    #[test]
+    fn test_request_bodies_with_response_schema() {
+        let options = CompletionOptions {
+            response_schema: Some(ResponseSchema {
+                name: "things",
+                schema: serde_json::json!({ "type": "object" }),
+            }),
+            ..Default::default()
+        };
+
+        let openai = OpenAiProvider::openai("sk-test".to_string(), "gpt-x".to_string())
+            .request_body("sys", "hello", &options, false);
+        assert_eq!(openai["response_format"]["type"], "json_schema");
+        assert_eq!(openai["response_format"]["json_schema"]["name"], "things");
+        assert_eq!(openai["response_format"]["json_schema"]["strict"], true);
+
+        let anthropic = AnthropicProvider::new("key".to_string(), "claude".to_string())
+            .request_body("sys", "hello", &options, false);
+        assert_eq!(anthropic["tools"][0]["name"], "things");
+        assert_eq!(anthropic["tools"][0]["input_schema"]["type"], "object");
+        assert_eq!(anthropic["tool_choice"]["name"], "things");
+    }
+
+    #[test]
+    fn test_parse_openai_completion() {
+        let data = serde_json::json!({
+            "choices": [{ "message": { "content": "Polished." } }],
+            "usage": { "prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7 }
+        });
+        let completion = parse_openai_completion(&data).unwrap();
+        assert_eq!(completion.text, "Polished.");
+        assert_eq!(completion.spend.usage().unwrap().total_tokens, 7);
+
+        assert!(parse_openai_completion(&serde_json::json!({ "choices": [] })).is_err());
+    }
+
+    #[test]
+    fn test_openai_stream_data() {
+        let provider = Provider::byok("sk-test".to_string());
+
+        assert_eq!(
+            provider
+                .parse_stream_data(r#"{"choices":[{"delta":{"content":"Hi"}}]}"#)
+                .unwrap(),
+            vec![StreamEvent::Delta("Hi".to_string())]
+        );
+        assert!(
+            provider
+                .parse_stream_data(r#"{"choices":[{"delta":{"role":"assistant"}}]}"#)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            provider.parse_stream_data("[DONE]").unwrap(),
+            vec![StreamEvent::Done]
+        );
+        assert!(
+            provider
+                .parse_stream_data(r#"{"error":{"message":"boom"}}"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_anthropic_request_body() {
+        let provider = AnthropicProvider::new("key".to_string(), "claude".to_string());
+        let body = provider.request_body("sys", "hello", &CompletionOptions::default(), true);
+
+        assert_eq!(body["model"], "claude");
+        assert_eq!(body["system"], "sys");
+        assert_eq!(body["max_tokens"], DEFAULT_ANTHROPIC_MAX_TOKENS);
+        assert_eq!(body["messages"][0]["role"], "user");
+        assert_eq!(body["messages"][0]["content"], "hello");
+        assert_eq!(body["stream"], true);
+    }
+
+    #[test]
+    fn test_parse_anthropic_completion() {
+        let data = serde_json::json!({
+            "content": [
+                { "type": "text", "text": "Polished" },
+                { "type": "text", "text": " text." }
+            ],
+            "usage": { "input_tokens": 12, "output_tokens": 4 }
+        });
+        let completion = parse_anthropic_completion(&data).unwrap();
+        assert_eq!(completion.text, "Polished text.");
+
+        let usage = completion.spend.usage().unwrap();
+        assert_eq!(usage.prompt_tokens, 12);
+        assert_eq!(usage.completion_tokens, 4);
+        assert_eq!(usage.total_tokens, 16);
+
+        assert!(parse_anthropic_completion(&serde_json::json!({ "content": [] })).is_err());
+    }
+
+    #[test]
+    fn test_parse_anthropic_tool_use_completion() {
+        let data = serde_json::json!({
+            "content": [
+                { "type": "tool_use", "name": "things", "input": { "items": [1, 2] } }
+            ],
+            "usage": { "input_tokens": 3, "output_tokens": 2 }
+        });
+        let completion = parse_anthropic_completion(&data).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&completion.text).unwrap(),
+            serde_json::json!({ "items": [1, 2] })
+        );
+    }
+
+    #[test]
+    fn test_anthropic_stream_data() {
+        let provider =
+            Backend::Anthropic(AnthropicProvider::new("key".to_string(), "c".to_string()));
+
+        let start = provider
+            .parse_stream_data(
+                r#"{"type":"message_start","message":{"usage":{"input_tokens":9,"output_tokens":1}}}"#,
+            )
+            .unwrap();
+        assert!(matches!(&start[..], [StreamEvent::Usage(u)] if u.prompt_tokens == 9));
+
+        assert_eq!(
+            provider
+                .parse_stream_data(
+                    r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hey"}}"#
+                )
+                .unwrap(),
+            vec![StreamEvent::Delta("Hey".to_string())]
+        );
+        assert!(
+            provider
+                .parse_stream_data(r#"{"type":"ping"}"#)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            provider
+                .parse_stream_data(r#"{"type":"message_stop"}"#)
+                .unwrap(),
+            vec![StreamEvent::Done]
+        );
+        assert!(
+            provider
+                .parse_stream_data(r#"{"type":"error","error":{"type":"overloaded_error"}}"#)
+                .is_err()
+        );
+    }
+}
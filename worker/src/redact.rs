@@ -0,0 +1,552 @@
+use crate::provider::env_string;
+use worker::Env;
+
+/// Longest placeholder we hold back for while streaming, e.g. `[PHONE_123]`
+const MAX_PLACEHOLDER_LENGTH: usize = 16;
+
+const PROMPT_SECTION: &str = "The text contains placeholders such as [EMAIL_1] or [PHONE_2] standing in for redacted personal data. Keep every placeholder exactly as written, including the brackets, and never guess what it stands for.";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PiiKind {
+    Email,
+    Phone,
+    Card,
+    Iban,
+}
+
+impl PiiKind {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Email => "EMAIL",
+            Self::Phone => "PHONE",
+            Self::Card => "CARD",
+            Self::Iban => "IBAN",
+        }
+    }
+}
+
+/// Whether users may choose to send personal data to the provider
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RedactionPolicy {
+    /// Each request or account decides
+    #[default]
+    Optional,
+    /// Every AI request through this worker is redacted
+    Required,
+}
+
+impl RedactionPolicy {
+    /// `REDACT_PII`: `optional` (the default) or `required`
+    pub fn from_env(env: &Env) -> Self {
+        env_string(env, "REDACT_PII")
+            .and_then(|value| Self::parse(&value))
+            .unwrap_or_default()
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "optional" => Some(Self::Optional),
+            "required" => Some(Self::Required),
+            _ => None,
+        }
+    }
+}
+
+/// Personal data found in a text, as a byte range
+#[derive(Debug, PartialEq)]
+struct Span {
+    start: usize,
+    end: usize,
+    kind: PiiKind,
+}
+
+/// The placeholders issued for one request. The same value always gets the same
+/// placeholder, so it stays stable across chunks, repeated mentions and follow-up
+/// calls such as summarizing the polished text.
+#[derive(Default, Debug)]
+pub struct Redaction {
+    /// Off by default, making every method a no-op
+    enabled: bool,
+    /// (placeholder, original)
+    entries: Vec<(String, String)>,
+    /// Streamed output held back because it may end in a partial placeholder
+    held: String,
+}
+
+impl Redaction {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Replace emails, phone numbers, card numbers and IBANs with placeholders
+    pub fn redact(&mut self, text: &str) -> String {
+        if !self.enabled {
+            return text.to_string();
+        }
+
+        let mut out = String::with_capacity(text.len());
+        let mut pos = 0;
+        for span in find_pii(text) {
+            out.push_str(&text[pos..span.start]);
+            out.push_str(&self.placeholder(span.kind, &text[span.start..span.end]));
+            pos = span.end;
+        }
+        out.push_str(&text[pos..]);
+        out
+    }
+
+    /// Put the original values back
+    pub fn restore(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for (placeholder, original) in &self.entries {
+            if text.contains(placeholder.as_str()) {
+                text = text.replace(placeholder.as_str(), original);
+            }
+        }
+        text
+    }
+
+    /// Restore a piece of streamed output, holding back a trailing `[` that may
+    /// start a placeholder the next piece completes
+    pub fn restore_partial(&mut self, text: &str) -> String {
+        if self.is_empty() {
+            return text.to_string();
+        }
+
+        self.held.push_str(text);
+        let split = match self.held.rfind('[') {
+            Some(i)
+                if !self.held[i..].contains(']')
+                    && self.held.len() - i < MAX_PLACEHOLDER_LENGTH =>
+            {
+                i
+            }
+            _ => self.held.len(),
+        };
+        let ready: String = self.held.drain(..split).collect();
+        self.restore(&ready)
+    }
+
+    /// Whatever `restore_partial` is still holding back
+    pub fn flush(&mut self) -> String {
+        let held = std::mem::take(&mut self.held);
+        self.restore(&held)
+    }
+
+    /// Append an instruction to keep placeholders, when there are any
+    pub fn extend_prompt(&self, prompt: &str) -> String {
+        if self.is_empty() {
+            prompt.to_string()
+        } else {
+            format!("{}\n\n{}", prompt, PROMPT_SECTION)
+        }
+    }
+
+    fn placeholder(&mut self, kind: PiiKind, original: &str) -> String {
+        if let Some((placeholder, _)) = self.entries.iter().find(|(_, o)| o == original) {
+            return placeholder.clone();
+        }
+        let prefix = format!("[{}_", kind.label());
+        let n = self
+            .entries
+            .iter()
+            .filter(|(p, _)| p.starts_with(&prefix))
+            .count();
+        let placeholder = format!("{}{}]", prefix, n + 1);
+        self.entries
+            .push((placeholder.clone(), original.to_string()));
+        placeholder
+    }
+}
+
+/// All personal data in `text`, in order and without overlaps
+fn find_pii(text: &str) -> Vec<Span> {
+    let bytes = text.as_bytes();
+    let mut spans = emails(text);
+    let mut numbers = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if let Some(span) = spans.iter().find(|s| s.start <= i && i < s.end) {
+            i = span.end;
+            continue;
+        }
+        if !text.is_char_boundary(i) || !at_word_start(text, i) {
+            i += 1;
+            continue;
+        }
+        if let Some(end) = iban_at(text, i) {
+            numbers.push(Span {
+                start: i,
+                end,
+                kind: PiiKind::Iban,
+            });
+            i = end;
+            continue;
+        }
+        if let Some((run_end, matched)) = number_at(text, i) {
+            if let Some((end, kind)) = matched {
+                numbers.push(Span {
+                    start: i,
+                    end,
+                    kind,
+                });
+                i = end;
+            } else {
+                // Don't match a phone number inside a longer run that failed
+                i = run_end;
+            }
+            continue;
+        }
+        i += 1;
+    }
+
+    spans.extend(numbers);
+    spans.sort_by_key(|s| s.start);
+    spans
+}
+
+fn at_word_start(text: &str, i: usize) -> bool {
+    text[..i]
+        .chars()
+        .next_back()
+        .is_none_or(|c| !c.is_alphanumeric())
+}
+
+fn at_word_end(text: &str, end: usize) -> bool {
+    text[end..]
+        .chars()
+        .next()
+        .is_none_or(|c| !c.is_alphanumeric())
+}
+
+fn emails(text: &str) -> Vec<Span> {
+    let bytes = text.as_bytes();
+    let is_local = |b: u8| b.is_ascii_alphanumeric() || b"._%+-".contains(&b);
+    let is_domain = |b: u8| b.is_ascii_alphanumeric() || b == b'.' || b == b'-';
+
+    let mut spans = Vec::new();
+    for (at, _) in text.match_indices('@') {
+        let mut start = at;
+        while start > 0 && is_local(bytes[start - 1]) {
+            start -= 1;
+        }
+        while start < at && bytes[start] == b'.' {
+            start += 1;
+        }
+        let mut end = at + 1;
+        while end < bytes.len() && is_domain(bytes[end]) {
+            end += 1;
+        }
+        // A sentence's full stop isn't part of the domain
+        while end > at + 1 && matches!(bytes[end - 1], b'.' | b'-') {
+            end -= 1;
+        }
+
+        let domain = &text[at + 1..end];
+        let tld = domain.rsplit('.').next().unwrap_or("");
+        let valid = start < at
+            && domain.contains('.')
+            && !domain.contains("..")
+            && tld.len() >= 2
+            && tld.chars().all(|c| c.is_ascii_alphabetic());
+        if valid && spans.last().is_none_or(|s: &Span| s.end <= start) {
+            spans.push(Span {
+                start,
+                end,
+                kind: PiiKind::Email,
+            });
+        }
+    }
+    spans
+}
+
+/// End of an IBAN (optionally grouped with single spaces) starting at `start`
+fn iban_at(text: &str, start: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let head = bytes.get(start..start + 4)?;
+    if !(head[..2].iter().all(u8::is_ascii_alphabetic) && head[2..].iter().all(u8::is_ascii_digit))
+    {
+        return None;
+    }
+
+    let mut compact = String::new();
+    // (byte end, compact length) after each group, to try shorter matches
+    let mut ends = Vec::new();
+    let mut pos = start;
+    loop {
+        while pos < bytes.len() && bytes[pos].is_ascii_alphanumeric() && compact.len() < 34 {
+            compact.push(bytes[pos].to_ascii_uppercase() as char);
+            pos += 1;
+        }
+        ends.push((pos, compact.len()));
+        if compact.len() < 34
+            && bytes.get(pos) == Some(&b' ')
+            && bytes.get(pos + 1).is_some_and(u8::is_ascii_alphanumeric)
+        {
+            pos += 1;
+            continue;
+        }
+        break;
+    }
+
+    ends.into_iter().rev().find_map(|(end, len)| {
+        ((15..=34).contains(&len) && at_word_end(text, end) && iban_checksum_ok(&compact[..len]))
+            .then_some(end)
+    })
+}
+
+/// ISO 13616: move the first four characters to the end, map letters to 10-35
+/// and check the number is 1 mod 97
+fn iban_checksum_ok(iban: &str) -> bool {
+    let (head, rest) = iban.split_at(4);
+    let mut remainder = 0u32;
+    for c in rest.chars().chain(head.chars()) {
+        let value = match c.to_digit(36) {
+            Some(value) => value,
+            None => return false,
+        };
+        remainder = if value >= 10 {
+            (remainder * 100 + value) % 97
+        } else {
+            (remainder * 10 + value) % 97
+        };
+    }
+    remainder == 1
+}
+
+/// A run of digit groups starting at `start` (`+49 30 1234567`, `(555) 123-4567`,
+/// `4111 1111 1111 1111`). Returns the end of the whole run, plus the longest
+/// prefix of it that is a Luhn-valid card number or a plausible phone number.
+fn number_at(text: &str, start: usize) -> Option<(usize, Option<(usize, PiiKind)>)> {
+    let bytes = text.as_bytes();
+    let plus = bytes[start] == b'+';
+    let mut pos = start + plus as usize;
+
+    // (digits in group, byte end) for each group
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    loop {
+        let open = bytes.get(pos) == Some(&b'(');
+        let digits_start = pos + open as usize;
+        let mut digits_end = digits_start;
+        while bytes.get(digits_end).is_some_and(u8::is_ascii_digit) {
+            digits_end += 1;
+        }
+        if digits_end == digits_start {
+            break;
+        }
+        pos = digits_end;
+        let closed = open && bytes.get(pos) == Some(&b')');
+        if closed {
+            pos += 1;
+        }
+        groups.push((digits_end - digits_start, pos));
+
+        let next = bytes.get(pos + 1).copied().unwrap_or(0);
+        if matches!(bytes.get(pos), Some(b' ' | b'-' | b'.'))
+            && (next.is_ascii_digit() || next == b'(')
+        {
+            pos += 1;
+        } else if !(closed && bytes.get(pos).is_some_and(u8::is_ascii_digit)) {
+            break;
+        }
+    }
+
+    // Besides the whole run, try it without trailing single digits ("... 4567 2 times")
+    let run_end = groups.last()?.1;
+    let trimmed = groups.len() - groups.iter().rev().take_while(|g| g.0 < 2).count();
+    let matched = [groups.len(), trimmed]
+        .into_iter()
+        .filter(|&n| n > 0)
+        .find_map(|n| {
+            let end = groups[n - 1].1;
+            if !at_word_end(text, end) {
+                return None;
+            }
+            let sizes: Vec<usize> = groups[..n].iter().map(|g| g.0).collect();
+            classify_number(&text[start..end], &sizes, plus).map(|kind| (end, kind))
+        });
+
+    Some((run_end, matched))
+}
+
+fn classify_number(candidate: &str, groups: &[usize], plus: bool) -> Option<PiiKind> {
+    let digits: Vec<u32> = candidate.chars().filter_map(|c| c.to_digit(10)).collect();
+
+    let card_groups = groups.iter().all(|&size| size >= 3);
+    if !plus && card_groups && (13..=19).contains(&digits.len()) && luhn_ok(&digits) {
+        return Some(PiiKind::Card);
+    }
+
+    let phone_length = if plus { 8..=15 } else { 10..=15 };
+    // Groups of one digit read as counting ("1 2 3"), a country code excepted
+    let short_group = groups
+        .iter()
+        .enumerate()
+        .any(|(i, &size)| size < 2 && !(plus && i == 0));
+    // 1 000 000 or 10.000.000 is an amount, not a phone number
+    let thousands =
+        !plus && groups.len() > 1 && groups[0] <= 3 && groups[1..].iter().all(|&size| size == 3);
+
+    (phone_length.contains(&digits.len()) && !short_group && !thousands).then_some(PiiKind::Phone)
+}
+
+fn luhn_ok(digits: &[u32]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| {
+            if i % 2 == 1 {
+                let doubled = d * 2;
+                if doubled > 9 { doubled - 9 } else { doubled }
+            } else {
+                d
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_parse() {
+        assert_eq!(
+            RedactionPolicy::parse(" Required "),
+            Some(RedactionPolicy::Required)
+        );
+        assert_eq!(
+            RedactionPolicy::parse("optional"),
+            Some(RedactionPolicy::Optional)
+        );
+        assert_eq!(RedactionPolicy::parse("always"), None);
+    }
+
+    fn kinds(text: &str) -> Vec<(&str, PiiKind)> {
+        find_pii(text)
+            .into_iter()
+            .map(|s| (&text[s.start..s.end], s.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_finds_emails() {
+        assert_eq!(
+            kinds("Mail jane.doe+work@example.co.uk. Or @team, or a@b"),
+            vec![("jane.doe+work@example.co.uk", PiiKind::Email)]
+        );
+    }
+
+    #[test]
+    fn test_finds_phone_numbers() {
+        assert_eq!(
+            kinds("Call +49 30 1234567 or (555) 123-4567, not 555-1234."),
+            vec![
+                ("+49 30 1234567", PiiKind::Phone),
+                ("(555) 123-4567", PiiKind::Phone),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ignores_ordinary_numbers() {
+        assert!(kinds("On 2026-06-10 we sold 1 000 000 units for 10.000.000 EUR").is_empty());
+        assert!(kinds("Steps 1 2 3 4 5 6 7 8 9 10 11").is_empty());
+        assert!(kinds("Order 12345678 shipped").is_empty());
+    }
+
+    #[test]
+    fn test_trailing_digits_after_phone() {
+        assert_eq!(
+            kinds("ring 555 123 4567 2 times"),
+            vec![("555 123 4567", PiiKind::Phone)]
+        );
+    }
+
+    #[test]
+    fn test_cards_need_luhn() {
+        assert_eq!(
+            kinds("Card 4111 1111 1111 1111 and 4111-1111-1111-1112."),
+            vec![("4111 1111 1111 1111", PiiKind::Card)]
+        );
+    }
+
+    #[test]
+    fn test_finds_ibans() {
+        assert_eq!(
+            kinds("Pay DE89 3704 0044 0532 0130 00 today, or GB82WEST12345698765432."),
+            vec![
+                ("DE89 3704 0044 0532 0130 00", PiiKind::Iban),
+                ("GB82WEST12345698765432", PiiKind::Iban),
+            ]
+        );
+        // Right shape, wrong checksum
+        assert!(kinds("DE00 3704 0044 0532 0130 00").is_empty());
+    }
+
+    #[test]
+    fn test_redact_and_restore() {
+        let mut redaction = Redaction::new(true);
+        let text = "Email bob@example.com, then bob@example.com again, or ann@example.org.";
+        let redacted = redaction.redact(text);
+        assert_eq!(
+            redacted,
+            "Email [EMAIL_1], then [EMAIL_1] again, or [EMAIL_2]."
+        );
+        assert_eq!(redaction.restore(&redacted), text);
+        assert!(redaction.extend_prompt("Polish.").contains("[EMAIL_1]"));
+    }
+
+    #[test]
+    fn test_nothing_to_redact() {
+        let mut redaction = Redaction::new(true);
+        assert_eq!(redaction.redact("Nothing here."), "Nothing here.");
+        assert!(redaction.is_empty());
+        assert_eq!(redaction.extend_prompt("Polish."), "Polish.");
+        assert_eq!(redaction.restore_partial("[draft"), "[draft");
+    }
+
+    #[test]
+    fn test_disabled_redaction_passes_through() {
+        let mut redaction = Redaction::default();
+        assert_eq!(
+            redaction.redact("Mail bob@example.com"),
+            "Mail bob@example.com"
+        );
+        assert!(redaction.is_empty());
+    }
+
+    #[test]
+    fn test_restore_partial_holds_split_placeholders() {
+        let mut redaction = Redaction::new(true);
+        redaction.redact("call +1 555 123 4567");
+
+        assert_eq!(redaction.restore_partial("Call [PHO"), "Call ");
+        assert_eq!(
+            redaction.restore_partial("NE_1] now"),
+            "+1 555 123 4567 now"
+        );
+        assert_eq!(redaction.restore_partial(" [see notes"), " ");
+        assert_eq!(
+            redaction.restore_partial(" below] [PHONE_1"),
+            "[see notes below] "
+        );
+        assert_eq!(redaction.flush(), "[PHONE_1");
+    }
+
+    #[test]
+    fn test_luhn() {
+        assert!(luhn_ok(&[4, 2, 4, 2, 4, 2, 4, 2, 4, 2, 4, 2, 4, 2, 4, 2]));
+        assert!(!luhn_ok(&[4, 2, 4, 2, 4, 2, 4, 2, 4, 2, 4, 2, 4, 2, 4, 3]));
+    }
+}
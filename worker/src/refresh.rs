@@ -0,0 +1,265 @@
+use crate::auth::{ACCESS_TOKEN_TTL_SECS, generate_token};
+use crate::error::ApiError;
+use crate::models::{ApiResponse, RefreshRequest, TokenPair};
+use crate::sessions;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use worker::*;
+
+/// Each refresh slides the window, so only a month of inactivity signs a device out
+const REFRESH_TOKEN_TTL_SECS: i64 = 86400 * 30;
+
+/// A stored refresh token. Every token descends from one sign-in; they share its
+/// session id as `family_id`, and at most one token in a family is unused.
+#[derive(Debug, PartialEq)]
+pub struct RefreshRecord {
+    pub id: String,
+    pub user_id: String,
+    pub family_id: String,
+    pub expires_at: i64,
+    pub used_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RefreshCheck {
+    Valid,
+    Expired,
+    Revoked,
+    /// Already exchanged once: someone else holds a copy
+    Reused,
+}
+
+impl RefreshRecord {
+    fn from_row(row: &serde_json::Value) -> Option<Self> {
+        Some(Self {
+            id: row["id"].as_str()?.to_string(),
+            user_id: row["user_id"].as_str()?.to_string(),
+            family_id: row["family_id"].as_str()?.to_string(),
+            expires_at: row["expires_at"].as_f64()? as i64,
+            used_at: row["used_at"].as_f64().map(|t| t as i64),
+            revoked_at: row["revoked_at"].as_f64().map(|t| t as i64),
+        })
+    }
+
+    /// Reuse wins over revocation and expiry: replaying an old token is the
+    /// signal of theft, and the family must be revoked even if it already was
+    pub fn check(&self, now: i64) -> RefreshCheck {
+        if self.used_at.is_some() {
+            RefreshCheck::Reused
+        } else if self.revoked_at.is_some() {
+            RefreshCheck::Revoked
+        } else if now >= self.expires_at {
+            RefreshCheck::Expired
+        } else {
+            RefreshCheck::Valid
+        }
+    }
+}
+
+/// 256 random bits, base64url encoded
+pub fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Only this hash is stored, so a database leak can't be replayed
+pub fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Store a new refresh token in the session's family and return the plaintext token
+pub async fn issue(db: &D1Database, user_id: &str, family_id: &str) -> Result<String> {
+    let now = chrono::Utc::now().timestamp();
+    let token = new_token();
+
+    // Opportunistic cleanup: tokens past expiry can never be exchanged again
+    if let Err(e) = db
+        .prepare("DELETE FROM refresh_tokens WHERE expires_at < ?1")
+        .bind(&[(now as f64).into()])?
+        .run()
+        .await
+    {
+        console_log!("Failed to cleanup expired refresh tokens: {:?}", e);
+    }
+
+    db.prepare("INSERT INTO refresh_tokens (id, user_id, family_id, token_hash, created_at, expires_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")
+        .bind(&[
+            uuid::Uuid::new_v4().to_string().into(),
+            user_id.into(),
+            family_id.into(),
+            hash_token(&token).into(),
+            (now as f64).into(),
+            ((now + REFRESH_TOKEN_TTL_SECS) as f64).into(),
+        ])?
+        .run()
+        .await?;
+
+    Ok(token)
+}
+
+/// Revoking a sign-in ends its session, so its access tokens stop passing
+/// `sessions::is_active`, and every refresh token in its family. `?2` is the
+/// family id, which is the session id.
+const REVOKE_FAMILY: [&str; 2] = [
+    "UPDATE sessions SET revoked_at = ?1 WHERE id = ?2 AND revoked_at IS NULL",
+    "UPDATE refresh_tokens SET revoked_at = ?1 WHERE family_id = ?2 AND revoked_at IS NULL",
+];
+
+/// Revoke a sign-in: its session and every token descended from it
+pub async fn revoke_family(db: &D1Database, family_id: &str) -> Result<()> {
+    let now: wasm_bindgen::JsValue = (chrono::Utc::now().timestamp() as f64).into();
+    let statements = REVOKE_FAMILY
+        .iter()
+        .map(|sql| db.prepare(*sql).bind(&[now.clone(), family_id.into()]))
+        .collect::<Result<Vec<_>>>()?;
+    db.batch(statements).await?;
+    Ok(())
+}
+
+/// Exchange a refresh token for a new access token and refresh token. The old
+/// refresh token is spent; presenting it again revokes the whole family.
+pub async fn refresh(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let body: RefreshRequest = match req.json().await {
+        Ok(b) => b,
+        Err(_) => {
+            return ApiError::validation("Invalid request body").into_response();
+        }
+    };
+
+    let db = ctx.env.d1("DB")?;
+    let now = chrono::Utc::now().timestamp();
+
+    let record = db
+        .prepare("SELECT id, user_id, family_id, expires_at, used_at, revoked_at FROM refresh_tokens WHERE token_hash = ?1")
+        .bind(&[hash_token(body.refresh_token.trim()).into()])?
+        .first::<serde_json::Value>(None)
+        .await?
+        .as_ref()
+        .and_then(RefreshRecord::from_row);
+
+    let Some(record) = record else {
+        return ApiError::Unauthorized("Invalid refresh token".to_string()).into_response();
+    };
+
+    match record.check(now) {
+        RefreshCheck::Valid => {}
+        RefreshCheck::Reused => {
+            console_log!(
+                "Refresh token reuse detected, revoking family {}",
+                record.family_id
+            );
+            revoke_family(&db, &record.family_id).await?;
+            return ApiError::Unauthorized("Refresh token already used".to_string())
+                .into_response();
+        }
+        RefreshCheck::Revoked => {
+            return ApiError::Unauthorized("Refresh token revoked".to_string()).into_response();
+        }
+        RefreshCheck::Expired => {
+            return ApiError::Unauthorized("Refresh token expired".to_string()).into_response();
+        }
+    }
+
+    // Conditional, so two concurrent exchanges can't both succeed
+    let spent = db
+        .prepare("UPDATE refresh_tokens SET used_at = ?1 WHERE id = ?2 AND used_at IS NULL AND revoked_at IS NULL")
+        .bind(&[(now as f64).into(), record.id.clone().into()])?
+        .run()
+        .await?
+        .meta()?
+        .and_then(|m| m.changes)
+        .is_some_and(|changes| changes > 0);
+
+    if !spent {
+        revoke_family(&db, &record.family_id).await?;
+        return ApiError::Unauthorized("Refresh token already used".to_string()).into_response();
+    }
+
+    let refresh_token = issue(&db, &record.user_id, &record.family_id).await?;
+    let country = req.cf().and_then(|cf| cf.country());
+    sessions::touch(&db, &record.family_id, country.as_deref()).await?;
+
+    Response::from_json(&ApiResponse::success(TokenPair {
+        token: generate_token(&record.user_id, &record.family_id, &ctx)?,
+        expires_in: ACCESS_TOKEN_TTL_SECS,
+        refresh_token,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> RefreshRecord {
+        RefreshRecord {
+            id: "t1".to_string(),
+            user_id: "u1".to_string(),
+            family_id: "f1".to_string(),
+            expires_at: 2_000,
+            used_at: None,
+            revoked_at: None,
+        }
+    }
+
+    #[test]
+    fn test_check_valid_and_expired() {
+        assert_eq!(record().check(1_999), RefreshCheck::Valid);
+        assert_eq!(record().check(2_000), RefreshCheck::Expired);
+    }
+
+    #[test]
+    fn test_check_reuse_wins() {
+        let reused = RefreshRecord {
+            used_at: Some(1_500),
+            revoked_at: Some(1_600),
+            ..record()
+        };
+        assert_eq!(reused.check(3_000), RefreshCheck::Reused);
+
+        let revoked = RefreshRecord {
+            revoked_at: Some(1_600),
+            ..record()
+        };
+        assert_eq!(revoked.check(1_700), RefreshCheck::Revoked);
+    }
+
+    #[test]
+    fn test_from_row() {
+        let row = serde_json::json!({
+            "id": "t1",
+            "user_id": "u1",
+            "family_id": "f1",
+            "expires_at": 2000.0,
+            "used_at": null,
+            "revoked_at": null,
+        });
+        assert_eq!(RefreshRecord::from_row(&row), Some(record()));
+        assert_eq!(RefreshRecord::from_row(&serde_json::json!({})), None);
+    }
+
+    #[test]
+    fn test_revoke_family_ends_the_session() {
+        // Reuse and lost races revoke by family id; the session shares that id
+        assert!(REVOKE_FAMILY[0].starts_with("UPDATE sessions SET revoked_at = ?1 WHERE id = ?2"));
+        assert!(
+            REVOKE_FAMILY[1]
+                .starts_with("UPDATE refresh_tokens SET revoked_at = ?1 WHERE family_id = ?2")
+        );
+    }
+
+    #[test]
+    fn test_tokens_are_random_and_hashed() {
+        let a = new_token();
+        let b = new_token();
+        assert_ne!(a, b);
+        assert_eq!(URL_SAFE_NO_PAD.decode(&a).unwrap().len(), 32);
+
+        assert_eq!(hash_token(&a), hash_token(&a));
+        assert_ne!(hash_token(&a), hash_token(&b));
+        assert!(!hash_token(&a).contains(&a));
+    }
+}
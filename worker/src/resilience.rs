@@ -0,0 +1,166 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+use worker::Env;
+
+const DEFAULT_MAX_RETRIES: u32 = 2;
+const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+const MAX_TIMEOUT_MS: u64 = 120_000;
+const BASE_BACKOFF_MS: u64 = 250;
+const MAX_BACKOFF_MS: u64 = 4_000;
+
+/// Consecutive failed calls after which a backend is skipped
+const BREAKER_THRESHOLD: u32 = 3;
+/// How long a tripped backend is skipped before it gets another try
+const BREAKER_COOLDOWN_MS: i64 = 30_000;
+
+/// How hard to try one backend before falling back to the next
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Extra attempts after the first, for retryable errors only
+    pub max_retries: u32,
+    /// Per attempt, until the response headers arrive
+    pub timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            timeout: Duration::from_millis(DEFAULT_TIMEOUT_MS),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// `LLM_MAX_RETRIES` and `LLM_TIMEOUT_MS`, falling back to the defaults
+    pub fn from_env(env: &Env) -> Self {
+        let var = |name: &str| env.var(name).ok().map(|v| v.to_string());
+        let defaults = Self::default();
+        Self {
+            max_retries: var("LLM_MAX_RETRIES")
+                .and_then(|v| v.trim().parse().ok())
+                .filter(|&n| n <= 5)
+                .unwrap_or(defaults.max_retries),
+            timeout: var("LLM_TIMEOUT_MS")
+                .and_then(|v| v.trim().parse().ok())
+                .filter(|&ms| (1..=MAX_TIMEOUT_MS).contains(&ms))
+                .map_or(defaults.timeout, Duration::from_millis),
+        }
+    }
+
+    /// Exponential backoff before retry `attempt` (1-based) with equal jitter:
+    /// half the step is fixed, the other half scaled by `random` in [0, 1)
+    pub fn backoff(&self, attempt: u32, random: f64) -> Duration {
+        let step = BASE_BACKOFF_MS
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(MAX_BACKOFF_MS);
+        let half = step / 2;
+        Duration::from_millis(half + (half as f64 * random.clamp(0.0, 1.0)) as u64)
+    }
+}
+
+/// Failure count for one backend. Once tripped it is skipped until the cooldown
+/// passes, then half-open: the next call decides whether it closes or trips again.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Breaker {
+    failures: u32,
+    /// Unix millis until which the backend is skipped
+    open_until: i64,
+}
+
+impl Breaker {
+    fn allows(&self, now: i64) -> bool {
+        self.failures < BREAKER_THRESHOLD || now >= self.open_until
+    }
+
+    fn record(&mut self, success: bool, now: i64) {
+        if success {
+            *self = Self::default();
+            return;
+        }
+        self.failures += 1;
+        if self.failures >= BREAKER_THRESHOLD {
+            self.open_until = now + BREAKER_COOLDOWN_MS;
+        }
+    }
+}
+
+thread_local! {
+    /// Per isolate, so each instance learns about an outage on its own
+    static BREAKERS: RefCell<HashMap<String, Breaker>> = RefCell::new(HashMap::new());
+}
+
+/// Whether calls to the backend identified by `key` should go ahead
+pub fn breaker_allows(key: &str, now: i64) -> bool {
+    BREAKERS.with(|breakers| breakers.borrow().get(key).is_none_or(|b| b.allows(now)))
+}
+
+pub fn breaker_record(key: &str, success: bool, now: i64) {
+    BREAKERS.with(|breakers| {
+        breakers
+            .borrow_mut()
+            .entry(key.to_string())
+            .or_default()
+            .record(success, now)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_with_jitter_and_cap() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(1, 0.0), Duration::from_millis(125));
+        assert_eq!(policy.backoff(1, 0.999), Duration::from_millis(249));
+        assert_eq!(policy.backoff(2, 0.0), Duration::from_millis(250));
+        assert_eq!(policy.backoff(3, 0.5), Duration::from_millis(750));
+        assert_eq!(policy.backoff(10, 0.0), Duration::from_millis(2_000));
+        assert!(policy.backoff(40, 1.0) <= Duration::from_millis(MAX_BACKOFF_MS));
+    }
+
+    #[test]
+    fn test_breaker_trips_after_threshold() {
+        let mut breaker = Breaker::default();
+        for _ in 0..BREAKER_THRESHOLD - 1 {
+            breaker.record(false, 1_000);
+        }
+        assert!(breaker.allows(1_000));
+
+        breaker.record(false, 1_000);
+        assert!(!breaker.allows(1_000));
+        assert!(!breaker.allows(1_000 + BREAKER_COOLDOWN_MS - 1));
+        assert!(breaker.allows(1_000 + BREAKER_COOLDOWN_MS));
+    }
+
+    #[test]
+    fn test_breaker_half_open() {
+        let mut breaker = Breaker::default();
+        for _ in 0..BREAKER_THRESHOLD {
+            breaker.record(false, 0);
+        }
+        let later = BREAKER_COOLDOWN_MS;
+
+        // A failed trial trips it again straight away
+        breaker.record(false, later);
+        assert!(!breaker.allows(later + 1));
+
+        // A successful one closes it
+        breaker.record(true, later * 3);
+        assert_eq!(breaker, Breaker::default());
+    }
+
+    #[test]
+    fn test_breaker_registry_is_per_key() {
+        for _ in 0..BREAKER_THRESHOLD {
+            breaker_record("openai:test-a", false, 0);
+        }
+        assert!(!breaker_allows("openai:test-a", 1));
+        assert!(breaker_allows("openai:test-b", 1));
+
+        breaker_record("openai:test-a", true, 2);
+        assert!(breaker_allows("openai:test-a", 3));
+    }
+}
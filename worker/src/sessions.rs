@@ -0,0 +1,365 @@
+use crate::auth::authenticate;
+use crate::error::ApiError;
+use crate::models::{ApiResponse, LogoutRequest, SessionInfo};
+use crate::refresh;
+use worker::*;
+
+const MAX_DEVICE_NAME_LENGTH: usize = 100;
+
+const SESSION_COLUMNS: &str = "id, device_name, ip_country, created_at, last_seen_at";
+
+/// Where a sign-in came from, as shown in the session list
+pub struct Device {
+    pub name: Option<String>,
+    /// ISO 3166 country code from Cloudflare's IP geolocation
+    pub country: Option<String>,
+}
+
+impl Device {
+    /// The name the client gave, or one derived from its User-Agent
+    pub fn from_request(req: &Request, requested_name: Option<&str>) -> Self {
+        let user_agent = req.headers().get("User-Agent").ok().flatten();
+        Self {
+            name: device_name(requested_name, user_agent.as_deref()),
+            country: req.cf().and_then(|cf| cf.country()),
+        }
+    }
+}
+
+/// Start a session for a new sign-in and return its id
+pub async fn create(db: &D1Database, user_id: &str, device: &Device) -> Result<String> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().timestamp() as f64;
+    db.prepare("INSERT INTO sessions (id, user_id, device_name, ip_country, created_at, last_seen_at) VALUES (?1, ?2, ?3, ?4, ?5, ?5)")
+        .bind(&[
+            id.clone().into(),
+            user_id.into(),
+            optional(device.name.as_deref()),
+            optional(device.country.as_deref()),
+            now.into(),
+        ])?
+        .run()
+        .await?;
+    Ok(id)
+}
+
+/// Note activity on a session. Called on refresh, so `last_seen_at` is accurate
+/// to the access token lifetime without a write on every request.
+pub async fn touch(db: &D1Database, session_id: &str, country: Option<&str>) -> Result<()> {
+    db.prepare(
+        "UPDATE sessions SET last_seen_at = ?1, ip_country = COALESCE(?2, ip_country) WHERE id = ?3",
+    )
+    .bind(&[
+        (chrono::Utc::now().timestamp() as f64).into(),
+        optional(country),
+        session_id.into(),
+    ])?
+    .run()
+    .await?;
+    Ok(())
+}
+
+/// Whether the session exists, belongs to the user and hasn't been revoked
+pub async fn is_active(db: &D1Database, user_id: &str, session_id: &str) -> Result<bool> {
+    Ok(db
+        .prepare("SELECT id FROM sessions WHERE id = ?1 AND user_id = ?2 AND revoked_at IS NULL")
+        .bind(&[session_id.into(), user_id.into()])?
+        .first::<String>(Some("id"))
+        .await?
+        .is_some())
+}
+
+/// Revoke one of the user's sessions and its refresh tokens. False if the user
+/// has no such active session.
+pub async fn revoke(db: &D1Database, user_id: &str, session_id: &str) -> Result<bool> {
+    let revoked = db
+        .prepare("UPDATE sessions SET revoked_at = ?1 WHERE id = ?2 AND user_id = ?3 AND revoked_at IS NULL")
+        .bind(&[
+            (chrono::Utc::now().timestamp() as f64).into(),
+            session_id.into(),
+            user_id.into(),
+        ])?
+        .run()
+        .await?
+        .meta()?
+        .and_then(|m| m.changes)
+        .is_some_and(|changes| changes > 0);
+
+    if revoked {
+        refresh::revoke_family(db, session_id).await?;
+    }
+    Ok(revoked)
+}
+
+/// Sign the user out everywhere, including tokens issued before sessions existed
+pub async fn revoke_all(db: &D1Database, user_id: &str) -> Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    db.batch(revoke_all_statements(db, user_id, now)?).await?;
+    Ok(())
+}
+
+/// Signing out everywhere, with `?1` the time and `?2` the user id
+const REVOKE_ALL: [&str; 3] = [
+    "UPDATE sessions SET revoked_at = ?1 WHERE user_id = ?2 AND revoked_at IS NULL",
+    "UPDATE refresh_tokens SET revoked_at = ?1 WHERE user_id = ?2 AND revoked_at IS NULL",
+    "UPDATE users SET signed_out_at = ?1 WHERE id = ?2",
+];
+
+/// The statements behind [`revoke_all`], for callers that must batch them with
+/// their own change to the account
+pub fn revoke_all_statements(
+    db: &D1Database,
+    user_id: &str,
+    now: i64,
+) -> Result<Vec<D1PreparedStatement>> {
+    let now: wasm_bindgen::JsValue = (now as f64).into();
+    REVOKE_ALL
+        .iter()
+        .map(|sql| db.prepare(*sql).bind(&[now.clone(), user_id.into()]))
+        .collect()
+}
+
+/// Like [`revoke_all_statements`], but each statement only applies while
+/// `condition` holds, with `arg` bound to `?3`. Lets a batch sign out only if an
+/// earlier statement's precondition was met.
+pub fn revoke_all_statements_if(
+    db: &D1Database,
+    user_id: &str,
+    now: i64,
+    condition: &'static str,
+    arg: &str,
+) -> Result<Vec<D1PreparedStatement>> {
+    let now: wasm_bindgen::JsValue = (now as f64).into();
+    REVOKE_ALL
+        .iter()
+        .map(|sql| {
+            db.prepare(format!("{} AND {}", sql, condition)).bind(&[
+                now.clone(),
+                user_id.into(),
+                arg.into(),
+            ])
+        })
+        .collect()
+}
+
+/// The signed-in user's active sessions, most recently used first
+pub async fn list_sessions(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let auth = match authenticate(&req, &ctx).await {
+        Ok(auth) => auth,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let rows = ctx
+        .env
+        .d1("DB")?
+        .prepare(format!(
+            "SELECT {} FROM sessions WHERE user_id = ?1 AND revoked_at IS NULL ORDER BY last_seen_at DESC",
+            SESSION_COLUMNS
+        ))
+        .bind(&[auth.user_id.into()])?
+        .all()
+        .await?
+        .results::<serde_json::Value>()?;
+
+    let sessions: Vec<SessionInfo> = rows
+        .iter()
+        .map(|row| session_from_row(row, auth.session_id.as_deref()))
+        .collect();
+
+    Response::from_json(&ApiResponse::success(sessions))
+}
+
+pub async fn delete_session(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let auth = match authenticate(&req, &ctx).await {
+        Ok(auth) => auth,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let session_id = ctx.param("id").cloned().unwrap_or_default();
+    if !revoke(&ctx.env.d1("DB")?, &auth.user_id, &session_id).await? {
+        return ApiError::NotFound("Session not found".to_string()).into_response();
+    }
+
+    Response::from_json(&ApiResponse::success(
+        serde_json::json!({ "revoked": session_id }),
+    ))
+}
+
+/// Sign out this session, or with `{"everywhere": true}` every session
+pub async fn logout(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let auth = match authenticate(&req, &ctx).await {
+        Ok(auth) => auth,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    // The body is optional
+    let text = req.text().await.unwrap_or_default();
+    let body: LogoutRequest = if text.trim().is_empty() {
+        LogoutRequest::default()
+    } else {
+        match serde_json::from_str(&text) {
+            Ok(b) => b,
+            Err(_) => {
+                return ApiError::validation("Invalid request body").into_response();
+            }
+        }
+    };
+
+    let db = ctx.env.d1("DB")?;
+    match (&auth.session_id, body.everywhere) {
+        (Some(session_id), false) => {
+            revoke(&db, &auth.user_id, session_id).await?;
+        }
+        // Tokens from before sessions can only be revoked all at once
+        _ => revoke_all(&db, &auth.user_id).await?,
+    }
+
+    Response::from_json(&ApiResponse::success(serde_json::json!({
+        "everywhere": body.everywhere || auth.session_id.is_none(),
+    })))
+}
+
+fn optional(value: Option<&str>) -> wasm_bindgen::JsValue {
+    match value {
+        Some(value) => value.into(),
+        None => wasm_bindgen::JsValue::NULL,
+    }
+}
+
+fn session_from_row(row: &serde_json::Value, current: Option<&str>) -> SessionInfo {
+    let id = row["id"].as_str().unwrap_or("").to_string();
+    SessionInfo {
+        current: current == Some(id.as_str()),
+        id,
+        device_name: row["device_name"].as_str().map(str::to_string),
+        ip_country: row["ip_country"].as_str().map(str::to_string),
+        created_at: row["created_at"].as_f64().unwrap_or(0.0) as i64,
+        last_seen_at: row["last_seen_at"].as_f64().unwrap_or(0.0) as i64,
+    }
+}
+
+/// A display name for the device: the client's own, else e.g. "Firefox on macOS"
+pub fn device_name(requested: Option<&str>, user_agent: Option<&str>) -> Option<String> {
+    let name = match requested.map(str::trim).filter(|n| !n.is_empty()) {
+        Some(name) => name.to_string(),
+        None => describe_user_agent(user_agent?)?,
+    };
+    Some(name.chars().take(MAX_DEVICE_NAME_LENGTH).collect())
+}
+
+fn describe_user_agent(user_agent: &str) -> Option<String> {
+    // Order matters: iPad and Android agents also mention other platforms
+    let os = [
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("Android", "Android"),
+        ("Mac OS X", "macOS"),
+        ("Windows", "Windows"),
+        ("CrOS", "ChromeOS"),
+        ("Linux", "Linux"),
+    ]
+    .into_iter()
+    .find(|(needle, _)| user_agent.contains(needle))
+    .map(|(_, os)| os);
+
+    // Edge and Chrome also claim Safari; Edge also claims Chrome
+    let browser = [
+        ("Edg/", "Edge"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+    ]
+    .into_iter()
+    .find(|(needle, _)| user_agent.contains(needle))
+    .map(|(_, browser)| browser.to_string())
+    // Native clients, e.g. `mumble-fish/1.4 CFNetwork/1490`
+    .or_else(|| {
+        let product = user_agent.split_whitespace().next()?;
+        let name = product.split('/').next()?;
+        (!name.is_empty() && name != "Mozilla").then(|| name.to_string())
+    });
+
+    match (browser, os) {
+        (Some(browser), Some(os)) => Some(format!("{} on {}", browser, os)),
+        (Some(browser), None) => Some(browser),
+        (None, Some(os)) => Some(os.to_string()),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_name_prefers_requested() {
+        assert_eq!(
+            device_name(Some("  Work laptop "), Some("Mozilla/5.0")).as_deref(),
+            Some("Work laptop")
+        );
+        assert_eq!(
+            device_name(Some(&"x".repeat(500)), None).map(|n| n.len()),
+            Some(MAX_DEVICE_NAME_LENGTH)
+        );
+        assert_eq!(device_name(Some(" "), None), None);
+    }
+
+    #[test]
+    fn test_describe_user_agent() {
+        let cases = [
+            (
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:128.0) Gecko/20100101 Firefox/128.0",
+                "Firefox on macOS",
+            ),
+            (
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36 Edg/126.0.0.0",
+                "Edge on Windows",
+            ),
+            (
+                "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1",
+                "Safari on iOS",
+            ),
+            (
+                "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Mobile Safari/537.36",
+                "Chrome on Android",
+            ),
+            (
+                "mumble-fish/1.4 CFNetwork/1490 Darwin/23.2.0",
+                "mumble-fish",
+            ),
+        ];
+        for (user_agent, expected) in cases {
+            assert_eq!(
+                describe_user_agent(user_agent).as_deref(),
+                Some(expected),
+                "{}",
+                user_agent
+            );
+        }
+        assert_eq!(describe_user_agent("Mozilla/5.0"), None);
+        assert_eq!(describe_user_agent(""), None);
+    }
+
+    #[test]
+    fn test_session_from_row() {
+        let row = serde_json::json!({
+            "id": "s1",
+            "device_name": "Chrome on macOS",
+            "ip_country": null,
+            "created_at": 1000.0,
+            "last_seen_at": 2000.0,
+        });
+        let session = session_from_row(&row, Some("s1"));
+        assert!(session.current);
+        assert_eq!(session.device_name.as_deref(), Some("Chrome on macOS"));
+        assert_eq!(session.ip_country, None);
+        assert_eq!(session.last_seen_at, 2000);
+        assert!(!session_from_row(&row, None).current);
+    }
+}
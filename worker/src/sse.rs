@@ -0,0 +1,133 @@
+use serde::Serialize;
+
+/// Incremental parser for an upstream `text/event-stream` body.
+///
+/// Bytes are fed as they arrive and complete events are returned as their
+/// joined `data:` payloads. Partial events (and partial UTF-8 sequences) stay
+/// buffered until the terminating blank line shows up.
+#[derive(Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    /// How much of `buffer` is known to hold no blank line, so long events
+    /// arriving in small pieces aren't rescanned from the start every time
+    scanned: usize,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a chunk and return the `data` payload of every completed event
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        // Back up three bytes in case a `\r\n\r\n` straddles the last chunk
+        while let Some((end, sep_len)) =
+            find_event_boundary(&self.buffer, self.scanned.saturating_sub(3))
+        {
+            let block: Vec<u8> = self.buffer.drain(..end + sep_len).take(end).collect();
+            self.scanned = 0;
+            if let Some(data) = parse_data(&String::from_utf8_lossy(&block)) {
+                events.push(data);
+            }
+        }
+        self.scanned = self.buffer.len();
+        events
+    }
+}
+
+/// Find the first blank line at or after `from`, returning its offset and the
+/// separator length
+fn find_event_boundary(buffer: &[u8], from: usize) -> Option<(usize, usize)> {
+    for i in from..buffer.len() {
+        if buffer[i..].starts_with(b"\n\n") {
+            return Some((i, 2));
+        }
+        if buffer[i..].starts_with(b"\r\n\r\n") {
+            return Some((i, 4));
+        }
+    }
+    None
+}
+
+/// Join the `data:` lines of a single event block, ignoring comments and other fields
+fn parse_data(block: &str) -> Option<String> {
+    let lines: Vec<&str> = block
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect();
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+/// Format a named server-sent event with a JSON payload
+pub fn event<T: Serialize>(name: &str, data: &T) -> Vec<u8> {
+    let json = serde_json::to_string(data).unwrap_or_else(|_| "null".to_string());
+    format!("event: {}\ndata: {}\n\n", name, json).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parser_single_event() {
+        let mut parser = SseParser::new();
+        let events = parser.feed(b"data: {\"a\":1}\n\n");
+        assert_eq!(events, vec!["{\"a\":1}".to_string()]);
+    }
+
+    #[test]
+    fn test_parser_split_across_chunks() {
+        let mut parser = SseParser::new();
+        assert!(parser.feed(b"data: hel").is_empty());
+        assert!(parser.feed(b"lo\n").is_empty());
+        assert_eq!(parser.feed(b"\ndata: [DONE]\n\n"), vec!["hello", "[DONE]"]);
+    }
+
+    #[test]
+    fn test_parser_split_utf8_sequence() {
+        let bytes = "data: café\n\n".as_bytes();
+        let split = bytes.len() - 3; // inside the two-byte 'é'
+
+        let mut parser = SseParser::new();
+        assert!(parser.feed(&bytes[..split]).is_empty());
+        assert_eq!(parser.feed(&bytes[split..]), vec!["café"]);
+    }
+
+    #[test]
+    fn test_parser_crlf_and_comments() {
+        let mut parser = SseParser::new();
+        let events = parser.feed(b": keep-alive\r\n\r\nevent: x\r\ndata: one\r\ndata: two\r\n\r\n");
+        assert_eq!(events, vec!["one\ntwo"]);
+    }
+
+    #[test]
+    fn test_parser_resumes_scan() {
+        let mut parser = SseParser::new();
+        assert!(parser.feed(b"data: long").is_empty());
+        assert_eq!(parser.scanned, 10);
+        assert!(parser.feed(b" event\r\n\r").is_empty());
+        // The separator straddles two feeds and is still found
+        assert_eq!(parser.feed(b"\ndata: next\n"), vec!["long event"]);
+        assert_eq!(parser.scanned, 11);
+        assert_eq!(parser.feed(b"\n"), vec!["next"]);
+        assert_eq!(parser.scanned, 0);
+    }
+
+    #[test]
+    fn test_event_format() {
+        let bytes = event("delta", &serde_json::json!({ "text": "hi" }));
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "event: delta\ndata: {\"text\":\"hi\"}\n\n"
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use crate::access::{self, Estimate};
+use crate::error::ApiError;
+use crate::guard;
+use crate::models::{ApiResponse, NoteSummary, SummarizeRequest, SummarizeResponse};
+use crate::notes::set_note_title;
+use crate::provider::{CompletionOptions, LlmProvider, Provider, ProviderError, Spend, SpentError};
+use crate::redact::Redaction;
+use crate::usage::PendingUsage;
+use worker::*;
+
+/// One call covers the whole transcript, so allow what polish allows across chunks
+const MAX_SUMMARIZE_LENGTH_HOSTED: usize = 64_000;
+
+const MAX_TITLE_LENGTH: usize = 100;
+const MAX_TOPICS: usize = 8;
+
+const SUMMARIZE_PROMPT: &str = "You summarize dictated notes. Respond with a JSON object only, no code fences or explanation, of the form {\"title\": string, \"summary\": string, \"topics\": [string]}. The title is at most 8 words. The summary is one paragraph of 1-3 sentences. Topics are up to 5 short key topics in lowercase. Write all of them in the same language as the note.";
+
+pub async fn summarize(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let access = match access::authorize(&req, &ctx).await {
+        Ok(access) => access,
+        Err(e) => return e.into_response(),
+    };
+
+    let body: SummarizeRequest = match req.json().await {
+        Ok(b) => b,
+        Err(_) => {
+            return ApiError::validation("Invalid request body").into_response();
+        }
+    };
+
+    let text = body.text.trim();
+    if text.is_empty() {
+        return ApiError::validation("Text cannot be empty").into_response();
+    }
+    if !access.is_byok && text.len() > MAX_SUMMARIZE_LENGTH_HOSTED {
+        return ApiError::Validation {
+            message: format!(
+                "Text too long ({} chars). Maximum is {} chars. Use your own API key for longer texts.",
+                text.len(),
+                MAX_SUMMARIZE_LENGTH_HOSTED
+            ),
+            details: Some(serde_json::json!({ "max_length": MAX_SUMMARIZE_LENGTH_HOSTED })),
+        }
+        .into_response();
+    }
+
+    if let Err(e) = access.check_estimate(Estimate::read(text.chars().count())) {
+        return e.into_response();
+    }
+
+    if body.note_id.is_some() && access.user_id.is_none() {
+        return ApiError::Unauthorized("Sign in to title notes".to_string()).into_response();
+    }
+
+    let pending_usage = PendingUsage::start(ctx.env.d1("DB")?, &access, "summarize");
+
+    let mut redaction = Redaction::new(access.redacts(body.redact));
+    let (summary, spend) = match summarize_text(&access.provider, text, &mut redaction).await {
+        Ok(result) => result,
+        Err(e) => {
+            console_error!("Provider error: {}", e);
+            pending_usage.record_spent(e.spend.clone()).await;
+            return ApiError::from(e).into_response();
+        }
+    };
+
+    let served_by = access.provider.served_by(&spend);
+    pending_usage.record(spend.clone()).await;
+
+    if let (Some(note_id), Some(user_id)) = (&body.note_id, &access.user_id)
+        && !set_note_title(&ctx.env.d1("DB")?, user_id, note_id, &summary.title).await?
+    {
+        return ApiError::NotFound("Note not found".to_string()).into_response();
+    }
+
+    let billed = access.billed(&spend);
+    let quota = access.quota.map(|mut quota| {
+        quota.consume(billed);
+        quota
+    });
+
+    Response::from_json(&ApiResponse::success(SummarizeResponse {
+        summary,
+        served_by,
+        usage: spend.usage(),
+        quota,
+    }))
+}
+
+/// Generate a title, summary and topics for `text` in a single provider call.
+/// Personal data is redacted on the way out and restored in the result.
+pub async fn summarize_text(
+    provider: &Provider,
+    text: &str,
+    redaction: &mut Redaction,
+) -> std::result::Result<(NoteSummary, Spend), SpentError> {
+    let text = redaction.redact(text);
+    let completion = provider
+        .complete(
+            &guard::system_prompt(&redaction.extend_prompt(SUMMARIZE_PROMPT)),
+            &guard::wrap(&text),
+            &CompletionOptions::default(),
+        )
+        .await?;
+
+    // A reply that doesn't parse was still billed
+    match parse_summary(&redaction.restore(&completion.text)) {
+        Ok(summary) => Ok((summary, completion.spend)),
+        Err(e) => Err(SpentError {
+            error: ProviderError::InvalidResponse(e),
+            spend: completion.spend,
+        }),
+    }
+}
+
+/// Parse the model's JSON, tolerating code fences or prose around the object
+fn parse_summary(text: &str) -> std::result::Result<NoteSummary, String> {
+    let start = text.find('{').ok_or("No JSON object in summary")?;
+    let end = text.rfind('}').ok_or("No JSON object in summary")?;
+    if end < start {
+        return Err("No JSON object in summary".to_string());
+    }
+
+    let mut summary: NoteSummary =
+        serde_json::from_str(&text[start..=end]).map_err(|e| format!("Invalid summary: {}", e))?;
+
+    summary.title = summary.title.trim().trim_matches('"').trim().to_string();
+    if summary.title.is_empty() {
+        return Err("Summary has no title".to_string());
+    }
+    if summary.title.chars().count() > MAX_TITLE_LENGTH {
+        summary.title = summary.title.chars().take(MAX_TITLE_LENGTH).collect();
+    }
+    summary.summary = summary.summary.trim().to_string();
+    summary.topics = summary
+        .topics
+        .into_iter()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .take(MAX_TOPICS)
+        .collect();
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_summary() {
+        let summary = parse_summary(
+            r#"{"title":" Roadmap sync ","summary":"We agreed on Q3 goals.","topics":["roadmap"," ","hiring"]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(summary.title, "Roadmap sync");
+        assert_eq!(summary.summary, "We agreed on Q3 goals.");
+        assert_eq!(summary.topics, vec!["roadmap", "hiring"]);
+    }
+
+    #[test]
+    fn test_parse_summary_with_code_fence() {
+        let text = "```json\n{\"title\": \"Lunch\", \"summary\": \"Tacos at noon.\"}\n```";
+        let summary = parse_summary(text).unwrap();
+        assert_eq!(summary.title, "Lunch");
+        assert!(summary.topics.is_empty());
+    }
+
+    #[test]
+    fn test_parse_summary_rejects_invalid() {
+        assert!(parse_summary("Here is a summary of your note.").is_err());
+        assert!(parse_summary(r#"{"summary": "No title"}"#).is_err());
+        assert!(parse_summary(r#"{"title": "  ", "summary": "Blank"}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_summary_caps_title_and_topics() {
+        let text = serde_json::json!({
+            "title": "t".repeat(MAX_TITLE_LENGTH + 10),
+            "summary": "s",
+            "topics": (0..20).map(|i| i.to_string()).collect::<Vec<_>>()
+        })
+        .to_string();
+
+        let summary = parse_summary(&text).unwrap();
+        assert_eq!(summary.title.chars().count(), MAX_TITLE_LENGTH);
+        assert_eq!(summary.topics.len(), MAX_TOPICS);
+    }
+}
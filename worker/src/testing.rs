@@ -0,0 +1,18 @@
+//! Helpers shared by unit tests
+
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+/// Run a future to completion in one poll. The test doubles (mock providers,
+/// `MemoryMailer`) never suspend, so anything that does is a bug in the test.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    match future
+        .as_mut()
+        .poll(&mut Context::from_waker(Waker::noop()))
+    {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("test future suspended"),
+    }
+}
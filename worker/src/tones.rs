@@ -0,0 +1,309 @@
+use crate::auth::extract_and_verify_token;
+use crate::error::ApiError;
+use crate::models::{ApiResponse, CustomTone, ToneInfo, ToneInput, ToneStyle};
+use worker::*;
+
+const MAX_TONE_NAME_LENGTH: usize = 50;
+const MAX_TONE_INSTRUCTIONS_LENGTH: usize = 2000;
+const MAX_TONE_EXAMPLES: usize = 5;
+const MAX_TONE_EXAMPLE_LENGTH: usize = 1000;
+const MAX_TONES_PER_USER: i64 = 50;
+
+/// List built-in tones, followed by the caller's custom tones when signed in
+pub async fn list_tones(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let mut tones: Vec<ToneInfo> = ToneStyle::ALL.into_iter().map(ToneInfo::from).collect();
+
+    if let Ok(user_id) = extract_and_verify_token(&req, &ctx).await {
+        let db = ctx.env.d1("DB")?;
+        let rows = db
+            .prepare(
+                "SELECT id, name, instructions, examples, created_at, updated_at FROM tones WHERE user_id = ?1 ORDER BY created_at",
+            )
+            .bind(&[user_id.into()])?
+            .all()
+            .await?
+            .results::<serde_json::Value>()?;
+
+        tones.extend(rows.iter().map(|row| ToneInfo::from(tone_from_row(row))));
+    }
+
+    Response::from_json(&ApiResponse::success(tones))
+}
+
+pub async fn create_tone(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
+        Ok(id) => id,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let body: ToneInput = match req.json().await {
+        Ok(b) => b,
+        Err(_) => {
+            return ApiError::validation("Invalid request body").into_response();
+        }
+    };
+
+    if let Err(e) = validate_tone(&body) {
+        return ApiError::validation(e).into_response();
+    }
+
+    let db = ctx.env.d1("DB")?;
+
+    let count = db
+        .prepare("SELECT COUNT(*) AS count FROM tones WHERE user_id = ?1")
+        .bind(&[user_id.clone().into()])?
+        .first::<f64>(Some("count"))
+        .await?
+        .unwrap_or(0.0) as i64;
+
+    if count >= MAX_TONES_PER_USER {
+        return ApiError::validation(format!(
+            "Custom tone limit reached ({} tones)",
+            MAX_TONES_PER_USER
+        ))
+        .into_response();
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let tone = CustomTone {
+        id: uuid::Uuid::new_v4().to_string(),
+        name: body.name.trim().to_string(),
+        instructions: body.instructions.trim().to_string(),
+        examples: body.examples,
+        created_at: now,
+        updated_at: now,
+    };
+
+    db.prepare("INSERT INTO tones (id, user_id, name, instructions, examples, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")
+        .bind(&[
+            tone.id.clone().into(),
+            user_id.into(),
+            tone.name.clone().into(),
+            tone.instructions.clone().into(),
+            serde_json::to_string(&tone.examples)?.into(),
+            (now as f64).into(),
+            (now as f64).into(),
+        ])?
+        .run()
+        .await?;
+
+    Response::from_json(&ApiResponse::success(tone)).map(|r| r.with_status(201))
+}
+
+pub async fn update_tone(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
+        Ok(id) => id,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let tone_id = ctx.param("id").cloned().unwrap_or_default();
+
+    let body: ToneInput = match req.json().await {
+        Ok(b) => b,
+        Err(_) => {
+            return ApiError::validation("Invalid request body").into_response();
+        }
+    };
+
+    if let Err(e) = validate_tone(&body) {
+        return ApiError::validation(e).into_response();
+    }
+
+    let db = ctx.env.d1("DB")?;
+    let now = chrono::Utc::now().timestamp() as f64;
+
+    db.prepare("UPDATE tones SET name = ?1, instructions = ?2, examples = ?3, updated_at = ?4 WHERE id = ?5 AND user_id = ?6")
+        .bind(&[
+            body.name.trim().into(),
+            body.instructions.trim().into(),
+            serde_json::to_string(&body.examples)?.into(),
+            now.into(),
+            tone_id.clone().into(),
+            user_id.clone().into(),
+        ])?
+        .run()
+        .await?;
+
+    match find_tone(&db, &user_id, &tone_id).await? {
+        Some(tone) => Response::from_json(&ApiResponse::success(tone)),
+        None => ApiError::NotFound("Tone not found".to_string()).into_response(),
+    }
+}
+
+pub async fn delete_tone(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
+        Ok(id) => id,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let tone_id = ctx.param("id").cloned().unwrap_or_default();
+    let db = ctx.env.d1("DB")?;
+
+    let result = db
+        .prepare("DELETE FROM tones WHERE id = ?1 AND user_id = ?2")
+        .bind(&[tone_id.into(), user_id.into()])?
+        .run()
+        .await?;
+
+    let deleted = result
+        .meta()?
+        .and_then(|m| m.changes)
+        .is_some_and(|changes| changes > 0);
+
+    if !deleted {
+        return ApiError::NotFound("Tone not found".to_string()).into_response();
+    }
+
+    Response::from_json(&ApiResponse::success(()))
+}
+
+/// Look up one of the user's custom tones
+pub async fn find_tone(
+    db: &D1Database,
+    user_id: &str,
+    tone_id: &str,
+) -> Result<Option<CustomTone>> {
+    let row = db
+        .prepare("SELECT id, name, instructions, examples, created_at, updated_at FROM tones WHERE id = ?1 AND user_id = ?2")
+        .bind(&[tone_id.into(), user_id.into()])?
+        .first::<serde_json::Value>(None)
+        .await?;
+
+    Ok(row.as_ref().map(tone_from_row))
+}
+
+fn tone_from_row(row: &serde_json::Value) -> CustomTone {
+    CustomTone {
+        id: row["id"].as_str().unwrap_or("").to_string(),
+        name: row["name"].as_str().unwrap_or("").to_string(),
+        instructions: row["instructions"].as_str().unwrap_or("").to_string(),
+        examples: row["examples"]
+            .as_str()
+            .and_then(|e| serde_json::from_str(e).ok())
+            .unwrap_or_default(),
+        created_at: row["created_at"].as_f64().unwrap_or(0.0) as i64,
+        updated_at: row["updated_at"].as_f64().unwrap_or(0.0) as i64,
+    }
+}
+
+fn validate_tone(input: &ToneInput) -> std::result::Result<(), String> {
+    let name = input.name.trim();
+    if name.is_empty() {
+        return Err("Tone name cannot be empty".to_string());
+    }
+    if name.chars().count() > MAX_TONE_NAME_LENGTH {
+        return Err(format!(
+            "Tone name must be at most {} characters",
+            MAX_TONE_NAME_LENGTH
+        ));
+    }
+
+    let instructions = input.instructions.trim();
+    if instructions.is_empty() {
+        return Err("Tone instructions cannot be empty".to_string());
+    }
+    if instructions.chars().count() > MAX_TONE_INSTRUCTIONS_LENGTH {
+        return Err(format!(
+            "Tone instructions must be at most {} characters",
+            MAX_TONE_INSTRUCTIONS_LENGTH
+        ));
+    }
+
+    if input.examples.len() > MAX_TONE_EXAMPLES {
+        return Err(format!("At most {} examples allowed", MAX_TONE_EXAMPLES));
+    }
+    for example in &input.examples {
+        if example.input.trim().is_empty() || example.output.trim().is_empty() {
+            return Err("Examples need both input and output".to_string());
+        }
+        if example.input.chars().count() > MAX_TONE_EXAMPLE_LENGTH
+            || example.output.chars().count() > MAX_TONE_EXAMPLE_LENGTH
+        {
+            return Err(format!(
+                "Examples must be at most {} characters",
+                MAX_TONE_EXAMPLE_LENGTH
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ToneExample;
+
+    fn input(name: &str, instructions: &str) -> ToneInput {
+        ToneInput {
+            name: name.to_string(),
+            instructions: instructions.to_string(),
+            examples: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_validate_tone_ok() {
+        assert!(validate_tone(&input("Pirate", "Talk like a pirate")).is_ok());
+    }
+
+    #[test]
+    fn test_validate_tone_empty_fields() {
+        assert!(validate_tone(&input("  ", "Talk like a pirate")).is_err());
+        assert!(validate_tone(&input("Pirate", "")).is_err());
+    }
+
+    #[test]
+    fn test_validate_tone_length_limits() {
+        let long_name = "x".repeat(MAX_TONE_NAME_LENGTH + 1);
+        assert!(validate_tone(&input(&long_name, "ok")).is_err());
+
+        let long_instructions = "x".repeat(MAX_TONE_INSTRUCTIONS_LENGTH + 1);
+        assert!(validate_tone(&input("ok", &long_instructions)).is_err());
+    }
+
+    #[test]
+    fn test_validate_tone_examples() {
+        let example = ToneExample {
+            input: "hi".to_string(),
+            output: "Ahoy".to_string(),
+        };
+
+        let mut tone = input("Pirate", "Talk like a pirate");
+        tone.examples = vec![example.clone(); MAX_TONE_EXAMPLES];
+        assert!(validate_tone(&tone).is_ok());
+
+        tone.examples.push(example);
+        assert!(validate_tone(&tone).is_err());
+
+        tone.examples = vec![ToneExample {
+            input: "hi".to_string(),
+            output: " ".to_string(),
+        }];
+        assert!(validate_tone(&tone).is_err());
+    }
+
+    #[test]
+    fn test_tone_from_row() {
+        let row = serde_json::json!({
+            "id": "t1",
+            "name": "Pirate",
+            "instructions": "Arr",
+            "examples": r#"[{"input":"hi","output":"Ahoy"}]"#,
+            "created_at": 1700000000.0,
+            "updated_at": 1700000100.0
+        });
+
+        let tone = tone_from_row(&row);
+        assert_eq!(tone.id, "t1");
+        assert_eq!(tone.examples.len(), 1);
+        assert_eq!(tone.examples[0].output, "Ahoy");
+        assert_eq!(tone.updated_at, 1700000100);
+    }
+}
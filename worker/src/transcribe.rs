@@ -0,0 +1,720 @@
+use crate::access::{self, AiAccess};
+use crate::error::ApiError;
+use crate::glossary::{Glossary, load_glossary};
+use crate::language::normalize_tag;
+use crate::models::{
+    ApiResponse, AudioQuota, PolishRequest, ServedBy, TranscribeResponse, TranscriptSegment,
+};
+use crate::polish::{Polished, polish_text};
+use crate::provider::{
+    OPENAI_BASE_URL, ProviderError, ProviderResult, Spend, endpoint, env_secret, env_string,
+};
+use crate::usage::{PendingUsage, monthly_audio_quota};
+use worker::*;
+
+/// The OpenAI transcription API's own upload limit
+const MAX_AUDIO_BYTES: usize = 25 * 1024 * 1024;
+
+/// Must support `verbose_json` so segments come back with timestamps
+const DEFAULT_TRANSCRIBE_MODEL: &str = "whisper-1";
+
+/// Whisper only reads the last 224 tokens of its prompt
+const MAX_PROMPT_LENGTH: usize = 800;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioFormat {
+    M4a,
+    Wav,
+    Webm,
+}
+
+impl AudioFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::M4a => "m4a",
+            AudioFormat::Wav => "wav",
+            AudioFormat::Webm => "webm",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            AudioFormat::M4a => "audio/mp4",
+            AudioFormat::Wav => "audio/wav",
+            AudioFormat::Webm => "audio/webm",
+        }
+    }
+
+    /// A typical dictation bitrate, for estimating length when the file
+    /// header doesn't say: 64 kbps AAC, 16 kHz mono PCM, 32 kbps Opus
+    fn bytes_per_second(&self) -> usize {
+        match self {
+            AudioFormat::M4a => 8_000,
+            AudioFormat::Wav => 32_000,
+            AudioFormat::Webm => 4_000,
+        }
+    }
+}
+
+/// Recognise an upload by its declared type, falling back to the file extension
+/// for clients that send `application/octet-stream`
+pub fn audio_format(file_name: &str, content_type: &str) -> Option<AudioFormat> {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let by_type = match mime.as_str() {
+        "audio/mp4" | "audio/m4a" | "audio/x-m4a" => Some(AudioFormat::M4a),
+        "audio/wav" | "audio/x-wav" | "audio/wave" | "audio/vnd.wave" => Some(AudioFormat::Wav),
+        "audio/webm" | "video/webm" => Some(AudioFormat::Webm),
+        _ => None,
+    };
+    by_type.or_else(|| {
+        let (_, extension) = file_name.rsplit_once('.')?;
+        match extension.to_ascii_lowercase().as_str() {
+            "m4a" | "mp4" => Some(AudioFormat::M4a),
+            "wav" => Some(AudioFormat::Wav),
+            "webm" => Some(AudioFormat::Webm),
+            _ => None,
+        }
+    })
+}
+
+/// Where transcription requests go and with which key
+struct Transcriber {
+    base_url: String,
+    api_key: String,
+    model: String,
+    name: &'static str,
+}
+
+impl Transcriber {
+    /// `TRANSCRIBE_BASE_URL`, `TRANSCRIBE_MODEL` and `TRANSCRIBE_API_KEY`, with the
+    /// key falling back to `OPENAI_API_KEY` when the default endpoint is used
+    fn hosted(env: &Env) -> ProviderResult<Self> {
+        let base_url = env_string(env, "TRANSCRIBE_BASE_URL");
+        let api_key = env_secret(env, "TRANSCRIBE_API_KEY")
+            .or_else(|| {
+                base_url
+                    .is_none()
+                    .then(|| env_secret(env, "OPENAI_API_KEY"))
+                    .flatten()
+            })
+            .ok_or_else(|| {
+                ProviderError::Config("Transcription API key not configured on server".to_string())
+            })?;
+        Ok(Self {
+            name: if base_url.is_some() {
+                "openai-compatible"
+            } else {
+                "openai"
+            },
+            base_url: base_url.unwrap_or_else(|| OPENAI_BASE_URL.to_string()),
+            api_key,
+            model: env_string(env, "TRANSCRIBE_MODEL")
+                .unwrap_or_else(|| DEFAULT_TRANSCRIBE_MODEL.to_string()),
+        })
+    }
+
+    fn byok(api_key: String) -> Self {
+        Self {
+            base_url: OPENAI_BASE_URL.to_string(),
+            api_key,
+            model: DEFAULT_TRANSCRIBE_MODEL.to_string(),
+            name: "openai",
+        }
+    }
+
+    async fn transcribe(
+        &self,
+        audio: &Upload,
+        options: &[(&str, String)],
+    ) -> ProviderResult<Transcript> {
+        let boundary = format!("----mumblefish{}", uuid::Uuid::new_v4().simple());
+        let mut fields = vec![
+            ("model", self.model.clone()),
+            ("response_format", "verbose_json".to_string()),
+            ("timestamp_granularities[]", "segment".to_string()),
+        ];
+        fields.extend(options.iter().cloned());
+        let body = multipart_body(&boundary, &fields, audio);
+
+        let headers = Headers::new();
+        headers.set("Authorization", &format!("Bearer {}", self.api_key))?;
+        headers.set(
+            "Content-Type",
+            &format!("multipart/form-data; boundary={}", boundary),
+        )?;
+
+        let mut init = RequestInit::new();
+        init.with_method(Method::Post);
+        init.with_headers(headers);
+        init.with_body(Some(js_sys::Uint8Array::from(&body[..]).into()));
+
+        let req = Request::new_with_init(&endpoint(&self.base_url, "audio/transcriptions"), &init)?;
+        let mut resp = Fetch::Request(req).send().await?;
+
+        if !(200..300).contains(&resp.status_code()) {
+            return Err(ProviderError::Status {
+                provider: self.name,
+                status: resp.status_code(),
+                body: resp.text().await.unwrap_or_default(),
+            });
+        }
+
+        parse_transcription(&resp.json().await?)
+    }
+}
+
+/// An uploaded audio file
+pub struct Upload {
+    pub format: AudioFormat,
+    pub bytes: Vec<u8>,
+}
+
+impl Upload {
+    /// Length in whole seconds, from the file header where it records one and
+    /// from the size at a typical bitrate otherwise
+    pub fn estimated_seconds(&self) -> u64 {
+        let from_header = match self.format {
+            AudioFormat::Wav => wav_seconds(&self.bytes),
+            AudioFormat::M4a => mp4_seconds(&self.bytes),
+            AudioFormat::Webm => None,
+        };
+        let seconds = from_header
+            .unwrap_or_else(|| self.bytes.len() as f64 / self.format.bytes_per_second() as f64);
+        (seconds.ceil() as u64).max(1)
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// Data size over byte rate from a canonical RIFF/WAVE header
+fn wav_seconds(bytes: &[u8]) -> Option<f64> {
+    if bytes.get(0..4)? != b"RIFF" || bytes.get(8..12)? != b"WAVE" {
+        return None;
+    }
+    let byte_rate = u32::from_le_bytes(bytes.get(28..32)?.try_into().ok()?);
+    (byte_rate > 0).then(|| bytes.len().saturating_sub(44) as f64 / byte_rate as f64)
+}
+
+/// The movie header's duration: `moov` > `mvhd`
+fn mp4_seconds(bytes: &[u8]) -> Option<f64> {
+    let moov = mp4_box(bytes, b"moov")?;
+    let mvhd = mp4_box(moov, b"mvhd")?;
+    let (timescale, duration) = match mvhd.first()? {
+        0 => (read_u32(mvhd, 12)?, read_u32(mvhd, 16)? as u64),
+        1 => (read_u32(mvhd, 20)?, read_u64(mvhd, 24)?),
+        _ => return None,
+    };
+    (timescale > 0).then(|| duration as f64 / timescale as f64)
+}
+
+/// The body of the first box of `kind` among the boxes in `bytes`
+fn mp4_box<'a>(bytes: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut at = 0;
+    while at + 8 <= bytes.len() {
+        let size = read_u32(bytes, at)? as usize;
+        let (header, size) = match size {
+            0 => (8, bytes.len() - at),
+            1 => (16, usize::try_from(read_u64(bytes, at + 8)?).ok()?),
+            _ => (8, size),
+        };
+        if size < header {
+            return None;
+        }
+        if &bytes[at + 4..at + 8] == kind {
+            return bytes.get(at + header..at + size);
+        }
+        at = at.checked_add(size)?;
+    }
+    None
+}
+
+/// Encode text fields and the audio file as `multipart/form-data`
+pub fn multipart_body(boundary: &str, fields: &[(&str, String)], audio: &Upload) -> Vec<u8> {
+    let mut body = Vec::with_capacity(audio.bytes.len() + 1024);
+    for (name, value) in fields {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                boundary, name, value
+            )
+            .as_bytes(),
+        );
+    }
+    body.extend_from_slice(
+        format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"audio.{}\"\r\nContent-Type: {}\r\n\r\n",
+            boundary,
+            audio.format.extension(),
+            audio.format.content_type()
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(&audio.bytes);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    body
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Transcript {
+    pub text: String,
+    pub language: Option<String>,
+    pub duration: Option<f64>,
+    pub segments: Vec<TranscriptSegment>,
+}
+
+impl Transcript {
+    /// Whole seconds to meter, rounding any partial second up. Servers that
+    /// don't report a duration are billed the upload's estimated length.
+    pub fn billed_seconds(&self, audio: &Upload) -> u64 {
+        match self.duration {
+            Some(duration) => duration.max(0.0).ceil() as u64,
+            None => audio.estimated_seconds(),
+        }
+    }
+}
+
+/// Read a `verbose_json` transcription. Servers that omit segments or the
+/// duration still yield the text.
+pub fn parse_transcription(json: &serde_json::Value) -> ProviderResult<Transcript> {
+    let text = json["text"]
+        .as_str()
+        .ok_or_else(|| ProviderError::InvalidResponse("No transcript text".to_string()))?
+        .trim()
+        .to_string();
+
+    let segments: Vec<TranscriptSegment> = json["segments"]
+        .as_array()
+        .map(|segments| {
+            segments
+                .iter()
+                .filter_map(|segment| {
+                    Some(TranscriptSegment {
+                        start: segment["start"].as_f64()?,
+                        end: segment["end"].as_f64()?,
+                        text: segment["text"].as_str()?.trim().to_string(),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let duration = json["duration"]
+        .as_f64()
+        .or_else(|| segments.last().map(|s| s.end));
+
+    Ok(Transcript {
+        text,
+        language: json["language"].as_str().map(str::to_string),
+        duration,
+        segments,
+    })
+}
+
+/// Read the `polish` form field: the options of a polish request, minus the
+/// text, which comes from the transcript. Results are never streamed.
+pub fn parse_polish_options(value: &str) -> std::result::Result<PolishRequest, ApiError> {
+    let mut options: serde_json::Value = serde_json::from_str(value)
+        .map_err(|_| ApiError::validation("polish must be a JSON object"))?;
+    let object = options
+        .as_object_mut()
+        .ok_or_else(|| ApiError::validation("polish must be a JSON object"))?;
+    object.insert("text".to_string(), serde_json::Value::String(String::new()));
+    object.remove("stream");
+
+    serde_json::from_value(options)
+        .map_err(|e| ApiError::validation(format!("Invalid polish options: {}", e)))
+}
+
+/// Glossary spellings as a Whisper prompt, so names come out right the first time
+fn glossary_prompt(spellings: &[String]) -> Option<String> {
+    let mut prompt = String::new();
+    for spelling in spellings {
+        if prompt.len() + spelling.len() + 2 > MAX_PROMPT_LENGTH {
+            break;
+        }
+        if !prompt.is_empty() {
+            prompt.push_str(", ");
+        }
+        prompt.push_str(spelling);
+    }
+    (!prompt.is_empty()).then_some(prompt)
+}
+
+/// Transcribe an uploaded recording, optionally polishing the transcript in the
+/// same request. Hosted calls are metered by audio seconds.
+pub async fn transcribe(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let access = match access::authorize_transcription(&req, &ctx).await {
+        Ok(access) => access,
+        Err(e) => return e.into_response(),
+    };
+
+    if let Some(length) = req.headers().get("Content-Length")?
+        && length
+            .parse::<usize>()
+            .is_ok_and(|l| l > MAX_AUDIO_BYTES + 64 * 1024)
+    {
+        return too_large().into_response();
+    }
+
+    let form = match req.form_data().await {
+        Ok(form) => form,
+        Err(_) => {
+            return ApiError::validation("Expected a multipart/form-data body").into_response();
+        }
+    };
+
+    let file = match form.get("file") {
+        Some(FormEntry::File(file)) => file,
+        _ => return ApiError::validation("Missing audio file").into_response(),
+    };
+    let Some(format) = audio_format(&file.name(), &file.type_()) else {
+        return ApiError::validation("Unsupported audio format. Use m4a, wav or webm.")
+            .into_response();
+    };
+    if file.size() == 0 {
+        return ApiError::validation("Audio file is empty").into_response();
+    }
+    if file.size() > MAX_AUDIO_BYTES {
+        return too_large().into_response();
+    }
+
+    let language = match form.get_field("language").filter(|l| !l.trim().is_empty()) {
+        Some(tag) => match normalize_tag(&tag) {
+            // Whisper takes ISO-639-1 codes only
+            Some(tag) => Some(tag.split('-').next().unwrap_or_default().to_string()),
+            None => {
+                return ApiError::validation(format!("Invalid language tag \"{}\"", tag))
+                    .into_response();
+            }
+        },
+        None => None,
+    };
+
+    // Checked before transcribing so bad options don't cost an upload
+    let polish = match form.get_field("polish").filter(|p| !p.trim().is_empty()) {
+        Some(options) => match parse_polish_options(&options) {
+            Ok(options) => Some(options),
+            Err(e) => return e.into_response(),
+        },
+        None => None,
+    };
+
+    let audio = Upload {
+        format,
+        bytes: file.bytes().await?,
+    };
+
+    match transcribe_audio(&req, &ctx, access, audio, language, polish).await {
+        Ok(response) => Response::from_json(&ApiResponse::success(response)),
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn transcribe_audio(
+    req: &Request,
+    ctx: &RouteContext<()>,
+    access: AiAccess,
+    audio: Upload,
+    language: Option<String>,
+    polish: Option<PolishRequest>,
+) -> std::result::Result<TranscribeResponse, ApiError> {
+    let db = ctx.env.d1("DB")?;
+
+    let (transcriber, audio_quota) = match (access.is_byok, &access.user_id) {
+        (false, Some(user_id)) => {
+            let quota = monthly_audio_quota(&db, user_id).await?;
+            check_audio_quota(
+                &quota,
+                audio.estimated_seconds(),
+                chrono::Utc::now().timestamp(),
+            )?;
+            (Transcriber::hosted(&ctx.env)?, Some(quota))
+        }
+        _ => {
+            let key = req.headers().get("X-OpenAI-Key")?.unwrap_or_default();
+            (Transcriber::byok(key), None)
+        }
+    };
+
+    let mut options = Vec::new();
+    if let Some(language) = language {
+        options.push(("language", language));
+    }
+    if let Some(user_id) = &access.user_id {
+        let glossary = Glossary::new(&load_glossary(&db, user_id).await?);
+        if let Some(prompt) = glossary_prompt(glossary.spellings()) {
+            options.push(("prompt", prompt));
+        }
+    }
+
+    let pending_usage = PendingUsage::start(ctx.env.d1("DB")?, &access, "transcribe");
+    let transcript = match transcriber.transcribe(&audio, &options).await {
+        Ok(transcript) => transcript,
+        Err(e) => {
+            console_error!("Transcription error: {}", e);
+            return Err(e.into());
+        }
+    };
+
+    let seconds = transcript.billed_seconds(&audio);
+    pending_usage
+        .with_audio_seconds(seconds, &transcriber.model)
+        .record(Spend::default())
+        .await;
+
+    let mut response = TranscribeResponse {
+        text: transcript.text,
+        language: transcript.language,
+        duration: transcript.duration.unwrap_or(seconds as f64),
+        segments: transcript.segments,
+        served_by: ServedBy {
+            provider: transcriber.name,
+            model: transcriber.model,
+            cost_weight: 1.0,
+        },
+        audio_quota: audio_quota.map(|mut quota| {
+            quota.consume(seconds);
+            quota
+        }),
+        polish: None,
+        polish_error: None,
+    };
+
+    if let Some(mut body) = polish
+        && !response.text.is_empty()
+    {
+        body.text = response.text.clone();
+        match polish_text(ctx, access, body, false).await {
+            Ok(Polished::Done(polished)) => response.polish = Some(*polished),
+            Ok(Polished::Streaming(_)) => unreachable!("polish_text streams only when asked to"),
+            Err(e) => response.polish_error = Some(e.body().message),
+        }
+    }
+
+    Ok(response)
+}
+
+/// Refuse a hosted recording the allowance can't cover, judged by its
+/// estimated length
+fn check_audio_quota(
+    quota: &AudioQuota,
+    seconds: u64,
+    now: i64,
+) -> std::result::Result<(), ApiError> {
+    if quota.is_exhausted() {
+        return Err(ApiError::QuotaExceeded {
+            message: format!(
+                "Monthly transcription allowance of {} minutes on the {} plan is used up. Use your own API key or upgrade.",
+                quota.limit / 60,
+                quota.plan.id()
+            ),
+            retry_after: Some((quota.resets_at - now).max(0) as u32),
+        });
+    }
+    if seconds > quota.remaining {
+        return Err(ApiError::QuotaExceeded {
+            message: format!(
+                "This recording is about {} minutes long, but only {} of the monthly {} minutes remain. Shorten it, use your own API key, or upgrade.",
+                seconds.div_ceil(60),
+                quota.remaining / 60,
+                quota.limit / 60
+            ),
+            retry_after: None,
+        });
+    }
+    Ok(())
+}
+
+fn too_large() -> ApiError {
+    ApiError::Validation {
+        message: format!(
+            "Audio file too large. Maximum is {} MB.",
+            MAX_AUDIO_BYTES / (1024 * 1024)
+        ),
+        details: Some(serde_json::json!({ "max_bytes": MAX_AUDIO_BYTES })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Plan;
+
+    #[test]
+    fn test_audio_format() {
+        assert_eq!(
+            audio_format("memo.m4a", "audio/x-m4a"),
+            Some(AudioFormat::M4a)
+        );
+        assert_eq!(
+            audio_format("rec", "audio/webm;codecs=opus"),
+            Some(AudioFormat::Webm)
+        );
+        assert_eq!(
+            audio_format("Memo.WAV", "application/octet-stream"),
+            Some(AudioFormat::Wav)
+        );
+        assert_eq!(audio_format("song.mp3", "audio/mpeg"), None);
+        assert_eq!(audio_format("notes", ""), None);
+    }
+
+    #[test]
+    fn test_multipart_body() {
+        let audio = Upload {
+            format: AudioFormat::Wav,
+            bytes: b"RIFF".to_vec(),
+        };
+        let body = multipart_body("XYZ", &[("model", "whisper-1".to_string())], &audio);
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "--XYZ\r\nContent-Disposition: form-data; name=\"model\"\r\n\r\nwhisper-1\r\n\
+             --XYZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"audio.wav\"\r\nContent-Type: audio/wav\r\n\r\n\
+             RIFF\r\n--XYZ--\r\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_transcription() {
+        let json = serde_json::json!({
+            "text": " Hello there. Second bit. ",
+            "language": "english",
+            "duration": 4.2,
+            "segments": [
+                { "id": 0, "start": 0.0, "end": 1.5, "text": " Hello there." },
+                { "id": 1, "start": 1.5, "end": 4.2, "text": " Second bit." },
+                { "id": 2, "text": "no timing" }
+            ]
+        });
+        let transcript = parse_transcription(&json).unwrap();
+        assert_eq!(transcript.text, "Hello there. Second bit.");
+        assert_eq!(transcript.language.as_deref(), Some("english"));
+        assert_eq!(transcript.segments.len(), 2);
+        assert_eq!(transcript.segments[1].start, 1.5);
+        assert_eq!(transcript.segments[1].text, "Second bit.");
+        let audio = Upload {
+            format: AudioFormat::Webm,
+            bytes: vec![0; 400_000],
+        };
+        assert_eq!(transcript.billed_seconds(&audio), 5);
+    }
+
+    #[test]
+    fn test_parse_transcription_minimal() {
+        let transcript = parse_transcription(&serde_json::json!({ "text": "hi" })).unwrap();
+        assert_eq!(transcript.duration, None);
+        assert!(transcript.segments.is_empty());
+        assert!(parse_transcription(&serde_json::json!({ "error": "nope" })).is_err());
+
+        // Without a duration the upload's estimated length is billed, not zero
+        let audio = Upload {
+            format: AudioFormat::Webm,
+            bytes: vec![0; 400_000],
+        };
+        assert_eq!(transcript.billed_seconds(&audio), 100);
+    }
+
+    fn wav(byte_rate: u32, data_len: usize) -> Vec<u8> {
+        let mut bytes = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        bytes.resize(28, 0);
+        bytes.extend_from_slice(&byte_rate.to_le_bytes());
+        bytes.resize(44 + data_len, 0);
+        bytes
+    }
+
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(kind);
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    #[test]
+    fn test_estimated_seconds() {
+        // 16 kHz stereo PCM, 2.5 seconds of it
+        let audio = Upload {
+            format: AudioFormat::Wav,
+            bytes: wav(64_000, 160_000),
+        };
+        assert_eq!(audio.estimated_seconds(), 3);
+
+        // mvhd version 0: timescale 1000, duration 61.5 s, after an ftyp box
+        let mut mvhd = vec![0; 12];
+        mvhd.extend_from_slice(&1000u32.to_be_bytes());
+        mvhd.extend_from_slice(&61_500u32.to_be_bytes());
+        let mut bytes = mp4_box(b"ftyp", b"M4A ");
+        bytes.extend(mp4_box(b"mdat", &[0; 100]));
+        bytes.extend(mp4_box(b"moov", &mp4_box(b"mvhd", &mvhd)));
+        let audio = Upload {
+            format: AudioFormat::M4a,
+            bytes,
+        };
+        assert_eq!(audio.estimated_seconds(), 62);
+
+        // No readable header: the size at a typical bitrate
+        let audio = Upload {
+            format: AudioFormat::M4a,
+            bytes: vec![0; 80_000],
+        };
+        assert_eq!(audio.estimated_seconds(), 10);
+        let audio = Upload {
+            format: AudioFormat::Webm,
+            bytes: vec![0; 10],
+        };
+        assert_eq!(audio.estimated_seconds(), 1);
+    }
+
+    #[test]
+    fn test_check_audio_quota() {
+        let limit = Plan::Free.monthly_audio_allowance();
+        let quota = AudioQuota::new(Plan::Free, limit - 120, 1_000);
+        assert!(check_audio_quota(&quota, 120, 0).is_ok());
+        match check_audio_quota(&quota, 121, 0) {
+            Err(ApiError::QuotaExceeded { retry_after, .. }) => assert_eq!(retry_after, None),
+            other => panic!("expected QuotaExceeded, got {:?}", other),
+        }
+
+        let spent = AudioQuota::new(Plan::Free, limit, 1_000);
+        match check_audio_quota(&spent, 1, 400) {
+            Err(ApiError::QuotaExceeded { retry_after, .. }) => {
+                assert_eq!(retry_after, Some(600))
+            }
+            other => panic!("expected QuotaExceeded, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_polish_options() {
+        let options =
+            parse_polish_options(r#"{"tone": "casual", "stream": true, "text": "ignored"}"#)
+                .unwrap();
+        assert_eq!(options.tone.id(), "casual");
+        assert!(options.text.is_empty());
+        assert!(!options.stream);
+
+        assert!(parse_polish_options("[]").is_err());
+        assert!(parse_polish_options("casual").is_err());
+        assert!(parse_polish_options("{}").is_err());
+    }
+
+    #[test]
+    fn test_glossary_prompt() {
+        assert_eq!(glossary_prompt(&[]), None);
+        assert_eq!(
+            glossary_prompt(&["Kubernetes".to_string(), "Mumble Fish".to_string()]).as_deref(),
+            Some("Kubernetes, Mumble Fish")
+        );
+        let long: Vec<String> = (0..200).map(|i| format!("term{}", i)).collect();
+        assert!(glossary_prompt(&long).unwrap().len() <= MAX_PROMPT_LENGTH);
+    }
+}
@@ -0,0 +1,323 @@
+use crate::access::AiAccess;
+use crate::auth::{extract_and_verify_token, get_query_param};
+use crate::error::ApiError;
+use crate::models::{ApiResponse, AudioQuota, Plan, Quota, UsagePeriod, UsageReport};
+use crate::provider::{LlmProvider, Spend};
+use chrono::{DateTime, Datelike, Duration, TimeZone, Utc};
+use worker::*;
+
+const DEFAULT_USAGE_DAYS: u32 = 30;
+const MAX_USAGE_DAYS: u32 = 90;
+const USAGE_MONTHS: u32 = 12;
+
+const DAILY_USAGE_QUERY: &str = "SELECT strftime('%Y-%m-%d', created_at, 'unixepoch') AS period, COUNT(*) AS requests, SUM(byok) AS byok_requests, SUM(prompt_tokens) AS prompt_tokens, SUM(completion_tokens) AS completion_tokens, SUM(total_tokens) AS total_tokens FROM usage_events WHERE user_id = ?1 AND created_at >= ?2 GROUP BY period ORDER BY period";
+const MONTHLY_USAGE_QUERY: &str = "SELECT strftime('%Y-%m', created_at, 'unixepoch') AS period, COUNT(*) AS requests, SUM(byok) AS byok_requests, SUM(prompt_tokens) AS prompt_tokens, SUM(completion_tokens) AS completion_tokens, SUM(total_tokens) AS total_tokens FROM usage_events WHERE user_id = ?1 AND created_at >= ?2 GROUP BY period ORDER BY period";
+
+/// One metered provider call. The BYOK flag is recorded, never the key itself.
+pub struct UsageEvent {
+    /// `None` for anonymous BYOK calls
+    pub user_id: Option<String>,
+    /// The model that answered (first, when several did)
+    pub model: String,
+    pub tone: String,
+    pub spend: Spend,
+    pub latency_ms: i64,
+    pub byok: bool,
+    /// Quota tokens charged per token of the selected model, for usage without a backend
+    pub cost_weight: f64,
+    /// Seconds of audio transcribed, 0 for text calls
+    pub audio_seconds: u64,
+}
+
+async fn record_usage(db: &D1Database, event: &UsageEvent) -> Result<()> {
+    let usage = event.spend.usage().unwrap_or_default();
+    let user_id = match &event.user_id {
+        Some(user_id) => user_id.as_str().into(),
+        None => wasm_bindgen::JsValue::NULL,
+    };
+
+    db.prepare("INSERT INTO usage_events (id, user_id, model, tone, prompt_tokens, completion_tokens, total_tokens, billed_tokens, audio_seconds, latency_ms, byok, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)")
+        .bind(&[
+            uuid::Uuid::new_v4().to_string().into(),
+            user_id,
+            event.model.as_str().into(),
+            event.tone.as_str().into(),
+            (usage.prompt_tokens as f64).into(),
+            (usage.completion_tokens as f64).into(),
+            (usage.total_tokens as f64).into(),
+            (event.spend.billed(event.cost_weight) as f64).into(),
+            (event.audio_seconds as f64).into(),
+            (event.latency_ms as f64).into(),
+            (if event.byok { 1.0 } else { 0.0 }).into(),
+            (Utc::now().timestamp() as f64).into(),
+        ])?
+        .run()
+        .await?;
+
+    Ok(())
+}
+
+/// A provider call to meter once its token usage is known
+pub struct PendingUsage {
+    db: D1Database,
+    event: UsageEvent,
+    /// Unix millis when the provider was first called
+    started_at: i64,
+}
+
+impl PendingUsage {
+    /// Start timing a call. `tone` is the tone id, or the operation for calls
+    /// that don't use one (e.g. `summarize`).
+    pub fn start(db: D1Database, access: &AiAccess, tone: &str) -> Self {
+        Self {
+            db,
+            event: UsageEvent {
+                user_id: access.user_id.clone(),
+                model: access.provider.model().to_string(),
+                tone: tone.to_string(),
+                spend: Spend::default(),
+                latency_ms: 0,
+                byok: access.is_byok,
+                cost_weight: access.cost_weight,
+                audio_seconds: 0,
+            },
+            started_at: Utc::now().timestamp_millis(),
+        }
+    }
+
+    /// Meter a transcription by the length of its audio, against its own model
+    pub fn with_audio_seconds(mut self, seconds: u64, model: &str) -> Self {
+        self.event.audio_seconds = seconds;
+        self.event.model = model.to_string();
+        self
+    }
+
+    /// Record the call against the backends that served it. Failures are logged
+    /// rather than failing the request.
+    pub async fn record(mut self, spend: Spend) {
+        if let Some(served_by) = spend.served_by() {
+            self.event.model = served_by.model.clone();
+        }
+        self.event.spend = spend;
+        self.event.latency_ms = Utc::now().timestamp_millis() - self.started_at;
+
+        if let Err(e) = record_usage(&self.db, &self.event).await {
+            console_error!("Failed to record usage: {:?}", e);
+        }
+    }
+
+    /// Record a failed call, if it got far enough to use any tokens
+    pub async fn record_spent(self, spend: Spend) {
+        if spend.usage().is_some() {
+            self.record(spend).await;
+        }
+    }
+}
+
+/// Daily totals for the last `days` days (default 30) and monthly totals for
+/// the last 12 months, oldest first. Days and months without usage are omitted.
+pub async fn get_usage(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
+        Ok(id) => id,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let url = req.url()?;
+    let days = match get_query_param(&url, "days").map(|d| d.parse::<u32>()) {
+        Some(Ok(days)) if (1..=MAX_USAGE_DAYS).contains(&days) => days,
+        Some(_) => {
+            return ApiError::validation(format!("days must be between 1 and {}", MAX_USAGE_DAYS))
+                .into_response();
+        }
+        None => DEFAULT_USAGE_DAYS,
+    };
+
+    let db = ctx.env.d1("DB")?;
+    let now = Utc::now();
+
+    let daily = usage_periods(&db, DAILY_USAGE_QUERY, &user_id, daily_since(now, days)).await?;
+    let monthly = usage_periods(
+        &db,
+        MONTHLY_USAGE_QUERY,
+        &user_id,
+        monthly_since(now, USAGE_MONTHS),
+    )
+    .await?;
+
+    Response::from_json(&ApiResponse::success(UsageReport { daily, monthly }))
+}
+
+async fn usage_periods(
+    db: &D1Database,
+    query: &str,
+    user_id: &str,
+    since: i64,
+) -> Result<Vec<UsagePeriod>> {
+    let rows = db
+        .prepare(query)
+        .bind(&[user_id.into(), (since as f64).into()])?
+        .all()
+        .await?
+        .results::<serde_json::Value>()?;
+
+    Ok(rows.iter().map(period_from_row).collect())
+}
+
+fn period_from_row(row: &serde_json::Value) -> UsagePeriod {
+    let count = |field: &str| row[field].as_f64().unwrap_or(0.0) as u64;
+    UsagePeriod {
+        period: row["period"].as_str().unwrap_or("").to_string(),
+        requests: count("requests"),
+        byok_requests: count("byok_requests"),
+        prompt_tokens: count("prompt_tokens"),
+        completion_tokens: count("completion_tokens"),
+        total_tokens: count("total_tokens"),
+    }
+}
+
+/// Start of the UTC day `days - 1` days before `now`, so today counts as one day
+fn daily_since(now: DateTime<Utc>, days: u32) -> i64 {
+    let today = now.date_naive().and_hms_opt(0, 0, 0).unwrap_or_default();
+    (today - Duration::days(days.saturating_sub(1) as i64))
+        .and_utc()
+        .timestamp()
+}
+
+/// Start of the UTC month `months - 1` months before `now`'s month
+fn monthly_since(now: DateTime<Utc>, months: u32) -> i64 {
+    month_start(now, -(months.saturating_sub(1) as i32))
+}
+
+/// Start of the UTC month `offset` months from `now`'s month
+pub fn month_start(now: DateTime<Utc>, offset: i32) -> i64 {
+    let index = now.year() * 12 + now.month0() as i32 + offset;
+    Utc.with_ymd_and_hms(
+        index.div_euclid(12),
+        index.rem_euclid(12) as u32 + 1,
+        1,
+        0,
+        0,
+        0,
+    )
+    .single()
+    .map_or(0, |start| start.timestamp())
+}
+
+/// The user's plan and the hosted tokens they've used this month
+pub async fn monthly_quota(db: &D1Database, user_id: &str) -> Result<Quota> {
+    let now = Utc::now();
+
+    let plan = user_plan(db, user_id).await?;
+
+    let used = db
+        .prepare("SELECT SUM(billed_tokens) AS used FROM usage_events WHERE user_id = ?1 AND byok = 0 AND created_at >= ?2")
+        .bind(&[user_id.into(), (month_start(now, 0) as f64).into()])?
+        .first::<serde_json::Value>(None)
+        .await?
+        .and_then(|row| row["used"].as_f64())
+        .unwrap_or(0.0) as u64;
+
+    Ok(Quota::new(plan, used, month_start(now, 1)))
+}
+
+/// The user's hosted transcription allowance for the current UTC month
+pub async fn monthly_audio_quota(db: &D1Database, user_id: &str) -> Result<AudioQuota> {
+    let now = Utc::now();
+    let plan = user_plan(db, user_id).await?;
+
+    let used = db
+        .prepare("SELECT SUM(audio_seconds) AS used FROM usage_events WHERE user_id = ?1 AND byok = 0 AND created_at >= ?2")
+        .bind(&[user_id.into(), (month_start(now, 0) as f64).into()])?
+        .first::<serde_json::Value>(None)
+        .await?
+        .and_then(|row| row["used"].as_f64())
+        .unwrap_or(0.0) as u64;
+
+    Ok(AudioQuota::new(plan, used, month_start(now, 1)))
+}
+
+async fn user_plan(db: &D1Database, user_id: &str) -> Result<Plan> {
+    Ok(db
+        .prepare("SELECT plan FROM users WHERE id = ?1")
+        .bind(&[user_id.into()])?
+        .first::<String>(Some("plan"))
+        .await?
+        .map_or(Plan::Free, |plan| Plan::parse(&plan)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_daily_since_includes_today() {
+        let now = at("2026-03-15T18:30:00Z");
+        assert_eq!(daily_since(now, 1), at("2026-03-15T00:00:00Z").timestamp());
+        assert_eq!(daily_since(now, 30), at("2026-02-14T00:00:00Z").timestamp());
+    }
+
+    #[test]
+    fn test_monthly_since_crosses_year() {
+        let now = at("2026-03-15T18:30:00Z");
+        assert_eq!(
+            monthly_since(now, 1),
+            at("2026-03-01T00:00:00Z").timestamp()
+        );
+        assert_eq!(
+            monthly_since(now, 12),
+            at("2025-04-01T00:00:00Z").timestamp()
+        );
+        assert_eq!(
+            monthly_since(now, 3),
+            at("2026-01-01T00:00:00Z").timestamp()
+        );
+    }
+
+    #[test]
+    fn test_month_start_next_month() {
+        assert_eq!(
+            month_start(at("2026-12-31T23:59:59Z"), 1),
+            at("2027-01-01T00:00:00Z").timestamp()
+        );
+    }
+
+    #[test]
+    fn test_period_from_row() {
+        let row = serde_json::json!({
+            "period": "2026-03-15",
+            "requests": 4.0,
+            "byok_requests": 1.0,
+            "prompt_tokens": 400.0,
+            "completion_tokens": 150.0,
+            "total_tokens": 550.0
+        });
+
+        assert_eq!(
+            period_from_row(&row),
+            UsagePeriod {
+                period: "2026-03-15".to_string(),
+                requests: 4,
+                byok_requests: 1,
+                prompt_tokens: 400,
+                completion_tokens: 150,
+                total_tokens: 550,
+            }
+        );
+    }
+
+    #[test]
+    fn test_period_from_row_null_sums() {
+        let row =
+            serde_json::json!({ "period": "2026-03", "requests": 1.0, "byok_requests": null });
+        let period = period_from_row(&row);
+        assert_eq!(period.requests, 1);
+        assert_eq!(period.byok_requests, 0);
+        assert_eq!(period.total_tokens, 0);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::auth::extract_and_verify_token;
+use crate::error::ApiError;
+use crate::jwt::{self, Keys};
+use crate::mailer::{ConfiguredMailer, Email, Mailer, app_url, cooldown_wait};
+use crate::models::{ApiResponse, VerifyEmailRequest};
+use crate::provider::env_string;
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+/// Audience of email verification tokens, so they can't pass as access tokens
+const VERIFY_AUDIENCE: &str = "mumble.fish/verify-email";
+const VERIFICATION_TTL_SECS: i64 = 86400;
+/// Minimum gap between verification emails to one account
+const RESEND_COOLDOWN_SECS: i64 = 60;
+
+/// What a signed-in account with an unverified email may do
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnverifiedPolicy {
+    /// Everything a verified account can
+    Allow,
+    /// Everything except hosted AI calls; BYOK still works
+    NoHosted,
+}
+
+impl UnverifiedPolicy {
+    /// `UNVERIFIED_ACCOUNTS`: `allow`, or `no-hosted` (the default)
+    pub fn from_env(env: &Env) -> Self {
+        env_string(env, "UNVERIFIED_ACCOUNTS")
+            .and_then(|value| Self::parse(&value))
+            .unwrap_or(Self::NoHosted)
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "allow" => Some(Self::Allow),
+            "no-hosted" | "no_hosted" => Some(Self::NoHosted),
+            _ => None,
+        }
+    }
+}
+
+/// Claims of a verification link. `iat` must match the account's
+/// `verification_sent_at`, so only the most recent link works, and only once.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct VerificationClaims {
+    sub: String,
+    email: String,
+    iat: i64,
+    exp: i64,
+    #[serde(default, skip_deserializing)]
+    iss: String,
+    #[serde(default, skip_deserializing)]
+    aud: String,
+}
+
+fn verification_token(
+    keys: &Keys,
+    user_id: &str,
+    email: &str,
+    now: i64,
+) -> serde_json::Result<String> {
+    keys.sign(&VerificationClaims {
+        sub: user_id.to_string(),
+        email: email.to_string(),
+        iat: now,
+        exp: now + VERIFICATION_TTL_SECS,
+        iss: jwt::ISSUER.to_string(),
+        aud: VERIFY_AUDIENCE.to_string(),
+    })
+}
+
+fn verification_email(to: &str, link: &str) -> Email {
+    Email {
+        to: to.to_string(),
+        subject: "Confirm your email for mumble.fish".to_string(),
+        text: format!(
+            "Confirm this address to finish setting up your mumble.fish account:\n\n{}\n\nThe link expires in 24 hours. If you didn't sign up, you can ignore this email.",
+            link
+        ),
+    }
+}
+
+/// Sign a verification link for `email` and send it
+async fn send_verification(
+    mailer: &impl Mailer,
+    keys: &Keys,
+    app_url: &str,
+    user_id: &str,
+    email: &str,
+    now: i64,
+) -> Result<()> {
+    let token = verification_token(keys, user_id, email, now)?;
+    let link = format!("{}/verify-email?token={}", app_url, token);
+    mailer.send(&verification_email(email, &link)).await
+}
+
+/// Record that a verification email is going out, then send it
+pub async fn start(db: &D1Database, env: &Env, user_id: &str, email: &str) -> Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    db.prepare("UPDATE users SET verification_sent_at = ?1 WHERE id = ?2")
+        .bind(&[(now as f64).into(), user_id.into()])?
+        .run()
+        .await?;
+
+    let keys = Keys::from_env(env).map_err(Error::RustError)?;
+    send_verification(
+        &ConfiguredMailer::from_env(env)?,
+        &keys,
+        &app_url(env),
+        user_id,
+        email,
+        now,
+    )
+    .await
+}
+
+pub async fn is_verified(db: &D1Database, user_id: &str) -> Result<bool> {
+    Ok(db
+        .prepare("SELECT email_verified_at FROM users WHERE id = ?1")
+        .bind(&[user_id.into()])?
+        .first::<serde_json::Value>(None)
+        .await?
+        .is_some_and(|row| !row["email_verified_at"].is_null()))
+}
+
+/// Whether the account may make hosted AI calls under the configured policy
+pub async fn may_use_hosted(db: &D1Database, env: &Env, user_id: &str) -> Result<bool> {
+    match UnverifiedPolicy::from_env(env) {
+        UnverifiedPolicy::Allow => Ok(true),
+        UnverifiedPolicy::NoHosted => is_verified(db, user_id).await,
+    }
+}
+
+/// Confirm an email address from the token in a verification link
+pub async fn verify_email(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let body: VerifyEmailRequest = match req.json().await {
+        Ok(b) => b,
+        Err(_) => {
+            return ApiError::validation("Invalid request body").into_response();
+        }
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    let keys = Keys::from_env(&ctx.env).map_err(Error::RustError)?;
+    let claims: VerificationClaims = match keys.verify(body.token.trim(), VERIFY_AUDIENCE, now) {
+        Ok(claims) => claims,
+        Err(_) => {
+            return ApiError::validation("Invalid or expired verification link").into_response();
+        }
+    };
+
+    // Conditional on the link being the latest one sent to the current address
+    let verified = ctx
+        .env
+        .d1("DB")?
+        .prepare("UPDATE users SET email_verified_at = ?1 WHERE id = ?2 AND email = ?3 AND verification_sent_at = ?4 AND email_verified_at IS NULL")
+        .bind(&[
+            (now as f64).into(),
+            claims.sub.into(),
+            claims.email.into(),
+            (claims.iat as f64).into(),
+        ])?
+        .run()
+        .await?
+        .meta()?
+        .and_then(|m| m.changes)
+        .is_some_and(|changes| changes > 0);
+
+    if !verified {
+        return ApiError::validation("Verification link already used or replaced by a newer one")
+            .into_response();
+    }
+
+    Response::from_json(&ApiResponse::success(
+        serde_json::json!({ "email_verified": true }),
+    ))
+}
+
+/// Send a fresh verification link to the signed-in user
+pub async fn resend(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let user_id = match extract_and_verify_token(&req, &ctx).await {
+        Ok(id) => id,
+        Err(e) => {
+            return ApiError::Unauthorized(e).into_response();
+        }
+    };
+
+    let db = ctx.env.d1("DB")?;
+    let Some(user) = db
+        .prepare("SELECT email, email_verified_at, verification_sent_at FROM users WHERE id = ?1")
+        .bind(&[user_id.clone().into()])?
+        .first::<serde_json::Value>(None)
+        .await?
+    else {
+        return ApiError::NotFound("User not found".to_string()).into_response();
+    };
+
+    if !user["email_verified_at"].is_null() {
+        return ApiError::Conflict("Email already verified".to_string()).into_response();
+    }
+
+    let sent_at = user["verification_sent_at"].as_f64().map(|t| t as i64);
+    if let Some(retry_after) = cooldown_wait(
+        sent_at,
+        RESEND_COOLDOWN_SECS,
+        chrono::Utc::now().timestamp(),
+    ) {
+        return ApiError::RateLimited { retry_after }.into_response();
+    }
+
+    let email = user["email"].as_str().unwrap_or_default();
+    start(&db, &ctx.env, &user_id, email).await?;
+
+    Response::from_json(&ApiResponse::success(serde_json::json!({ "sent": true })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mailer::MemoryMailer;
+    use crate::testing::block_on;
+
+    fn keys() -> Keys {
+        Keys::from_config(Some("k1:secret"), None).unwrap()
+    }
+
+    fn link_token(email: &Email) -> String {
+        let (_, rest) = email.text.split_once("?token=").unwrap();
+        rest.split_whitespace().next().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_send_verification() {
+        let mailer = MemoryMailer::default();
+        block_on(send_verification(
+            &mailer,
+            &keys(),
+            "https://app.test",
+            "user-1",
+            "ada@example.com",
+            1_000,
+        ))
+        .unwrap();
+
+        let sent = mailer.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "ada@example.com");
+        assert!(
+            sent[0]
+                .text
+                .contains("https://app.test/verify-email?token=")
+        );
+
+        let claims: VerificationClaims = keys()
+            .verify(&link_token(&sent[0]), VERIFY_AUDIENCE, 1_001)
+            .unwrap();
+        assert_eq!(claims.sub, "user-1");
+        assert_eq!(claims.email, "ada@example.com");
+        assert_eq!(claims.iat, 1_000);
+    }
+
+    #[test]
+    fn test_verification_token_is_not_an_access_token() {
+        let token = verification_token(&keys(), "user-1", "ada@example.com", 1_000).unwrap();
+        assert_eq!(
+            keys()
+                .verify::<crate::models::TokenClaims>(&token, jwt::API_AUDIENCE, 1_001)
+                .unwrap_err(),
+            "Invalid token audience"
+        );
+        assert!(
+            keys()
+                .verify::<VerificationClaims>(
+                    &token,
+                    VERIFY_AUDIENCE,
+                    1_000 + VERIFICATION_TTL_SECS
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_policy_parse() {
+        assert_eq!(
+            UnverifiedPolicy::parse("allow"),
+            Some(UnverifiedPolicy::Allow)
+        );
+        assert_eq!(
+            UnverifiedPolicy::parse(" No-Hosted "),
+            Some(UnverifiedPolicy::NoHosted)
+        );
+        assert_eq!(UnverifiedPolicy::parse("maybe"), None);
+    }
+}